@@ -0,0 +1,104 @@
+use crate::{escape_dump_field, Formatter, QueryResult};
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+// The plain format for a terminal: successes in green, errors in red, (nil) dimmed, and the key column of
+// RANGE and DUMP padded so the values line up. Everything else is shown the same as without color
+pub struct Colored;
+
+impl Formatter for Colored {
+    fn format(&self, result: &QueryResult) -> String {
+        colorize(result)
+    }
+
+    fn format_error(&self, message: &str) -> String {
+        paint(RED, message)
+    }
+}
+
+fn colorize(result: &QueryResult) -> String {
+    match result {
+        QueryResult::Entries(entries) => aligned(entries.iter().map(|(key, value)| (key.clone(), value.clone()))),
+        QueryResult::Dump(entries) => {
+            aligned(entries.iter().map(|(key, value)| (escape_dump_field(key), escape_dump_field(value))))
+        }
+        QueryResult::Previous(None) => paint(DIM, "(nil)"),
+        QueryResult::Values(values) => values
+            .iter()
+            .map(|value| value.clone().unwrap_or_else(|| paint(DIM, "(nil)")))
+            .collect::<Vec<String>>()
+            .join("\n"),
+        result if is_success(result) => paint(GREEN, &result.to_string()),
+        result => result.to_string(),
+    }
+}
+
+// The results that report a change or a setting being made, which are the ones shown as SUCCESS: ...
+fn is_success(result: &QueryResult) -> bool {
+    matches!(
+        result,
+        QueryResult::Inserted { .. }
+            | QueryResult::Updated { .. }
+            | QueryResult::Deleted(_)
+            | QueryResult::Popped { .. }
+            | QueryResult::Renamed { .. }
+            | QueryResult::Copied { .. }
+            | QueryResult::Cleared(_)
+            | QueryResult::PairsSet(_)
+            | QueryResult::Saved { .. }
+            | QueryResult::Loaded { .. }
+            | QueryResult::Exported { .. }
+            | QueryResult::Imported { .. }
+            | QueryResult::Ingested { .. }
+            | QueryResult::Began
+            | QueryResult::Committed(_)
+            | QueryResult::RolledBack(_)
+            | QueryResult::Undone(_)
+            | QueryResult::Using(_)
+            | QueryResult::PrefixSet(_)
+            | QueryResult::Subscribed(_)
+            | QueryResult::Watching(_)
+            | QueryResult::StatsReset
+    )
+}
+
+fn paint(color: &str, text: &str) -> String {
+    format!("{color}{text}{RESET}")
+}
+
+// One "key  value" line per entry, with every key padded to the width of the longest
+fn aligned(entries: impl Iterator<Item = (String, String)>) -> String {
+    let entries: Vec<(String, String)> = entries.collect();
+    let width = entries.iter().map(|(key, _)| key.chars().count()).max().unwrap_or(0);
+    entries
+        .iter()
+        .map(|(key, value)| format!("{key:<width$}  {value}"))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formatted_output() {
+        let inserted = QueryResult::Inserted { key: "a".to_string(), value: "1".to_string() };
+        assert_eq!(Colored.format(&inserted), "\x1b[32mSUCCESS: Inserted a:1 into database\x1b[0m");
+        assert_eq!(Colored.format(&QueryResult::Previous(None)), "\x1b[2m(nil)\x1b[0m");
+        assert_eq!(Colored.format(&QueryResult::Values(vec![Some("x".to_string()), None])), "x\n\x1b[2m(nil)\x1b[0m");
+        assert_eq!(Colored.format(&QueryResult::Value("plain".to_string())), "plain");
+        assert_eq!(Colored.format_error("Query is malformed"), "\x1b[31mQuery is malformed\x1b[0m");
+
+        let dump = QueryResult::Dump(vec![
+            ("a".to_string(), "1".to_string()),
+            ("longer".to_string(), "tab\there".to_string()),
+        ]);
+        assert_eq!(Colored.format(&dump), "a       1\nlonger  tab\\there");
+        let range = QueryResult::Entries(vec![("k1".to_string(), "v".to_string()), ("key2".to_string(), "w".to_string())]);
+        assert_eq!(Colored.format(&range), "k1    v\nkey2  w");
+    }
+}
@@ -0,0 +1,187 @@
+use std::borrow::Cow;
+use std::io;
+
+use crate::{Entry, Storage};
+
+// Values longer than this many bytes are compressed by --compress. Shorter ones rarely shrink enough to be worth it
+pub const COMPRESS_THRESHOLD: usize = 256;
+
+// The shortest and longest repeat a single back-reference can stand for, and how far back it can reach
+const MIN_MATCH: usize = 4;
+const MAX_MATCH: usize = MIN_MATCH + 0x7F;
+const MAX_OFFSET: usize = 0xFFFF;
+const MAX_LITERALS: usize = 0x80;
+
+// A small LZ77 compressor, which is plenty for the repetitive text blobs --compress is for. The output is a
+// series of tokens, each starting with a control byte:
+//   0x00-0x7F  a run of control + 1 literal bytes, which follow it
+//   0x80-0xFF  a copy of (control & 0x7F) + 4 bytes starting a two-byte big-endian offset back
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() / 2);
+    // The last position each four-byte sequence was seen at
+    let mut seen = vec![usize::MAX; 1 << 12];
+    let mut literals_start = 0;
+    let mut i = 0;
+
+    while i + MIN_MATCH <= input.len() {
+        let slot = hash(&input[i..i + MIN_MATCH]);
+        let candidate = seen[slot];
+        seen[slot] = i;
+
+        if candidate != usize::MAX && i - candidate <= MAX_OFFSET && input[candidate..candidate + MIN_MATCH] == input[i..i + MIN_MATCH] {
+            let mut len = MIN_MATCH;
+            while len < MAX_MATCH && i + len < input.len() && input[candidate + len] == input[i + len] {
+                len += 1;
+            }
+            push_literals(&mut output, &input[literals_start..i]);
+            let offset = i - candidate;
+            output.push(0x80 | (len - MIN_MATCH) as u8);
+            output.extend_from_slice(&(offset as u16).to_be_bytes());
+            i += len;
+            literals_start = i;
+        } else {
+            i += 1;
+        }
+    }
+    push_literals(&mut output, &input[literals_start..]);
+    output
+}
+
+// The reverse of compress. None if the bytes aren't something compress could have written
+pub fn decompress(input: &[u8]) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(input.len() * 2);
+    let mut i = 0;
+
+    while i < input.len() {
+        let control = input[i] as usize;
+        i += 1;
+        if control < 0x80 {
+            let literals = input.get(i..i + control + 1)?;
+            output.extend_from_slice(literals);
+            i += control + 1;
+        } else {
+            let offset = u16::from_be_bytes([*input.get(i)?, *input.get(i + 1)?]) as usize;
+            i += 2;
+            if offset == 0 || offset > output.len() {
+                return None;
+            }
+            // Copied a byte at a time, since a repeat is allowed to overlap the bytes it's producing
+            let start = output.len() - offset;
+            for n in 0..(control & 0x7F) + MIN_MATCH {
+                output.push(output[start + n]);
+            }
+        }
+    }
+    Some(output)
+}
+
+fn push_literals(output: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(MAX_LITERALS) {
+        output.push((chunk.len() - 1) as u8);
+        output.extend_from_slice(chunk);
+    }
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    (word.wrapping_mul(0x9E37_79B1) >> 20) as usize
+}
+
+// A Storage that compresses each value longer than threshold bytes on its way into the store underneath.
+// Reads decompress through Value, so neither the queries nor the other wrappers can tell the difference.
+// With no threshold it passes everything through untouched
+pub struct CompressedStore<S: Storage> {
+    inner: S,
+    threshold: Option<usize>,
+}
+
+impl<S: Storage> CompressedStore<S> {
+    pub fn new(inner: S, threshold: Option<usize>) -> Self {
+        CompressedStore { inner, threshold }
+    }
+}
+
+impl<S: Storage> Storage for CompressedStore<S> {
+    fn get_entry(&self, key: &str) -> Option<&Entry> {
+        self.inner.get_entry(key)
+    }
+
+    fn insert_entry(&mut self, key: String, mut entry: Entry) -> Option<Entry> {
+        if let Some(threshold) = self.threshold {
+            entry.value = entry.value.compressed(threshold);
+        }
+        self.inner.insert_entry(key, entry)
+    }
+
+    fn remove_entry(&mut self, key: &str) -> Option<Entry> {
+        self.inner.remove_entry(key)
+    }
+
+    fn contains_key(&self, key: &str) -> bool {
+        self.inner.contains_key(key)
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn keys(&self) -> Box<dyn Iterator<Item = &String> + '_> {
+        self.inner.keys()
+    }
+
+    fn clear(&mut self) {
+        self.inner.clear()
+    }
+
+    fn range(&self, start: &str, end: &str) -> Vec<(&String, Cow<'_, str>)> {
+        self.inner.range(start, end)
+    }
+
+    fn keys_after(&self, after: &str, count: usize) -> Vec<&String> {
+        self.inner.keys_after(after, count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn backend_name(&self) -> &'static str {
+        self.inner.backend_name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn round_trips() {
+        let repetitive = "abcabcabcabcabcabcabcabc-".repeat(100);
+        let inputs: [&[u8]; 5] = [b"", b"x", b"no repeats here", repetitive.as_bytes(), &[0u8; 5000]];
+        for input in inputs {
+            assert_eq!(decompress(&compress(input)).unwrap(), input);
+        }
+        assert!(compress(repetitive.as_bytes()).len() < repetitive.len() / 10);
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        // A copy reaching back before the start, and a literal run cut short
+        assert!(decompress(&[0x80, 0x00, 0x05]).is_none());
+        assert!(decompress(&[0x05, b'a']).is_none());
+    }
+
+    #[test]
+    fn store_compresses_large_values() {
+        let mut store = CompressedStore::new(HashMap::<String, Entry>::new(), Some(COMPRESS_THRESHOLD));
+        let blob = "lorem ipsum dolor sit amet ".repeat(100);
+        store.insert("blob".to_string(), blob.clone());
+        store.insert("small".to_string(), "tiny".to_string());
+
+        assert!(store.get_entry("blob").unwrap().value.is_compressed());
+        assert!(!store.get_entry("small").unwrap().value.is_compressed());
+        assert_eq!(store.get("blob").unwrap(), blob);
+        assert_eq!(store.remove("blob").unwrap(), blob);
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::{now_millis, Databases, EvictionPolicy, Formatter, Notifier, OutputFormat, SharedRng, Stats};
+
+// Every setting that changes how the engine behaves, built once in main from the command-line flags and
+// passed down to wherever it's needed. Default gives the plain behaviour, so anything that doesn't care
+// about a setting can pass Config::default()
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    // Lowercase every key before it is looked up or stored, so User and user are the same entry
+    pub case_insensitive_keys: bool,
+    // Refuse keys containing control characters like tabs and newlines
+    pub reject_control_keys: bool,
+    // Stop a script at the first failing statement instead of reporting it and carrying on
+    pub strict: bool,
+    // The longest value, in bytes, that a write may leave in the store. None means no limit
+    pub max_value_bytes: Option<usize>,
+    // The longest line, in bytes, read from the prompt, a script or a client. None means no limit
+    pub max_line_bytes: Option<usize>,
+    // How many seconds a key lives after a command creates it, before it expires. None means keys never expire
+    // unless they're given a TTL
+    pub default_ttl: Option<u64>,
+    // The most keys the store may hold. None means no limit
+    pub max_keys: Option<usize>,
+    // How many earlier values each key keeps for HISTORY, recorded by every write that replaces one. 0 keeps none
+    pub history: usize,
+    // Which keys go when a write takes the store past max_keys
+    pub eviction: EvictionPolicy,
+    // Where TOUCH and --max-keys get the access times they record, shared by every clone
+    pub access_clock: AccessClock,
+    // Refuse every command that would change the store, leaving reads working as normal
+    pub read_only: bool,
+    // Let --serve clients run SAVE, LOAD, EXPORT, IMPORT and INGEST, which read and write files on the server.
+    // Set by --serve-files
+    pub serve_files: bool,
+    // Put in front of every key a query names, so a session can keep to its own namespace. Set by PREFIX
+    pub key_prefix: Option<String>,
+    // Extra keywords for commands on top of the built-in ones like GET for SELECT, mapping each alias to the
+    // keyword it stands for. Both sides ignore case. Set by alias.NAME in the config file
+    pub aliases: HashMap<String, String>,
+    // What the interactive prompt shows, with %n for the database and %c for the command count. None means
+    // DEFAULT_PROMPT. Set by --prompt or RBDB_PROMPT
+    pub prompt: Option<String>,
+    // Print how long each query took, on stderr. Set by --timing or TIMING ON at the prompt
+    pub timing: bool,
+    // How results are printed at the prompt and in batch mode. Set by --format
+    pub format: OutputFormat,
+    // Color successes and errors and line up RANGE and DUMP columns. main turns it on when stdout and stderr are
+    // both terminals, unless --no-color. Only the plain format is ever colored
+    pub color: bool,
+    // Where the prompt writes the store back to when it exits. main sets it to the data file unless --no-autosave
+    pub autosave: Option<String>,
+    // Where RANDOMKEY gets its randomness. Seeded from the clock unless --seed picks a fixed sequence
+    pub rng: SharedRng,
+    // The counters STATS reports, shared by every clone so all connections add to the same numbers
+    pub stats: Stats,
+    // The databases USE can switch to besides the one rbdb was started with, shared by every clone
+    pub databases: Databases,
+    // Where changes are sent on their way to SUBSCRIBEd connections, shared by every clone
+    pub notifier: Notifier,
+    // When rbdb started, for INFO's uptime
+    pub started_at: StartTime,
+}
+
+impl Config {
+    // What report prints results with
+    pub fn formatter(&self) -> &'static dyn Formatter {
+        self.format.formatter(self.color)
+    }
+}
+
+// Defaults to the moment it's made, which for the Config main builds is startup
+#[derive(Debug, Clone, Copy)]
+pub struct StartTime(pub Instant);
+
+impl StartTime {
+    pub fn elapsed(&self) -> Duration {
+        self.0.elapsed()
+    }
+}
+
+impl Default for StartTime {
+    fn default() -> Self {
+        StartTime(Instant::now())
+    }
+}
+
+// Hands out access times as Unix milliseconds, except that two uses in the same millisecond still get
+// different times, so the order keys were used in never comes down to a tie
+#[derive(Debug, Clone, Default)]
+pub struct AccessClock(Arc<AtomicU64>);
+
+impl AccessClock {
+    pub fn tick(&self) -> u64 {
+        let now = now_millis();
+        let mut last = self.0.load(Ordering::Relaxed);
+        loop {
+            let next = now.max(last + 1);
+            match self.0.compare_exchange_weak(last, next, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => return next,
+                Err(actual) => last = actual,
+            }
+        }
+    }
+}
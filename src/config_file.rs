@@ -0,0 +1,167 @@
+// Just enough TOML for rbdb.toml: one `name = value` setting per line, where the value is a double-quoted
+// string or a whole number, and # starts a comment. Aliases use TOML's dotted names, as in alias.FETCH =
+// "SELECT". Tables, arrays and the rest of TOML aren't needed for a handful of flat settings, so they're
+// refused like any other malformed line
+use std::fs;
+use std::io;
+
+// Where main looks for settings when --config doesn't name a file
+pub const DEFAULT_CONFIG_FILE: &str = "rbdb.toml";
+
+// The settings a config file can give. Each one is None when the file leaves it out, and a command-line flag
+// for the same setting always wins over it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigFile {
+    // "hash" or "btree", as for --backend
+    pub backend: Option<String>,
+    // The data file to load and save, as if it were given on the command line
+    pub data_file: Option<String>,
+    pub max_value_bytes: Option<usize>,
+    // Seconds before a newly created key expires, as for --default-ttl
+    pub default_ttl: Option<u64>,
+    pub prompt: Option<String>,
+    // Each alias.NAME line, as NAME and the keyword it stands for, in the order they're given
+    pub aliases: Vec<(String, String)>,
+}
+
+// Reads the settings in path. A file that isn't there gives None, so rbdb runs the same without one.
+// Anything else that goes wrong, reading or parsing, is reported with the file's name
+pub fn read_config_file(path: &str) -> Result<Option<ConfigFile>, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => parse(&text).map(Some).map_err(|e| format!("{path}: {e}")),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{path}: {e}")),
+    }
+}
+
+// Every line is a setting, a comment or blank. On anything else the error says which line and why
+pub fn parse(text: &str) -> Result<ConfigFile, String> {
+    let mut file = ConfigFile::default();
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let Some((name, value)) = line.split_once('=') else {
+            return Err(format!("line {line_number}: expected name = value"));
+        };
+        let (name, value) = (name.trim(), value.trim());
+        let fail = |what: &str| format!("line {line_number}: {name} {what}");
+        match name {
+            "backend" => file.backend = Some(parse_string(value).ok_or_else(|| fail("must be a quoted string"))?),
+            "data_file" => file.data_file = Some(parse_string(value).ok_or_else(|| fail("must be a quoted string"))?),
+            "prompt" => file.prompt = Some(parse_string(value).ok_or_else(|| fail("must be a quoted string"))?),
+            "max_value_bytes" => file.max_value_bytes = Some(value.parse().map_err(|_| fail("must be a number of bytes"))?),
+            "default_ttl" => match value.parse() {
+                Ok(seconds) if seconds > 0 => file.default_ttl = Some(seconds),
+                _ => return Err(fail("must be a positive number of seconds")),
+            },
+            _ if name.starts_with("alias.") => {
+                let alias = &name["alias.".len()..];
+                let target = parse_string(value).ok_or_else(|| fail("must be a quoted string"))?;
+                if alias.is_empty() || alias.contains(char::is_whitespace) {
+                    return Err(format!("line {line_number}: {name} is not a name an alias can have"));
+                }
+                if crate::find_command(alias).is_some() {
+                    return Err(format!("line {line_number}: {alias} is already a command"));
+                }
+                if crate::find_command(&target).is_none() {
+                    return Err(fail(&format!("names {target}, which is not a command")));
+                }
+                file.aliases.push((alias.to_string(), target));
+            }
+            _ => return Err(format!("line {line_number}: unknown setting {name}")),
+        }
+    }
+    Ok(file)
+}
+
+// Cuts the line at the first # that isn't inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+// A double-quoted string with TOML's common escapes, and nothing after the closing quote
+fn parse_string(value: &str) -> Option<String> {
+    let inner = value.strip_prefix('"')?;
+    let mut parsed = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return chars.as_str().is_empty().then_some(parsed),
+            '\\' => parsed.push(match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                '"' => '"',
+                '\\' => '\\',
+                _ => return None,
+            }),
+            c => parsed.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_setting() {
+        let text = r#"
+# Settings for the shared dev database
+backend = "btree"
+data_file = "dev.db"   # saved on exit
+max_value_bytes = 4096
+default_ttl = 3600
+prompt = "dev #%c> "
+alias.fetch = "SELECT"
+alias.GRAB = "get"
+"#;
+        let expected = ConfigFile {
+            backend: Some("btree".to_string()),
+            data_file: Some("dev.db".to_string()),
+            max_value_bytes: Some(4096),
+            default_ttl: Some(3600),
+            prompt: Some("dev #%c> ".to_string()),
+            aliases: vec![("fetch".to_string(), "SELECT".to_string()), ("GRAB".to_string(), "get".to_string())],
+        };
+        assert_eq!(parse(text).unwrap(), expected);
+        assert_eq!(parse("").unwrap(), ConfigFile::default());
+        assert_eq!(parse(r#"prompt = "say \"hi\"\t""#).unwrap().prompt.unwrap(), "say \"hi\"\t");
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        let cases = [
+            ("backend btree", "line 1: expected name = value"),
+            ("backend = btree", "line 1: backend must be a quoted string"),
+            ("\nprompt = \"open", "line 2: prompt must be a quoted string"),
+            ("prompt = \"a\" \"b\"", "line 1: prompt must be a quoted string"),
+            ("max_value_bytes = -1", "line 1: max_value_bytes must be a number of bytes"),
+            ("default_ttl = 0", "line 1: default_ttl must be a positive number of seconds"),
+            ("[server]", "line 1: expected name = value"),
+            ("colour = \"on\"", "line 1: unknown setting colour"),
+            ("alias.fetch = \"FROB\"", "line 1: alias.fetch names FROB, which is not a command"),
+            ("alias.get = \"SELECT\"", "line 1: get is already a command"),
+            ("alias. = \"SELECT\"", "line 1: alias. is not a name an alias can have"),
+            ("alias.fetch = 1", "line 1: alias.fetch must be a quoted string"),
+        ];
+        for (text, error) in cases {
+            assert_eq!(parse(text).unwrap_err(), error, "{text}");
+        }
+    }
+}
@@ -0,0 +1,25 @@
+// CRC-32 (the IEEE polynomial used by zip, gzip and PNG), for spotting files that were damaged on disk.
+// Computed a bit at a time, which is plenty fast for the size of file rbdb writes
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_ne!(crc32(b"key\tvalue\n"), crc32(b"key\tvalua\n"));
+    }
+}
@@ -0,0 +1,118 @@
+// Just enough CSV (RFC 4180 style) for EXPORT and IMPORT: fields are separated by commas, and a field
+// containing a comma, quote or line break is wrapped in double quotes with inner quotes doubled
+
+// Quotes a field only when it needs it, so plain values stay readable
+pub fn escape_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// Splits CSV text into rows of fields, each tagged with the line number the row starts on.
+// Quoted fields may span lines. On malformed input the offending line number is returned as the error.
+// Blank lines are skipped
+pub fn parse(contents: &str) -> Result<Vec<(usize, Vec<String>)>, usize> {
+    let mut rows: Vec<(usize, Vec<String>)> = Vec::new();
+    let mut fields: Vec<String> = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut quoted = false;
+    let mut line = 1;
+    let mut row_start = 1;
+    let mut chars = contents.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                '\n' => {
+                    line += 1;
+                    field.push(c);
+                }
+                _ => field.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            // A quote is only allowed to open a field
+            '"' if field.is_empty() && !quoted => {
+                in_quotes = true;
+                quoted = true;
+            }
+            ',' => {
+                fields.push(std::mem::take(&mut field));
+                quoted = false;
+            }
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+                if !is_blank(&fields, quoted) {
+                    rows.push((row_start, std::mem::take(&mut fields)));
+                }
+                fields.clear();
+                quoted = false;
+                line += 1;
+                row_start = line;
+            }
+            // Anything else straight after a closing quote, or a stray quote mid-field
+            _ if quoted || c == '"' => return Err(line),
+            _ => field.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err(row_start);
+    }
+
+    // The last row may not end with a newline
+    fields.push(field);
+    if !is_blank(&fields, quoted) {
+        rows.push((row_start, fields));
+    }
+
+    Ok(rows)
+}
+
+fn is_blank(fields: &[String], quoted: bool) -> bool {
+    fields.len() == 1 && fields[0].is_empty() && !quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape_field("plain"), "plain");
+        assert_eq!(escape_field("a,b"), "\"a,b\"");
+        assert_eq!(escape_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(escape_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn parse_round_trips_escaped_fields() {
+        let values = ["plain", "a,b", "say \"hi\"", "two\nlines", ""];
+        let contents: String = values.iter().map(|v| format!("key,{}\n", escape_field(v))).collect();
+        let rows = parse(&contents).unwrap();
+        let parsed: Vec<&str> = rows.iter().map(|(_, fields)| fields[1].as_str()).collect();
+        assert_eq!(parsed, values);
+
+        // The row after the two-line value starts two lines later
+        let starts: Vec<usize> = rows.iter().map(|(line, _)| *line).collect();
+        assert_eq!(starts, vec![1, 2, 3, 4, 6]);
+    }
+
+    #[test]
+    fn parse_reports_bad_line() {
+        assert_eq!(parse("a,1\nb,\"unterminated\n"), Err(2));
+        assert_eq!(parse("a,1\nb,\"quoted\"junk\n"), Err(2));
+        assert_eq!(parse("a,1\n\nb,2\nc,mid\"quote\n"), Err(4));
+    }
+}
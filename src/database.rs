@@ -0,0 +1,176 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::{execute_line, execute_query, now_millis, Config, Entry, Query, QueryResult, QueryType, RbdbError, Storage};
+
+/// A store to embed in another program, with nothing to set up. It takes the same lines the prompt does, and
+/// `get`, `set` and `delete` cover the everyday cases without building a line at all.
+///
+/// The prompt itself isn't built on it: the REPL, scripts and `--serve` share their store behind a lock and keep
+/// a `Session` each, where a `Database` owns its store outright. Both run lines through the same engine.
+///
+/// ```
+/// use rbdb::{Database, QueryResult};
+///
+/// let mut db = Database::new();
+/// let result = db.execute("INSERT a b").unwrap();
+/// assert_eq!(result, QueryResult::Inserted { key: "a".to_string(), value: "b".to_string() });
+/// assert_eq!(db.get("a").unwrap(), Some("b".to_string()));
+/// ```
+///
+/// Any backend and settings can be used instead of the defaults:
+///
+/// ```
+/// use std::collections::BTreeMap;
+/// use rbdb::{Config, Database, Entry};
+///
+/// let config = Config { case_insensitive_keys: true, ..Config::default() };
+/// let mut db = Database::with_store(BTreeMap::<String, Entry>::new(), config);
+/// db.set("User", "ann").unwrap();
+/// assert_eq!(db.get("user").unwrap(), Some("ann".to_string()));
+/// ```
+pub struct Database<S: Storage = HashMap<String, Entry>> {
+    store: S,
+    config: Config,
+}
+
+impl Database {
+    /// An empty database on the HashMap backend with the default settings.
+    pub fn new() -> Self {
+        Database::with_store(HashMap::new(), Config::default())
+    }
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Database::new()
+    }
+}
+
+impl<S: Storage> Database<S> {
+    pub fn with_store(store: S, config: Config) -> Self {
+        Database { store, config }
+    }
+
+    /// Runs one line, exactly as it would be typed at the prompt. Transactions, `USE` and the other commands
+    /// that need a session to remember them between lines answer with `RbdbError::NoSession`.
+    ///
+    /// ```
+    /// use rbdb::{Database, QueryResult, RbdbError};
+    ///
+    /// let mut db = Database::new();
+    /// db.execute("INSERT a b").unwrap();
+    /// assert!(matches!(db.execute("INCR a"), Err(RbdbError::NotAnInteger(_))));
+    /// assert_eq!(db.execute("COUNT").unwrap(), QueryResult::Count(1));
+    /// ```
+    pub fn execute(&mut self, line: &str) -> Result<QueryResult, RbdbError> {
+        execute_line(line, &mut self.store, &self.config)
+    }
+
+    /// The value of key, or None if it isn't set or has expired.
+    pub fn get(&mut self, key: &str) -> Result<Option<String>, RbdbError> {
+        match self.run(QueryType::Select, &[key])? {
+            QueryResult::Found { value, .. } => Ok(Some(value)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Sets key to value whether or not it already has one, like `UPSERT`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), RbdbError> {
+        self.run(QueryType::Upsert, &[key, value]).map(|_| ())
+    }
+
+    /// Removes key, returning whether there was anything to remove.
+    ///
+    /// ```
+    /// use rbdb::Database;
+    ///
+    /// let mut db = Database::new();
+    /// db.set("a", "1").unwrap();
+    /// assert!(db.delete("a").unwrap());
+    /// assert!(!db.delete("a").unwrap());
+    /// ```
+    pub fn delete(&mut self, key: &str) -> Result<bool, RbdbError> {
+        Ok(matches!(self.run(QueryType::GetDel, &[key])?, QueryResult::Previous(Some(_))))
+    }
+
+    /// Every key and its value, skipping keys that have expired. The BTreeMap backend gives them in key order,
+    /// the HashMap backend in no particular order. A value kept compressed is decompressed on the way out,
+    /// which is why values come as a `Cow` rather than a plain `&str`.
+    ///
+    /// ```
+    /// use std::collections::BTreeMap;
+    /// use rbdb::{Config, Database, Entry};
+    ///
+    /// let mut db = Database::with_store(BTreeMap::<String, Entry>::new(), Config::default());
+    /// db.set("b", "2").unwrap();
+    /// db.set("a", "1").unwrap();
+    /// let pairs: Vec<(&str, String)> = db.iter().map(|(key, value)| (key, value.into_owned())).collect();
+    /// assert_eq!(pairs, vec![("a", "1".to_string()), ("b", "2".to_string())]);
+    /// ```
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Cow<'_, str>)> {
+        let now = now_millis();
+        self.store.keys().filter_map(move |key| {
+            let entry = self.store.get_entry(key).filter(|entry| !entry.is_expired(now))?;
+            Some((key.as_str(), entry.value.as_str()))
+        })
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    // The helpers build their queries directly, so keys and values with spaces or quotes in them need no escaping
+    fn run(&mut self, q_type: QueryType, args: &[&str]) -> Result<QueryResult, RbdbError> {
+        let query = Query { q_type, args: args.iter().map(|arg| arg.to_string()).collect() };
+        execute_query(&query, &mut self.store, &self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn helpers_need_no_quoting() {
+        let mut db = Database::new();
+        db.set("full name", "ann \"the\" admin").unwrap();
+        assert_eq!(db.get("full name").unwrap(), Some("ann \"the\" admin".to_string()));
+        assert_eq!(db.execute(r#"SELECT "full name""#).unwrap().to_string(), "ann \"the\" admin");
+
+        // The same rules apply as at the prompt
+        assert!(matches!(db.set("", "x"), Err(RbdbError::InvalidKey(_))));
+        let mut db = Database::with_store(HashMap::new(), Config { read_only: true, ..Config::default() });
+        assert!(matches!(db.set("a", "1"), Err(RbdbError::ReadOnly)));
+        assert_eq!(db.get("a").unwrap(), None);
+    }
+
+    #[test]
+    fn answers_like_the_prompt() {
+        // The prompt goes through a Session rather than a Database, so this keeps the two from drifting apart
+        let shared = std::sync::Mutex::new(HashMap::<String, Entry>::new());
+        let mut session = crate::Session::new(&shared);
+        let mut db = Database::new();
+        let config = Config::default();
+        for line in ["INSERT a 1", "INSERT a 2", "INCR a", "APPEND a x", "SELECT a", "SELECT missing", "KEYS", "DELETE b", "BOGUS"] {
+            let expected = session.execute(line, &config).map_err(|e| e.to_string());
+            assert_eq!(db.execute(line).map_err(|e| e.to_string()), expected, "{line}");
+        }
+    }
+
+    #[test]
+    fn iterates_over_live_entries() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("stale".to_string(), Entry { expires_at: Some(1), ..Entry::new("gone") });
+        let mut db = Database::with_store(store, Config::default());
+        for (key, value) in [("c", "3"), ("a", "1"), ("b", "2")] {
+            db.set(key, value).unwrap();
+        }
+
+        let mut pairs: Vec<(String, String)> = db.iter().map(|(key, value)| (key.to_string(), value.into_owned())).collect();
+        pairs.sort();
+        let expected = [("a", "1"), ("b", "2"), ("c", "3")].map(|(key, value)| (key.to_string(), value.to_string()));
+        assert_eq!(pairs, expected);
+        assert_eq!(db.iter().filter(|(_, value)| value.parse::<i64>().unwrap() > 1).count(), 2);
+    }
+}
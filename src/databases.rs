@@ -0,0 +1,53 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::{lock, Entry, Storage};
+
+// What USE calls the store rbdb was started with. It's the one that gets loaded, saved, logged and swept
+pub const DEFAULT_DATABASE: &str = "default";
+
+// A database other than the default one. These live in memory only
+type Database = Box<dyn Storage + Send>;
+
+// The extra databases USE switches to, kept in Config alongside the store rbdb was started with. Clones share
+// the same databases, so two connections that USE the same name see the same keys
+#[derive(Clone)]
+pub struct Databases {
+    stores: Arc<Mutex<BTreeMap<String, Database>>>,
+    // Makes an empty store for a database the first time it's used, so they all match --backend
+    new_store: fn() -> Database,
+}
+
+impl Databases {
+    pub fn new(new_store: fn() -> Database) -> Self {
+        Databases { stores: Arc::default(), new_store }
+    }
+
+    // Every database there is, the default one included, in name order
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = lock(&self.stores).keys().cloned().collect();
+        names.push(DEFAULT_DATABASE.to_string());
+        names.sort();
+        names
+    }
+
+    // Runs f against the database called name, creating it empty if this is the first time it's been asked for
+    pub(crate) fn with<R>(&self, name: &str, f: impl FnOnce(&mut Database) -> R) -> R {
+        let mut stores = lock(&self.stores);
+        let store = stores.entry(name.to_string()).or_insert_with(self.new_store);
+        f(store)
+    }
+}
+
+impl Default for Databases {
+    fn default() -> Self {
+        Databases::new(|| Box::new(HashMap::<String, Entry>::new()))
+    }
+}
+
+impl fmt::Debug for Databases {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Databases").field("names", &self.names()).finish()
+    }
+}
@@ -0,0 +1,189 @@
+use std::env;
+use std::error::Error;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{Context, Editor, Helper, Highlighter, Hinter, Validator};
+
+use crate::{meta_command, prompt, read_heredoc, report, run_line, Config, RbdbError, Session, Storage, COMMANDS};
+
+// Plugs keyword completion into the editor. Hinting, highlighting and validation are left as they are
+#[derive(Helper, Highlighter, Hinter, Validator)]
+struct RbdbHelper;
+
+impl Completer for RbdbHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete_keyword(line, pos))
+    }
+}
+
+// Completes the command keyword under the cursor, returning where the replacement starts and every keyword
+// that fits. Only the first word is completed, keys and values are the user's own business
+fn complete_keyword(line: &str, pos: usize) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    let start = before.len() - before.trim_start().len();
+    let prefix = &before[start..];
+
+    if prefix.contains(char::is_whitespace) {
+        return (pos, Vec::new());
+    }
+
+    let prefix = prefix.to_uppercase();
+    let candidates = COMMANDS
+        .iter()
+        .flat_map(|command| std::iter::once(command.name).chain(command.aliases.iter().copied()))
+        .filter(|keyword| keyword.starts_with(&prefix))
+        .map(str::to_string)
+        .collect();
+    (start, candidates)
+}
+
+// Keeps track of Ctrl-C at the prompt. The first press only throws away the line being typed, so a stray one
+// never ends the session and loses whatever hasn't been saved. A second press before anything else is entered exits
+#[derive(Default)]
+struct Interrupts {
+    pending: bool,
+}
+
+impl Interrupts {
+    // Called on every Ctrl-C, returning whether this one should end the session
+    fn interrupt(&mut self) -> bool {
+        std::mem::replace(&mut self.pending, true)
+    }
+
+    // Called whenever a line is entered
+    fn reset(&mut self) {
+        self.pending = false;
+    }
+}
+
+// The prompt used when a person is at the keyboard: up/down scroll through earlier queries, and the
+// history is kept in ~/.rbdb_history between sessions
+pub fn run_line_editor<S: Storage>(store: &Mutex<S>, config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut editor: Editor<RbdbHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(RbdbHelper));
+    let history = history_path();
+    let mut session = Session::new(store);
+    let mut config = config.clone();
+    let mut interrupts = Interrupts::default();
+    let mut commands = 0;
+
+    // There's no history the very first time, which is fine
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+
+    loop {
+        let input = match editor.readline(&prompt(&config, &session, commands + 1)) {
+            Ok(input) => input,
+            Err(ReadlineError::Interrupted) => {
+                if interrupts.interrupt() {
+                    println!("Goodbye");
+                    break;
+                }
+                eprintln!("(Press Ctrl-C again to exit)");
+                continue;
+            }
+            Err(ReadlineError::Eof) => {
+                println!("Goodbye");
+                break;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        interrupts.reset();
+        // The editor has already read the whole line, but it's refused all the same, like anywhere else
+        if let Some(limit) = config.max_line_bytes
+            && input.len() > limit
+        {
+            report(&mut io::stdout().lock(), &Err(RbdbError::LineTooLong(limit)), &config)?;
+            continue;
+        }
+        if input.trim().is_empty() {
+            continue;
+        }
+        commands += 1;
+        editor.add_history_entry(input.as_str())?;
+
+        if input.trim() == "quit" || input.trim() == "exit" {
+            break;
+        }
+
+        if let Some(message) = meta_command(&input, &mut config) {
+            println!("{message}");
+            continue;
+        }
+
+        // The rest of a here-doc gets a prompt of its own. Ctrl-C or Ctrl-D gives up on it
+        let line = read_heredoc(&input, || match editor.readline("    -> ") {
+            Ok(line) => Ok(Some(line)),
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => Ok(None),
+            Err(e) => Err(RbdbError::Io(io::Error::other(e))),
+        });
+        let line = match line {
+            Ok(line) => line,
+            Err(RbdbError::Io(e)) => return Err(e.into()),
+            Err(e) => {
+                report(&mut io::stdout().lock(), &Err(e), &config)?;
+                continue;
+            }
+        };
+
+        run_line(&line, &mut io::stdout().lock(), &mut session, &config, false)?;
+    }
+
+    // Losing the history isn't worth failing the session over
+    if let Some(path) = &history
+        && let Err(e) = editor.save_history(path)
+    {
+        eprintln!("Could not save history to {}: {e}", path.display());
+    }
+    Ok(())
+}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".rbdb_history"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completes_keywords() {
+        assert_eq!(complete_keyword("INS", 3), (0, vec!["INSERT".to_string()]));
+        assert_eq!(complete_keyword("  sel", 5), (2, vec!["SELECT".to_string(), "SELECTWHERE".to_string()]));
+
+        // Ambiguous prefixes offer every match
+        let (start, mut candidates) = complete_keyword("IN", 2);
+        candidates.sort();
+        assert_eq!(start, 0);
+        assert_eq!(candidates, vec!["INCR", "INCRBY", "INFO", "INGEST", "INSERT"]);
+
+        // Aliases come from the table too
+        assert_eq!(complete_keyword("FL", 2).1, vec!["FLUSH"]);
+    }
+
+    #[test]
+    fn one_interrupt_keeps_the_session_going() {
+        let mut interrupts = Interrupts::default();
+        assert!(!interrupts.interrupt());
+
+        // Entering a line in between starts the count over
+        interrupts.reset();
+        assert!(!interrupts.interrupt());
+        assert!(interrupts.interrupt());
+    }
+
+    #[test]
+    fn only_completes_the_command() {
+        assert!(complete_keyword("SELECT IN", 9).1.is_empty());
+        assert!(complete_keyword("ZZZ", 3).1.is_empty());
+    }
+}
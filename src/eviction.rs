@@ -0,0 +1,34 @@
+use crate::Storage;
+
+// How to pick which key goes when a write takes the store past --max-keys
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum EvictionPolicy {
+    // The key used longest ago. One that has never been used, like a key loaded from a file, goes first
+    #[default]
+    Lru,
+}
+
+impl EvictionPolicy {
+    fn victim<S: Storage>(self, store: &S) -> Option<String> {
+        match self {
+            // Ties can only be between keys that were never used, so those are broken by name to stay predictable
+            EvictionPolicy::Lru => store
+                .keys()
+                .min_by_key(|key| (store.get_entry(key).and_then(|entry| entry.accessed_at), *key))
+                .cloned(),
+        }
+    }
+}
+
+// Removes keys until the store holds no more than limit, returning how many went
+pub(crate) fn evict<S: Storage>(store: &mut S, limit: usize, policy: EvictionPolicy) -> usize {
+    let mut evicted = 0;
+    while store.len() > limit {
+        let Some(key) = policy.victim(store) else {
+            break;
+        };
+        store.remove_entry(&key);
+        evicted += 1;
+    }
+    evicted
+}
@@ -0,0 +1,178 @@
+use serde_json::Value as Json;
+
+use crate::color::Colored;
+use crate::{escape_dump_field, QueryResult};
+
+// Turns query results into the text the prompt and batch mode print. The engine only ever hands back a
+// QueryResult, so how it looks is decided entirely here
+pub trait Formatter {
+    fn format(&self, result: &QueryResult) -> String;
+
+    // How an error message is shown. Errors go to stderr as text whatever the format
+    fn format_error(&self, message: &str) -> String {
+        message.to_string()
+    }
+}
+
+// What --format picked
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Plain,
+    Json,
+    Tsv,
+}
+
+impl OutputFormat {
+    // Color only ever applies to the plain format, the others are meant to be read by programs
+    pub fn formatter(self, color: bool) -> &'static dyn Formatter {
+        match self {
+            OutputFormat::Plain if color => &Colored,
+            OutputFormat::Plain => &Plain,
+            OutputFormat::Json => &JsonFormat,
+            OutputFormat::Tsv => &Tsv,
+        }
+    }
+}
+
+// The results exactly as QueryResult displays them
+pub struct Plain;
+
+impl Formatter for Plain {
+    fn format(&self, result: &QueryResult) -> String {
+        result.to_string()
+    }
+}
+
+// One JSON document per result. Lists of keys are arrays, entries are {"key":...,"value":...} objects, and
+// anything that's just a message, like SUCCESS: ..., is a JSON string
+pub struct JsonFormat;
+
+impl Formatter for JsonFormat {
+    fn format(&self, result: &QueryResult) -> String {
+        let json = match result {
+            QueryResult::Found { key, value } => entry(key, value),
+            QueryResult::Value(value) => Json::from(value.as_str()),
+            QueryResult::Integer(n) => Json::from(*n),
+            QueryResult::Length(n) | QueryResult::Count(n) => Json::from(*n),
+            QueryResult::Bool(b) => Json::from(*b),
+            QueryResult::Keys(keys) | QueryResult::History(keys) => Json::from(keys.clone()),
+            QueryResult::Scan { cursor, keys } => {
+                return format!("{{\"cursor\":{},\"keys\":{}}}", Json::from(cursor.as_str()), Json::from(keys.clone()));
+            }
+            QueryResult::Values(values) => values.iter().map(|value| Json::from(value.clone())).collect(),
+            QueryResult::Previous(value) => Json::from(value.clone()),
+            QueryResult::Entries(entries) | QueryResult::Dump(entries) => {
+                entries.iter().map(|(key, value)| entry(key, value)).collect()
+            }
+            // Written by hand to keep the fields in the same order the plain format lists them
+            QueryResult::Stats(stats) => {
+                return format!(
+                    "{{\"hits\":{},\"misses\":{},\"reads\":{},\"writes\":{},\"evictions\":{}}}",
+                    stats.hits, stats.misses, stats.reads, stats.writes, stats.evictions
+                );
+            }
+            QueryResult::Info(fields) => {
+                let fields: Vec<String> =
+                    fields.iter().map(|(name, value)| format!("{}:{}", Json::from(*name), Json::from(value.as_str()))).collect();
+                return format!("{{{}}}", fields.join(","));
+            }
+            other => Json::from(other.to_string()),
+        };
+        json.to_string()
+    }
+}
+
+fn entry(key: &str, value: &str) -> Json {
+    let mut object = serde_json::Map::new();
+    object.insert("key".to_string(), Json::from(key));
+    object.insert("value".to_string(), Json::from(value));
+    Json::Object(object)
+}
+
+// Tab-separated lines, one row per key, entry or counter. Fields are escaped the same way as DUMP so a
+// tab or newline in a value can't break a row apart
+pub struct Tsv;
+
+impl Formatter for Tsv {
+    fn format(&self, result: &QueryResult) -> String {
+        let rows: Vec<String> = match result {
+            QueryResult::Found { key, value } => vec![row(&[key, value])],
+            QueryResult::Keys(keys) | QueryResult::History(keys) => keys.iter().map(|key| escape_dump_field(key)).collect(),
+            QueryResult::Scan { cursor, keys } => {
+                std::iter::once(cursor.clone()).chain(keys.iter().map(|key| escape_dump_field(key))).collect()
+            }
+            QueryResult::Values(values) => {
+                values.iter().map(|value| value.as_deref().map_or("(nil)".to_string(), escape_dump_field)).collect()
+            }
+            QueryResult::Entries(entries) | QueryResult::Dump(entries) => {
+                entries.iter().map(|(key, value)| row(&[key, value])).collect()
+            }
+            QueryResult::Stats(stats) => [
+                ("hits", stats.hits),
+                ("misses", stats.misses),
+                ("reads", stats.reads),
+                ("writes", stats.writes),
+                ("evictions", stats.evictions),
+            ]
+            .iter()
+            .map(|(name, count)| format!("{name}\t{count}"))
+            .collect(),
+            QueryResult::Info(fields) => fields.iter().map(|(name, value)| row(&[name, value])).collect(),
+            other => vec![escape_dump_field(&other.to_string())],
+        };
+        rows.join("\n")
+    }
+}
+
+fn row(fields: &[&str]) -> String {
+    fields.iter().map(|field| escape_dump_field(field)).collect::<Vec<String>>().join("\t")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StatsSnapshot;
+
+    fn found() -> QueryResult {
+        QueryResult::Found { key: "user".to_string(), value: "ann \"a\"".to_string() }
+    }
+
+    fn entries() -> QueryResult {
+        QueryResult::Entries(vec![("a".to_string(), "1".to_string()), ("b".to_string(), "two\tparts".to_string())])
+    }
+
+    #[test]
+    fn plain_is_unchanged() {
+        assert_eq!(Plain.format(&found()), "ann \"a\"");
+        assert_eq!(Plain.format(&entries()), "a 1\nb two\tparts");
+    }
+
+    #[test]
+    fn json_rendering() {
+        assert_eq!(JsonFormat.format(&found()), r#"{"key":"user","value":"ann \"a\""}"#);
+        assert_eq!(JsonFormat.format(&QueryResult::Keys(vec!["a".to_string(), "b".to_string()])), r#"["a","b"]"#);
+        assert_eq!(JsonFormat.format(&QueryResult::Values(vec![Some("x".to_string()), None])), r#"["x",null]"#);
+        assert_eq!(JsonFormat.format(&QueryResult::Count(3)), "3");
+        let scan = QueryResult::Scan { cursor: "61".to_string(), keys: vec!["a".to_string()] };
+        assert_eq!(JsonFormat.format(&scan), r#"{"cursor":"61","keys":["a"]}"#);
+        assert_eq!(JsonFormat.format(&QueryResult::Bool(true)), "true");
+        assert_eq!(JsonFormat.format(&entries()), r#"[{"key":"a","value":"1"},{"key":"b","value":"two\tparts"}]"#);
+        assert_eq!(JsonFormat.format(&QueryResult::Deleted("a".to_string())), r#""SUCCESS: Deleted a""#);
+        let stats = StatsSnapshot { hits: 1, misses: 2, reads: 3, writes: 4, evictions: 0 };
+        assert_eq!(JsonFormat.format(&QueryResult::Stats(stats)), r#"{"hits":1,"misses":2,"reads":3,"writes":4,"evictions":0}"#);
+        let info = QueryResult::Info(vec![("version", "1.0".to_string()), ("backend", "hash".to_string())]);
+        assert_eq!(JsonFormat.format(&info), r#"{"version":"1.0","backend":"hash"}"#);
+    }
+
+    #[test]
+    fn tsv_rendering() {
+        assert_eq!(Tsv.format(&found()), "user\tann \"a\"");
+        assert_eq!(Tsv.format(&entries()), "a\t1\nb\ttwo\\tparts");
+        assert_eq!(Tsv.format(&QueryResult::Keys(vec!["a".to_string(), "b".to_string()])), "a\nb");
+        assert_eq!(Tsv.format(&QueryResult::Values(vec![Some("x".to_string()), None])), "x\n(nil)");
+        let stats = StatsSnapshot { hits: 1, misses: 2, reads: 3, writes: 4, evictions: 0 };
+        assert_eq!(Tsv.format(&QueryResult::Stats(stats)), "hits\t1\nmisses\t2\nreads\t3\nwrites\t4\nevictions\t0");
+        assert_eq!(Tsv.format(&QueryResult::Count(3)), "3");
+    }
+}
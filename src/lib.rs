@@ -1,203 +1,4490 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::io;
-use std::io::Write;
+use std::io::{BufRead, IsTerminal, Write};
+use std::fs;
+use std::fmt;
 use std::error::Error;
 use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-pub fn rbdb_run(store: &mut HashMap<String, String>) -> Result<(), Box<dyn Error>> {
-    let mut input = String::new();
+mod base64;
+mod color;
+mod compress;
+mod config;
+mod config_file;
+mod crc32;
+mod csv;
+mod database;
+mod databases;
+mod editor;
+mod eviction;
+mod format;
+mod logger;
+mod notify;
+mod rng;
+mod server;
+mod session;
+mod stats;
+mod storage;
+mod sweeper;
+mod value;
+mod wal;
 
-    loop {
-        input.clear();
-        
-        // Print the prompt to let the user know they're in "query" mode
-        print!("RBDB -> ");
+pub use compress::{CompressedStore, COMPRESS_THRESHOLD};
+pub use config::{AccessClock, Config, StartTime};
+pub use config_file::{read_config_file, ConfigFile, DEFAULT_CONFIG_FILE};
+pub use database::Database;
+pub use databases::{Databases, DEFAULT_DATABASE};
+pub use eviction::EvictionPolicy;
+pub use format::{Formatter, OutputFormat};
+pub use logger::{init_logger, log_level};
+pub use notify::{Event, Notifier, Subscription, Watch};
+pub use rng::SharedRng;
+pub use server::serve;
+pub use session::Session;
+pub use stats::{Stats, StatsSnapshot};
+pub use storage::{Entry, Storage};
+pub use sweeper::Sweeper;
+pub use value::Value;
+pub use wal::WalStore;
+
+// The store is shared behind a Mutex so background work (like the expiry sweeper) can get at it
+// between queries. Each query holds the lock only while it runs
+pub fn rbdb_run<S: Storage>(store: &Mutex<S>, config: &Config) -> Result<(), Box<dyn Error>> {
+    // A terminal gets the line editor with history. When input is piped in there is nobody to prompt,
+    // so it's read line by line and only the query results are printed
+    if io::stdin().is_terminal() {
+        editor::run_line_editor(store, config)?;
+    } else {
+        run_repl(&mut io::stdin().lock(), &mut io::stdout().lock(), store, config, false)?;
+    }
 
-        // Flush the output to ensure the prompt is displayed immediately
-        io::stdout().flush()?;
+    // Reaching here means the session ended normally, so write the changes back rather than relying on a SAVE
+    if let Some(path) = &config.autosave {
+        let count = save_store(path, &*lock(store))?;
+        eprintln!("Saved {count} entries to {path}");
+    }
+    Ok(())
+}
 
-        // Read a line from the standard input
-        io::stdin().read_line(&mut input)?;
+// The REPL loop itself, reading from any BufRead and writing to any Write so it can be driven by
+// something other than the terminal
+fn run_repl<R: BufRead, W: Write, S: Storage>(
+    reader: &mut R,
+    out: &mut W,
+    store: &Mutex<S>,
+    config: &Config,
+    interactive: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut session = Session::new(store);
+    // Meta-commands like TIMING can change the settings as the session goes on
+    let mut config = config.clone();
+    let mut commands = 0;
 
-        if input.trim() == "quit" || input.trim() == "exit" {
-            break
+    loop {
+        if interactive {
+            // Print the prompt to let the user know they're in "query" mode
+            write!(out, "{}", prompt(&config, &session, commands + 1))?;
+
+            // Flush the output to ensure the prompt is displayed immediately
+            out.flush()?;
         }
-        
-        // Tokenize the input
-        let tokens: Vec<&str> = input.split_ascii_whitespace().collect();
 
-        // build_query returns a Result<_, Box<dyn Error>>
-        // using ? means: on Err, immediately return that Err from 'run'
-        let query = match Query::build_query(tokens) {
-            Ok(q) => q,
+        // Read a line from the input. None means EOF (Ctrl-D or the end of piped input)
+        let input = match read_next_line(reader, config.max_line_bytes) {
+            Ok(Some(input)) => input,
+            Ok(None) => {
+                if interactive {
+                    writeln!(out, "\nGoodbye")?;
+                }
+                break
+            }
+            Err(RbdbError::Io(e)) => return Err(e.into()),
             Err(e) => {
-                eprintln!("Query is malformed: {e}");
+                report(out, &Err(e), &config)?;
                 continue;
             }
         };
+        if !input.trim().is_empty() {
+            commands += 1;
+        }
+
+        if input.trim() == "quit" || input.trim() == "exit" {
+            break
+        }
+        if let Some(message) = meta_command(&input, &mut config) {
+            writeln!(out, "{message}")?;
+            continue;
+        }
 
-        // We now need to process the query, and update the store
-        match process_query(&query, store) {
-            Ok(r) => println!("{r}"),
+        let line = match read_heredoc(&input, || read_next_line(reader, config.max_line_bytes)) {
+            Ok(line) => line,
+            Err(RbdbError::Io(e)) => return Err(e.into()),
             Err(e) => {
-                eprintln!("Query processing failed: {e}");
+                report(out, &Err(e), &config)?;
                 continue;
             }
         };
 
-        // Print out the Query tokens
-        // let value_display = query.value.as_deref().unwrap_or("");
-        // println!("Query Contains: {:?} {} {}", query.q_type, query.key, value_display);
+        // A failed query is reported and skipped rather than ending the session
+        run_line(&line, out, &mut session, &config, false)?;
     }
     Ok(())
 }
 
-fn process_query(query: &Query, store: &mut HashMap<String, String>) -> Result<String, Box<dyn Error>> {
-    let mut query_result = String::new();
-    match query.q_type {
-        QueryType::Insert => {
-            if store.contains_key(&query.key) {
-                eprintln!("Key {} already exists. Use UPDATE query instead", query.key);
-            }
+// Runs every line from reader as a query, printing results as it goes. Blank lines and comments are
+// skipped. A failing line is reported and the script carries on, unless config.strict is set,
+// in which case the first failure stops the script and is returned. Otherwise the Status of the last
+// query that ran is returned
+pub fn run_script<R: BufRead, W: Write, S: Storage>(
+    mut reader: R,
+    out: &mut W,
+    store: &Mutex<S>,
+    config: &Config,
+) -> Result<Status, Box<dyn Error>> {
+    let mut status = Status::Success;
+    let mut session = Session::new(store);
+    let mut lines_read = 0;
+
+    while let Some(line) = read_next_line(&mut reader, config.max_line_bytes).transpose() {
+        lines_read += 1;
+        // Where the statement started, for reporting, even if a here-doc takes up the lines after it
+        let line_number = lines_read;
+        let outcome = match line {
+            Ok(line) => {
+                let trimmed = line.trim();
+                // Skipped here rather than left to run_line, so a trailing comment doesn't reset the status
+                if trimmed.is_empty() || is_comment(trimmed) {
+                    continue;
+                }
 
-            if let Some(ref value) = query.value {
-                store.insert(query.key.clone(), value.clone());
-                query_result = format!("SUCCESS: Inserted {}:{} into database", query.key, value);
-            } else {
-                eprintln!("INSERT requires a value, but none was provided");
+                match read_heredoc(trimmed, || {
+                    lines_read += 1;
+                    read_next_line(&mut reader, config.max_line_bytes)
+                }) {
+                    Ok(expanded) => run_line(&expanded, out, &mut session, config, config.strict)?,
+                    Err(RbdbError::Io(e)) => return Err(e.into()),
+                    Err(e) => unreadable_line(out, e, config)?,
+                }
             }
+            Err(RbdbError::Io(e)) => return Err(e.into()),
+            Err(e) => unreadable_line(out, e, config)?,
+        };
+        status = outcome.last;
+        if let Some((statement, e)) = outcome.first_failure
+            && config.strict
+        {
+            return Err(match statement {
+                Some(statement) => format!("Script stopped on line {line_number}, statement {statement}: {e}"),
+                None => format!("Script stopped on line {line_number}: {e}"),
+            }
+            .into());
         }
-        QueryType::Select => {
-            if let Some(value) = store.get(&query.key) {
-                query_result = format!{"{}", value};
-            } else {
-                eprintln!{"No entry found for key = {}", query.key};
+    }
+    Ok(status)
+}
+
+// Reports a line that couldn't be read in full, like an over-long one or a here-doc the input ended in the middle
+// of. It never got as far as running, but it counts as a failure like any other
+fn unreadable_line<W: Write>(out: &mut W, e: RbdbError, config: &Config) -> io::Result<LineOutcome> {
+    let failed = Err(e);
+    report(out, &failed, config)?;
+    Ok(LineOutcome { last: Status::of(&failed), first_failure: failed.err().map(|e| (None, e)) })
+}
+
+// What the prompt shows before the user types their nth command: config.prompt, or DEFAULT_PROMPT if it isn't
+// set, with %n replaced by the session's database, %c by n and %% by a lone %
+fn prompt<S: Storage>(config: &Config, session: &Session<S>, n: usize) -> String {
+    let template = config.prompt.as_deref().unwrap_or(DEFAULT_PROMPT);
+    let mut prompt = String::with_capacity(template.len());
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            prompt.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => prompt.push_str(session.database()),
+            Some('c') => prompt.push_str(&n.to_string()),
+            Some('%') => prompt.push('%'),
+            // Anything else isn't a substitution, so it's shown as it was written
+            Some(other) => {
+                prompt.push('%');
+                prompt.push(other);
             }
+            None => prompt.push('%'),
         }
-        QueryType::Update => {
-            if store.contains_key(&query.key) {
-                if let Some(ref value) = query.value {
-                    store.insert(query.key.clone(), value.clone());
-                    query_result = format!{"SUCCESS: Updated {} with {}", query.key, value};
-                } else {
-                    eprintln!("UPDATE requres a value, but none was provided");
+    }
+    prompt
+}
+
+// The prompt when neither --prompt nor RBDB_PROMPT picks another
+pub const DEFAULT_PROMPT: &str = "RBDB -> ";
+
+// Settings that can be changed from the prompt instead of the command line. They aren't queries and never
+// reach the parser. Returns what to tell the user when the line was one of them
+fn meta_command(line: &str, config: &mut Config) -> Option<String> {
+    let mut words = line.split_whitespace();
+    if !words.next()?.eq_ignore_ascii_case("TIMING") {
+        return None;
+    }
+
+    let message = match (words.next(), words.next()) {
+        (Some(setting), None) if setting.eq_ignore_ascii_case("ON") => {
+            config.timing = true;
+            "Timing is on"
+        }
+        (Some(setting), None) if setting.eq_ignore_ascii_case("OFF") => {
+            config.timing = false;
+            "Timing is off"
+        }
+        _ => "Usage: TIMING ON|OFF",
+    };
+    Some(message.to_string())
+}
+
+// How a query turned out, as far as an exit code is concerned
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Success,
+    Failed,
+    NotFound,
+}
+
+impl Status {
+    fn of(outcome: &Result<QueryResult, RbdbError>) -> Self {
+        match outcome {
+            Ok(QueryResult::NotFound(_)) => Status::NotFound,
+            Ok(_) => Status::Success,
+            Err(_) => Status::Failed,
+        }
+    }
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Status::Success => 0,
+            Status::Failed => 1,
+            Status::NotFound => 2,
+        }
+    }
+}
+
+// What happened on one line: the first failure, if there was one, and how the last statement to run turned out
+struct LineOutcome {
+    first_failure: Option<(Option<usize>, RbdbError)>,
+    last: Status,
+}
+
+// Runs each ;-separated statement on the line in order, reporting each one as it goes. The first failure
+// is kept, along with which statement it was (counting from 1) if the line held more than one.
+// The statements after a failure still run unless stop_on_error is set
+fn run_line<W: Write, S: Storage>(
+    line: &str,
+    out: &mut W,
+    session: &mut Session<S>,
+    config: &Config,
+    stop_on_error: bool,
+) -> io::Result<LineOutcome> {
+    let statements = split_statements(line);
+    let numbered = statements.len() > 1;
+    let mut first_failure = None;
+    let mut last = Status::Success;
+
+    for (index, statement) in statements.into_iter().enumerate() {
+        // The file is opened first, so a statement whose result would have nowhere to go isn't run at all
+        let (statement, redirect) = split_redirect(statement);
+        let mut file = None;
+        let started = Instant::now();
+        let outcome = match redirect.as_ref().map(Redirect::open).transpose() {
+            Ok(opened) => {
+                file = opened;
+                session.execute(statement, config)
+            }
+            Err(e) => Err(RbdbError::Io(e)),
+        };
+        let elapsed = started.elapsed();
+        let statement_number = numbered.then_some(index + 1);
+
+        match (&outcome, statement_number, &mut file) {
+            (Err(e), Some(n), _) => {
+                eprintln!("{}", config.formatter().format_error(&format!("Statement {n}: {}", describe_error(e))))
+            }
+            // Never colored, and a file that can't be written to is a problem with this statement, not the session
+            (_, _, Some(file)) => {
+                if let Err(e) = report(file, &outcome, &Config { color: false, ..config.clone() }) {
+                    eprintln!("{}", config.formatter().format_error(&describe_error(&RbdbError::Io(e))));
                 }
-            } else {
-                eprintln!("No entry found for key = {}", query.key);
             }
+            _ => report(out, &outcome, config)?,
         }
-        QueryType::Delete => {
-            if store.remove(&query.key).is_some() {
-                query_result = format!("SUCCESS: Deleted {}", query.key);
-            } else {
-                eprintln!("No entry found for key = {}", query.key);
+        // On stderr, so piping the results somewhere doesn't pick up the timings too
+        if config.timing {
+            eprintln!("Time: {:.3} ms", elapsed.as_secs_f64() * 1000.0);
+        }
+        last = Status::of(&outcome);
+
+        if let Err(e) = outcome
+            && first_failure.is_none()
+        {
+            first_failure = Some((statement_number, e));
+            if stop_on_error {
+                break;
             }
         }
     }
-
-    Ok(query_result)
+    Ok(LineOutcome { first_failure, last })
 }
 
+// Where a statement's result is written instead of the usual output, from a > path or >> path at its end
 #[derive(Debug, PartialEq)]
-enum QueryType {
-    Insert,
-    Select,
-    Update,
-    Delete,
+struct Redirect {
+    path: String,
+    // >> adds to the end of the file rather than replacing it
+    append: bool,
 }
 
-#[derive(Debug)]
-struct Query {
-    q_type: QueryType,
-    key: String,
-    value: Option<String>,
+impl Redirect {
+    fn open(&self) -> io::Result<fs::File> {
+        fs::OpenOptions::new().create(true).write(true).append(self.append).truncate(!self.append).open(&self.path)
+    }
 }
 
-impl Query {
-    pub fn build_query(tokens: Vec<&str>) -> Result<Self, Box<dyn Error>> {
-        // Require at least two tokens
-        if tokens.len() < 2 {
-            return Err("Not enough arguments".into());
+// Splits a trailing > path or >> path off a statement, before it's tokenized. The > has to start a token of its
+// own, outside quotes, and be followed by exactly one more token, so values like a>b or a lone > are left alone.
+// The path may be quoted if it has spaces in it
+fn split_redirect(statement: &str) -> (&str, Option<Redirect>) {
+    let mut operator = None;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut previous = ' ';
+    for (i, c) in statement.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if in_quotes && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            in_quotes = !in_quotes;
+        } else if c == '>' && !in_quotes && previous.is_whitespace() {
+            operator = Some(i);
         }
+        previous = c;
+    }
 
-        // Convert the first token into a QueryType
-        let q_type = match tokens[0].to_uppercase().as_str() {
-            "INSERT" => QueryType::Insert,
-            "SELECT" => QueryType::Select,
-            "UPDATE" => QueryType::Update,
-            "DELETE" => QueryType::Delete,
-            _ => return Err("Invalid query type".into())
+    let Some(start) = operator else {
+        return (statement, None);
+    };
+    let command = &statement[..start];
+    let (append, target) = match statement[start + 1..].strip_prefix('>') {
+        Some(target) => (true, target),
+        None => (false, &statement[start + 1..]),
+    };
+    match tokenize(target).as_deref() {
+        Ok([path]) if !command.trim().is_empty() => (command, Some(Redirect { path: path.clone(), append })),
+        _ => (statement, None),
+    }
+}
+
+// Whether the line is a comment, i.e. starts with # or --. Only whole lines count, so a # later on, quoted
+// or not, is just part of a value
+fn is_comment(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with('#') || line.starts_with("--")
+}
+
+// If the line ends with a here-doc marker like <<END, reads the lines after it with next_line up to one that is
+// exactly END, and hands back the line with the marker replaced by those lines, joined with newlines, as a
+// single quoted value. Any other line comes back as it is. Running out of input first is an error, and so is a
+// line next_line refuses, though only once the terminator is reached, so none of the value is taken for a query
+fn read_heredoc(line: &str, mut next_line: impl FnMut() -> Result<Option<String>, RbdbError>) -> Result<String, RbdbError> {
+    let Some((before, terminator)) = heredoc_marker(line) else {
+        return Ok(line.to_string());
+    };
+
+    let mut value: Vec<String> = Vec::new();
+    let mut refused = None;
+    loop {
+        let next = match next_line() {
+            Ok(Some(next)) => next,
+            Ok(None) => return Err(RbdbError::UnterminatedHeredoc(terminator.to_string())),
+            Err(RbdbError::Io(e)) => return Err(RbdbError::Io(e)),
+            Err(e) => {
+                refused.get_or_insert(e);
+                continue;
+            }
         };
+        let next = next.strip_suffix('\n').map_or(next.as_str(), |next| next.strip_suffix('\r').unwrap_or(next));
+        if next == terminator {
+            break;
+        }
+        value.push(next.to_string());
+    }
+    if let Some(e) = refused {
+        return Err(e);
+    }
+    let value = value.join("\n").replace('\\', "\\\\").replace('"', "\\\"");
+    Ok(format!("{before}\"{value}\""))
+}
+
+// Splits a line ending in <<WORD into what comes before the marker and WORD. A marker inside quotes or a
+// comment is just part of the text, and so is one that isn't a word of letters, digits and underscores
+fn heredoc_marker(line: &str) -> Option<(&str, &str)> {
+    if is_comment(line) {
+        return None;
+    }
+    let line = line.trim_end();
+    let start = line.rfind("<<")?;
+    let terminator = &line[start + 2..];
+    let is_word = !terminator.is_empty() && terminator.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    // The marker has to be a token of its own, after a command that's been closed off properly
+    let before = &line[..start];
+    if !is_word || !before.ends_with(char::is_whitespace) || tokenize(before).is_err() {
+        return None;
+    }
+    Some((before, terminator))
+}
+
+// One line from reader, newline and all, or None at the end of the input. A line longer than limit bytes, not
+// counting its line break, is refused with LineTooLong. The rest of it is read and thrown away a buffer at a time,
+// so a huge line never has to fit in memory and the next read starts on the line after it
+fn read_next_line<R: BufRead>(reader: &mut R, limit: Option<usize>) -> Result<Option<String>, RbdbError> {
+    let Some(limit) = limit else {
+        let mut line = String::new();
+        return Ok((reader.read_line(&mut line)? > 0).then_some(line));
+    };
 
-        let key = tokens[1].to_string();
-        let value = if tokens.len() > 2 {
-            Some(tokens[2].to_string())
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut read_any = false;
+    let mut too_long = false;
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            break;
+        }
+        read_any = true;
+        let end = buffer.iter().position(|&byte| byte == b'\n');
+        let chunk = &buffer[..end.map_or(buffer.len(), |end| end + 1)];
+        // A \r\n after the limit still fits, since the line break doesn't count towards it
+        if too_long || bytes.len() + chunk.len() > limit + 2 {
+            too_long = true;
+            bytes.clear();
         } else {
-            None
-        };
-    
-        Ok(Query { q_type, key, value })
+            bytes.extend_from_slice(chunk);
+        }
+        let used = chunk.len();
+        reader.consume(used);
+        if end.is_some() {
+            break;
+        }
+    }
+
+    if !read_any {
+        return Ok(None);
+    }
+    let content = bytes.strip_suffix(b"\n").map_or(&bytes[..], |bytes| bytes.strip_suffix(b"\r").unwrap_or(bytes));
+    if too_long || content.len() > limit {
+        return Err(RbdbError::LineTooLong(limit));
     }
+    let line = String::from_utf8(bytes).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8"))?;
+    Ok(Some(line))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+// Splits a line into statements on semicolons, leaving alone any that are inside double quotes.
+// Empty statements, like the one after a trailing semicolon, are dropped, and a comment has none at all
+fn split_statements(line: &str) -> Vec<&str> {
+    if is_comment(line) {
+        return Vec::new();
+    }
 
-    #[test]
-    fn good_query() {
-        let tokens = vec!["insert", "key", "value"];
-        let query = Query::build_query(tokens.clone()).unwrap();
-        assert_eq!(query.q_type, QueryType::Insert);
-        assert_eq!(query.key, tokens[1]);
-        let value = query.value.as_deref().unwrap();
-        assert_eq![value, tokens[2]];
+    let mut statements: Vec<&str> = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    for (i, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if in_quotes && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            in_quotes = !in_quotes;
+        } else if c == ';' && !in_quotes {
+            statements.push(&line[start..i]);
+            start = i + 1;
+        }
     }
+    statements.push(&line[start..]);
 
-    #[test]
-    fn bad_query() {
-        let tokens = vec!["delete"];
-        let query = Query::build_query(tokens);
-        assert!(query.is_err());
-        let query_err = query.unwrap_err();
-        assert_eq!(query_err.to_string(), "Not enough arguments");
+    statements.retain(|statement| !statement.trim().is_empty());
+    statements
+}
+
+// Locks any of the mutexes rbdb shares between threads: a store, the notifier's subscribers or a client's
+// connection. A panic while one was held can't leave what it guards half written, so a poisoned one is still usable
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Parses and processes a single line of input against the store, outside of any session. Transactions need
+// somewhere to live between lines, so BEGIN, COMMIT and ROLLBACK only work through a Session
+pub fn execute_line<S: Storage>(line: &str, store: &mut S, config: &Config) -> Result<QueryResult, RbdbError> {
+    let outcome = parse_statement(line, config).and_then(|query| execute_query(&query, store, config));
+    log_outcome(line, &outcome);
+    outcome
+}
+
+// Parses a statement that's about to run, logging what it parsed to at -vv
+fn parse_statement(line: &str, config: &Config) -> Result<Query, RbdbError> {
+    let query = Query::build_query_with(line, &config.aliases)?;
+    log::debug!("{:?} parsed as {:?} with args {:?}", line.trim(), query.q_type, query.args);
+    Ok(query)
+}
+
+// At -v every statement is logged along with how it turned out
+fn log_outcome(line: &str, outcome: &Result<QueryResult, RbdbError>) {
+    match outcome {
+        Ok(result) => log::info!("{:?} -> {:?}", line.trim(), result),
+        Err(e) => log::info!("{:?} failed: {}", line.trim(), e),
     }
+}
 
-    #[test]
-    fn insert_query() {
-        let query = Query{ q_type: QueryType::Insert, key: "some_key".to_string(), value: Some("some_value".to_string()) };
-        let mut store: HashMap<String, String> = HashMap::new();
-        let query_result = process_query(&query, &mut store).unwrap();
-        let result_string = "SUCCESS: Inserted some_key:some_value into database".to_string();
-        assert_eq!(query_result, result_string);
+fn execute_query<S: Storage>(query: &Query, store: &mut S, config: &Config) -> Result<QueryResult, RbdbError> {
+    let result = process_query(query, store, config);
+    // A backend that couldn't persist the change says so now, rather than the query quietly succeeding
+    store.flush()?;
+    result
+}
+
+// Prints the outcome of a query the way the REPL and batch mode both show it, in whichever format config picks
+fn report<W: Write>(out: &mut W, outcome: &Result<QueryResult, RbdbError>, config: &Config) -> io::Result<()> {
+    match outcome {
+        // Misses are reported alongside other problems on stderr, everything else is a real result
+        Ok(r @ QueryResult::NotFound(_)) => eprintln!("{r}"),
+        Ok(r) => {
+            // Something like KEYS on an empty store has nothing to show, and a bare newline would just be noise
+            let text = config.formatter().format(r);
+            if !text.is_empty() {
+                writeln!(out, "{text}")?;
+            }
+        }
+        Err(e) => eprintln!("{}", config.formatter().format_error(&describe_error(e))),
     }
+    Ok(())
+}
 
-    #[test]
-    fn select_query() {
-        let mut store: HashMap<String, String> = HashMap::new();
-        store.insert("some_key".to_string(), "some_value".to_string());
-        let query = Query{ q_type: QueryType::Select, key: "some_key".to_string() , value: None};
-        let query_result = process_query(&query, &mut store).unwrap();
-        let result_string = "some_value".to_string();
-        assert_eq!(query_result, result_string);
+// Says whether the query itself was bad or it was fine but couldn't be carried out
+fn describe_error(e: &RbdbError) -> String {
+    match e {
+        RbdbError::NotEnoughArguments
+        | RbdbError::TooManyArguments(_)
+        | RbdbError::InvalidQueryType(_)
+        | RbdbError::UnterminatedQuote
+        | RbdbError::UnterminatedHeredoc(_)
+        | RbdbError::LineTooLong(_)
+        | RbdbError::InvalidKey(_)
+        | RbdbError::NoWildcard(_)
+        | RbdbError::InvalidPredicate(_) => {
+            format!("Query is malformed: {e}")
+        }
+        _ => format!("Query processing failed: {e}"),
     }
+}
 
-    #[test]
-    fn update_query() {
-        let mut store: HashMap<String, String> = HashMap::new();
-        store.insert("some_key".to_string(), "some_value".to_string());
-        let query = Query{ q_type: QueryType::Update, key: "some_key".to_string(), value: Some("new_value".to_string()) };
-        let query_result = process_query(&query, &mut store).unwrap();
-        let result_string = "SUCCESS: Updated some_key with new_value".to_string();
-        assert_eq!(query_result, result_string);
+fn process_query<S: Storage>(query: &Query, store: &mut S, config: &Config) -> Result<QueryResult, RbdbError> {
+    // Keys are normalized once up front, so none of the arms below can forget to do it
+    let scoped = query.scoped(config);
+    let query = &*scoped;
+    // Queries don't have to come from build_query, so the keys are checked again here
+    query.validate_keys(config)?;
+    // Refused before anything else happens, so a read-only store really is left alone
+    if config.read_only && query.q_type.is_mutating() {
+        return Err(RbdbError::ReadOnly);
     }
 
-    #[test]
-    fn delete_query() {
-        let mut store: HashMap<String, String> = HashMap::new();
-        store.insert("some_key".to_string(), "some_value".to_string());
-        let query = Query{ q_type: QueryType::Delete, key: "some_key".to_string(), value: None };
-        let query_result = process_query(&query, &mut store).unwrap();
-        let result_string = "SUCCESS: Deleted some_key".to_string();
-        assert_eq!(query_result, result_string);
-    }
-}
\ No newline at end of file
+    // Expired keys are dropped lazily, the first time a query would otherwise see them
+    let now = now_millis();
+    let key = query.key();
+    if query.q_type.scans_store() {
+        expire_all(store, now);
+    } else if query.q_type.is_key_arg(0) {
+        expire_key(store, key, now);
+    }
+
+    // Asking about the counters, or how to use a command, shouldn't show up in them
+    match query.q_type {
+        QueryType::Stats
+        | QueryType::Help
+        | QueryType::Debug
+        | QueryType::Ping
+        | QueryType::Use
+        | QueryType::DbList
+        | QueryType::Prefix
+        | QueryType::Subscribe
+        | QueryType::Watch => {}
+        q_type if q_type.is_mutating() => config.stats.record_write(),
+        _ => config.stats.record_read(),
+    }
+
+    let query_result = match query.q_type {
+        QueryType::Insert => {
+            // Refuse to clobber an existing entry, UPDATE is the only way to change a value
+            if store.contains_key(key) {
+                return Err(RbdbError::KeyExists(key.to_string()));
+            }
+
+            let value = query.value()?;
+            check_value_size(value, config)?;
+            store.insert_entry(key.to_string(), new_entry(value.as_str(), now, config));
+            QueryResult::Inserted { key: shown_key(key, config).to_string(), value: value.clone() }
+        }
+        QueryType::Select => {
+            match store.get(key) {
+                Some(value) => {
+                    config.stats.record_hit();
+                    QueryResult::Found { key: shown_key(key, config).to_string(), value: value.into_owned() }
+                }
+                None => {
+                    config.stats.record_miss();
+                    QueryResult::NotFound(shown_key(key, config).to_string())
+                }
+            }
+        }
+        QueryType::Update | QueryType::Replace => {
+            if !store.contains_key(key) {
+                return Err(RbdbError::KeyNotFound(key.to_string()));
+            }
+
+            let value = query.value()?;
+            check_value_size(value, config)?;
+            let previous = overwrite(store, key, value.as_str(), now, config);
+            // REPLACE answers with the value it replaced, for callers keeping a record of what changed
+            match query.q_type {
+                QueryType::Replace => QueryResult::Previous(previous),
+                _ => QueryResult::Updated { key: shown_key(key, config).to_string(), value: value.clone() },
+            }
+        }
+        QueryType::Upsert => {
+            let value = query.value()?;
+            check_value_size(value, config)?;
+            match overwrite(store, key, value.as_str(), now, config) {
+                Some(_) => QueryResult::Updated { key: shown_key(key, config).to_string(), value: value.clone() },
+                None => QueryResult::Inserted { key: shown_key(key, config).to_string(), value: value.clone() },
+            }
+        }
+        QueryType::SetNx => {
+            // Like INSERT, except an existing key is an answer rather than an error, which is what a lock wants
+            if store.contains_key(key) {
+                return Ok(QueryResult::Integer(0));
+            }
+            let value = query.value()?;
+            check_value_size(value, config)?;
+            store.insert_entry(key.to_string(), new_entry(value.as_str(), now, config));
+            QueryResult::Integer(1)
+        }
+        QueryType::Cas => {
+            // CAS key expected new. A missing key reads as "", the same as it does for APPEND and STRLEN
+            let expected = query.value()?;
+            let Some(new_value) = query.arg(2) else {
+                return Err(RbdbError::MissingValue);
+            };
+            if store.get(key).unwrap_or_default() != expected.as_str() {
+                return Ok(QueryResult::Bool(false));
+            }
+            check_value_size(new_value, config)?;
+            overwrite(store, key, new_value.as_str(), now, config);
+            QueryResult::Bool(true)
+        }
+        QueryType::Append => {
+            let value = query.value()?;
+            // Appending to a missing key creates it, like Redis does
+            let mut appended = store.get(key).unwrap_or_default().into_owned();
+            appended.push_str(value);
+            // It's the finished value that has to fit, not just the piece being added
+            check_value_size(&appended, config)?;
+            let len = appended.len();
+            overwrite(store, key, appended, now, config);
+            QueryResult::Length(len)
+        }
+        QueryType::Delete => {
+            match store.remove(key) {
+                Some(_) => QueryResult::Deleted(shown_key(key, config).to_string()),
+                None => return Err(RbdbError::KeyNotFound(key.to_string())),
+            }
+        }
+        QueryType::PopDelete => {
+            // DELETE, but the confirmation carries the value that went, for logs that need to say what was lost
+            match store.remove(key) {
+                Some(value) => QueryResult::Popped { key: shown_key(key, config).to_string(), value },
+                None => return Err(RbdbError::KeyNotFound(key.to_string())),
+            }
+        }
+        QueryType::GetDel => {
+            // One step under the store's lock, so no other client can read or change the value in between
+            QueryResult::Previous(store.remove(key))
+        }
+        QueryType::DelPattern => {
+            // A pattern without a wildcard is a single key, and DELETE already says that more plainly. Under
+            // PREFIX the pattern has been prefixed like any key, so only the session's own keys can match
+            if !key.contains(['*', '?']) {
+                return Err(RbdbError::NoWildcard(key.to_string()));
+            }
+            let matching: Vec<String> = store.keys().filter(|candidate| glob_match(key, candidate)).cloned().collect();
+            for candidate in &matching {
+                store.remove(candidate);
+            }
+            QueryResult::Count(matching.len())
+        }
+        QueryType::Rename => {
+            // The destination key is the second argument
+            let new_key = query.value()?;
+            if !store.contains_key(key) {
+                return Err(RbdbError::KeyNotFound(key.to_string()));
+            }
+            expire_key(store, new_key, now);
+            if store.contains_key(new_key) {
+                return Err(RbdbError::KeyExists(new_key.clone()));
+            }
+
+            // Move the whole entry so any expiry goes along with the value
+            if let Some(entry) = store.remove_entry(key) {
+                store.insert_entry(new_key.clone(), entry);
+            }
+            QueryResult::Renamed { from: shown_key(key, config).to_string(), to: shown_key(new_key, config).to_string() }
+        }
+        QueryType::Copy => {
+            let destination = query.value()?;
+            let replace = match query.arg(2) {
+                None => false,
+                Some(option) if option.eq_ignore_ascii_case("REPLACE") => true,
+                Some(option) => return Err(RbdbError::UnknownOption(option.clone())),
+            };
+
+            let Some(entry) = store.get_entry(key).cloned() else {
+                return Err(RbdbError::KeyNotFound(key.to_string()));
+            };
+            expire_key(store, destination, now);
+            if !replace && store.contains_key(destination) {
+                return Err(RbdbError::KeyExists(destination.clone()));
+            }
+
+            // The copy gets the same expiry as the original
+            store.insert_entry(destination.clone(), entry);
+            QueryResult::Copied { from: shown_key(key, config).to_string(), to: shown_key(destination, config).to_string() }
+        }
+        QueryType::Incr | QueryType::Decr | QueryType::IncrBy => {
+            let step: i64 = match query.q_type {
+                QueryType::Incr => 1,
+                QueryType::Decr => -1,
+                _ => {
+                    let amount = query.value()?;
+                    parse_integer(amount)?
+                }
+            };
+
+            // A missing key counts up (or down) from zero
+            let current: i64 = match store.get(key) {
+                Some(value) => parse_integer(&value)?,
+                None => 0,
+            };
+            let new_value = current.checked_add(step).ok_or_else(|| RbdbError::IntegerOverflow(key.to_string()))?;
+            check_value_size(new_value.to_string(), config)?;
+            overwrite(store, key, new_value.to_string(), now, config);
+            QueryResult::Integer(new_value)
+        }
+        QueryType::SetMax | QueryType::SetMin => {
+            let value = query.value()?;
+            parse_number(value)?;
+            let wanted = if query.q_type == QueryType::SetMax { Ordering::Greater } else { Ordering::Less };
+            let wins = match store.get(key) {
+                Some(current) => compare_numbers(value, &current)? == wanted,
+                None => true,
+            };
+            if !wins {
+                return Ok(QueryResult::Integer(0));
+            }
+            check_value_size(value, config)?;
+            overwrite(store, key, value.as_str(), now, config);
+            QueryResult::Integer(1)
+        }
+        QueryType::Expire => {
+            let seconds = parse_seconds(query.value()?)?;
+            match store.get_entry(key).cloned() {
+                Some(mut entry) => {
+                    entry.expires_at = Some(now.saturating_add(seconds.saturating_mul(1000)));
+                    store.insert_entry(key.to_string(), entry);
+                    QueryResult::Bool(true)
+                }
+                None => QueryResult::Bool(false),
+            }
+        }
+        QueryType::ExpireAt => {
+            let expires_at = parse_seconds(query.value()?)?.saturating_mul(1000);
+            match store.get_entry(key).cloned() {
+                // A time that has already gone by expires the key on the spot
+                Some(_) if expires_at <= now => {
+                    store.remove(key);
+                    QueryResult::Deleted(shown_key(key, config).to_string())
+                }
+                Some(mut entry) => {
+                    entry.expires_at = Some(expires_at);
+                    store.insert_entry(key.to_string(), entry);
+                    QueryResult::Bool(true)
+                }
+                None => QueryResult::Bool(false),
+            }
+        }
+        QueryType::Persist => {
+            match store.get_entry(key).cloned() {
+                Some(mut entry) if entry.expires_at.is_some() => {
+                    entry.expires_at = None;
+                    store.insert_entry(key.to_string(), entry);
+                    QueryResult::Integer(1)
+                }
+                _ => QueryResult::Integer(0),
+            }
+        }
+        QueryType::SetEx => {
+            // SETEX key seconds value, so the value is the third argument
+            let seconds = parse_seconds(query.value()?)?;
+            let Some(value) = query.arg(2) else {
+                return Err(RbdbError::MissingValue);
+            };
+            check_value_size(value, config)?;
+            let expires_at = Some(now.saturating_add(seconds.saturating_mul(1000)));
+            let entry = Entry { expires_at, ..replaced_entry(store, key, value.as_str(), now, config) };
+            match store.insert_entry(key.to_string(), entry) {
+                Some(_) => QueryResult::Updated { key: shown_key(key, config).to_string(), value: value.clone() },
+                None => QueryResult::Inserted { key: shown_key(key, config).to_string(), value: value.clone() },
+            }
+        }
+        QueryType::Ttl => {
+            // Like Redis: -2 when the key doesn't exist, -1 when it exists but never expires
+            match store.get_entry(key) {
+                None => QueryResult::Integer(-2),
+                Some(Entry { expires_at: None, .. }) => QueryResult::Integer(-1),
+                Some(Entry { expires_at: Some(expires_at), .. }) => {
+                    let remaining_ms = expires_at.saturating_sub(now);
+                    QueryResult::Integer(remaining_ms.div_ceil(1000) as i64)
+                }
+            }
+        }
+        QueryType::Exists => {
+            // Absence is a normal answer here, not an error
+            QueryResult::Bool(store.contains_key(key))
+        }
+        QueryType::Touch => {
+            // Only the access time changes. Missing keys are skipped, and the answer is how many weren't
+            let mut touched = 0;
+            for key in &query.args {
+                expire_key(store, key, now);
+                if mark_used(store, key, config) {
+                    touched += 1;
+                }
+            }
+            QueryResult::Count(touched)
+        }
+        QueryType::GetSet => {
+            let value = query.value()?;
+            check_value_size(value, config)?;
+            QueryResult::Previous(overwrite(store, key, value.as_str(), now, config))
+        }
+        QueryType::SetB => {
+            let encoded = query.value()?;
+            let bytes = base64::decode(encoded).ok_or_else(|| RbdbError::InvalidBase64(encoded.clone()))?;
+            check_value_size(&bytes, config)?;
+            match overwrite(store, key, bytes, now, config) {
+                Some(_) => QueryResult::Updated { key: shown_key(key, config).to_string(), value: encoded.clone() },
+                None => QueryResult::Inserted { key: shown_key(key, config).to_string(), value: encoded.clone() },
+            }
+        }
+        QueryType::GetB => match store.get_entry(key) {
+            Some(entry) => QueryResult::Found { key: shown_key(key, config).to_string(), value: base64::encode(&entry.value.as_bytes()) },
+            None => QueryResult::NotFound(shown_key(key, config).to_string()),
+        },
+        QueryType::History => {
+            // Nothing to show for a missing key, or when --history is off
+            let history = store.get_entry(key).map(|entry| entry.history.iter().cloned().collect()).unwrap_or_default();
+            QueryResult::History(history)
+        }
+        QueryType::StrLen => {
+            // Measured in bytes, so "é" counts as 2, and always of the original value even if it's stored compressed.
+            // A missing key is just an empty string
+            QueryResult::Length(store.get_entry(key).map_or(0, |entry| entry.value.len()))
+        }
+        QueryType::GetRange => {
+            let start = parse_integer(query.value()?)?;
+            let Some(end) = query.arg(2) else {
+                return Err(RbdbError::MissingValue);
+            };
+            let end = parse_integer(end)?;
+            let value = store.get(key).unwrap_or_default();
+            QueryResult::Value(byte_range(&value, start, end))
+        }
+        QueryType::Type => {
+            let type_name = store.get(key).map_or("none", |value| infer_type(&value));
+            QueryResult::Value(type_name.to_string())
+        }
+        QueryType::MGet => {
+            // Every requested key gets a slot in the answer, in the order it was asked for
+            let values = query
+                .args
+                .iter()
+                .map(|key| {
+                    expire_key(store, key, now);
+                    store.get(key).map(Cow::into_owned)
+                })
+                .collect();
+            QueryResult::Values(values)
+        }
+        QueryType::MSet => {
+            // Checked before anything is written, so a bad MSET leaves the store untouched
+            let args = &query.args;
+            if !args.len().is_multiple_of(2) {
+                return Err(RbdbError::UnpairedArguments);
+            }
+            for pair in args.chunks(2) {
+                check_value_size(&pair[1], config)?;
+            }
+            for pair in args.chunks(2) {
+                overwrite(store, &pair[0], pair[1].as_str(), now, config);
+            }
+            QueryResult::PairsSet(args.len() / 2)
+        }
+        QueryType::Keys => {
+            // A bare KEYS is the same as KEYS *. Under PREFIX it only looks inside the namespace and leaves the
+            // prefix off what it prints, unless ALL asks for every key in the store as it really is
+            let pattern = query.arg(0).map_or("*", String::as_str);
+            let prefix = if query.all_option(1)? { "" } else { config.key_prefix.as_deref().unwrap_or("") };
+            let mut keys: Vec<String> = store
+                .keys()
+                .filter_map(|key| key.strip_prefix(prefix))
+                .filter(|key| glob_match(pattern, key))
+                .map(str::to_string)
+                .collect();
+            keys.sort();
+            QueryResult::Keys(keys)
+        }
+        QueryType::SelectWhere => {
+            // Looks at every value, so it takes time in proportion to the whole store, not the number of matches.
+            // Under PREFIX only the session's own keys are searched, and listed without the prefix, like KEYS
+            let predicate = parse_predicate(key)?;
+            let prefix = config.key_prefix.as_deref().unwrap_or("");
+            let mut keys: Vec<String> = store
+                .keys()
+                .filter(|key| key.starts_with(prefix))
+                .filter(|key| store.get(key).is_some_and(|value| predicate.matches(&value)))
+                .map(|key| key[prefix.len()..].to_string())
+                .collect();
+            keys.sort();
+            QueryResult::Keys(keys)
+        }
+        QueryType::Scan => {
+            // Each batch carries on from the last key the previous one ended on. Under PREFIX the session's keys
+            // all sort together, so the scan starts at the prefix and stops at the first key without it
+            let prefix = config.key_prefix.as_deref().unwrap_or("");
+            let after = match key {
+                "0" => prefix.to_string(),
+                cursor => decode_cursor(cursor)?,
+            };
+            let count = query.count_option(1, SCAN_COUNT)?;
+            // One extra key says whether there's anything left after this batch
+            let mut batch: Vec<&String> = store
+                .keys_after(&after, count.saturating_add(1))
+                .into_iter()
+                .take_while(|key| key.starts_with(prefix))
+                .collect();
+            let more = batch.len() > count;
+            batch.truncate(count);
+            let cursor = match batch.last() {
+                Some(last) if more => encode_cursor(last),
+                _ => "0".to_string(),
+            };
+            let keys = batch.iter().map(|key| key[prefix.len()..].to_string()).collect();
+            QueryResult::Scan { cursor, keys }
+        }
+        QueryType::Range => {
+            let end = query.value()?;
+            if key > end.as_str() {
+                return Err(RbdbError::InvalidRange { start: key.to_string(), end: end.clone() });
+            }
+            // Both bounds already carry any PREFIX, so only the namespace is scanned and the prefix comes off again
+            let prefix = config.key_prefix.as_deref().unwrap_or("");
+            let entries = store
+                .range(key, end)
+                .into_iter()
+                .map(|(key, value)| (key.strip_prefix(prefix).unwrap_or(key).to_string(), value.into_owned()))
+                .collect();
+            QueryResult::Entries(entries)
+        }
+        QueryType::Dump => {
+            // Like KEYS, only the session's namespace, with the prefix left off
+            let prefix = config.key_prefix.as_deref().unwrap_or("");
+            let mut entries: Vec<(String, String)> = store
+                .keys()
+                .filter(|key| key.starts_with(prefix))
+                .filter_map(|key| store.get(key).map(|value| (key[prefix.len()..].to_string(), value.into_owned())))
+                .collect();
+            entries.sort();
+            QueryResult::Dump(entries)
+        }
+        QueryType::RandomKey => {
+            // Sorted first so a seeded generator picks the same key whatever order the backend iterates in. Under
+            // PREFIX it's picked from the session's namespace and shown without the prefix
+            let prefix = config.key_prefix.as_deref().unwrap_or("");
+            let mut keys: Vec<&String> = store.keys().filter(|key| key.starts_with(prefix)).collect();
+            keys.sort();
+            let key = match keys.len() {
+                0 => None,
+                len => Some(keys[config.rng.below(len as u64) as usize][prefix.len()..].to_string()),
+            };
+            QueryResult::Previous(key)
+        }
+        QueryType::Count => match (&config.key_prefix, query.all_option(0)?) {
+            (Some(prefix), false) => QueryResult::Count(store.keys().filter(|key| key.starts_with(prefix.as_str())).count()),
+            _ => QueryResult::Count(store.len()),
+        },
+        QueryType::MemUsage => {
+            // Just the one key, or every key in the session's namespace like COUNT
+            match query.arg(0) {
+                Some(key) => match store.get_entry(key) {
+                    Some(entry) => QueryResult::Length(memory_usage(key, entry)),
+                    None => QueryResult::NotFound(shown_key(key, config).to_string()),
+                },
+                None => {
+                    let prefix = config.key_prefix.as_deref().unwrap_or("");
+                    let total = store
+                        .keys()
+                        .filter(|key| key.starts_with(prefix))
+                        .filter_map(|key| store.get_entry(key).map(|entry| memory_usage(key, entry)))
+                        .sum();
+                    QueryResult::Length(total)
+                }
+            }
+        }
+        QueryType::Clear => match &config.key_prefix {
+            // Under PREFIX only the session's own namespace is emptied, whatever else shares the store
+            Some(prefix) => {
+                let matching: Vec<String> = store.keys().filter(|key| key.starts_with(prefix.as_str())).cloned().collect();
+                for key in &matching {
+                    store.remove(key);
+                }
+                QueryResult::Cleared(matching.len())
+            }
+            None => {
+                let count = store.len();
+                store.clear();
+                QueryResult::Cleared(count)
+            }
+        },
+        QueryType::Save => {
+            let count = save_store(key, store)?;
+            QueryResult::Saved { path: key.to_string(), count }
+        }
+        QueryType::Load => {
+            let count = load_store(key, store, config)?;
+            QueryResult::Loaded { path: key.to_string(), count }
+        }
+        QueryType::Export => {
+            let count = match ExportFormat::from_path(key)? {
+                ExportFormat::Json => export_json(key, store)?,
+                ExportFormat::Csv => export_csv(key, store)?,
+            };
+            QueryResult::Exported { path: key.to_string(), count }
+        }
+        QueryType::Import => {
+            let count = match ExportFormat::from_path(key)? {
+                ExportFormat::Json => import_json(key, store, config)?,
+                ExportFormat::Csv => import_csv(key, store, config)?,
+            };
+            QueryResult::Imported { path: key.to_string(), count }
+        }
+        QueryType::Ingest => ingest_tsv(key, store, config)?,
+        // Never touches the store, so it answers even when there's nothing in it
+        QueryType::Ping => QueryResult::Value(query.arg(0).map_or("PONG", String::as_str).to_string()),
+        QueryType::Info => QueryResult::Info(vec![
+            ("version", env!("CARGO_PKG_VERSION").to_string()),
+            ("uptime_seconds", config.started_at.elapsed().as_secs().to_string()),
+            ("keys", store.len().to_string()),
+            ("backend", store.backend_name().to_string()),
+        ]),
+        QueryType::Stats => match query.arg(0) {
+            None => QueryResult::Stats(config.stats.snapshot()),
+            Some(option) if option.eq_ignore_ascii_case("RESET") => {
+                config.stats.reset();
+                QueryResult::StatsReset
+            }
+            Some(option) => return Err(RbdbError::UnknownOption(option.clone())),
+        },
+        QueryType::Help => {
+            let topic = query.arg(0).map(String::as_str);
+            QueryResult::Help(help_text(topic)?)
+        }
+        QueryType::Debug => QueryResult::Parsed(describe_parse(query.key(), config)),
+        // A Session handles these itself before the query ever gets here
+        QueryType::Begin
+        | QueryType::Commit
+        | QueryType::Rollback
+        | QueryType::Undo
+        | QueryType::Use
+        | QueryType::DbList
+        | QueryType::Prefix
+        | QueryType::Subscribe
+        | QueryType::Watch => return Err(RbdbError::NoSession),
+    };
+
+    // Under --max-keys a SELECT and every write count as using the keys they name, and a write that leaves
+    // the store over the limit pushes out whichever keys the eviction policy picks
+    if let Some(limit) = config.max_keys
+        && (query.q_type == QueryType::Select || query.q_type.is_mutating())
+    {
+        for (index, key) in query.args.iter().enumerate() {
+            if query.q_type.is_key_arg(index) {
+                mark_used(store, key, config);
+            }
+        }
+        config.stats.record_evictions(eviction::evict(store, limit, config.eviction));
+    }
+
+    Ok(query_result)
+}
+
+// Sets key to value, first putting the value it replaces at the front of the key's history when
+// config.history asks for one. Only the value changes, an existing key keeps its expiry, access time and
+// history, and a missing one is created with new_entry. Returns the replaced value, if there was one
+fn overwrite<S: Storage>(store: &mut S, key: &str, value: impl Into<Value>, now: u64, config: &Config) -> Option<String> {
+    let entry = replaced_entry(store, key, value, now, config);
+    store.insert_entry(key.to_string(), entry).map(|previous| previous.value.into_string())
+}
+
+// The entry overwrite stores for key, for writes like SETEX that change more than the value
+fn replaced_entry<S: Storage>(store: &S, key: &str, value: impl Into<Value>, now: u64, config: &Config) -> Entry {
+    match store.get_entry(key) {
+        Some(previous) => {
+            let mut entry = previous.clone();
+            if config.history > 0 {
+                entry.history.push_front(previous.value.as_str().into_owned());
+                entry.history.truncate(config.history);
+            }
+            Entry { value: value.into(), ..entry }
+        }
+        None => new_entry(value, now, config),
+    }
+}
+
+// The entry for a key that didn't exist, whichever command creates it. It expires after config.default_ttl
+// if there is one
+fn new_entry(value: impl Into<Value>, now: u64, config: &Config) -> Entry {
+    let expires_at = config.default_ttl.map(|seconds| now.saturating_add(seconds.saturating_mul(1000)));
+    Entry { value: value.into(), expires_at, ..Entry::new(String::new()) }
+}
+
+// A rough allowance for what every entry costs besides the bytes of its key and value: the String, Value and
+// VecDeque headers, the expiry and access times, and the map's own bookkeeping
+const ENTRY_OVERHEAD: usize = 96;
+
+// Approximately how many bytes key and its entry take up in memory, for MEMUSAGE. Any values kept for HISTORY
+// count too
+fn memory_usage(key: &str, entry: &Entry) -> usize {
+    ENTRY_OVERHEAD + key.len() + entry.value.stored_len() + entry.history.iter().map(String::len).sum::<usize>()
+}
+
+// Under PREFIX a key is shown the way the session named it, without the prefix
+fn shown_key<'a>(key: &'a str, config: &Config) -> &'a str {
+    config.key_prefix.as_deref().and_then(|prefix| key.strip_prefix(prefix)).unwrap_or(key)
+}
+
+// What DEBUG shows for a line: the query it parses to, with its keys as they'd reach the store, or why it
+// doesn't parse
+fn describe_parse(line: &str, config: &Config) -> String {
+    match Query::build_query_with(line, &config.aliases) {
+        Ok(query) => {
+            let query = query.scoped(config);
+            let key = match query.arg(0) {
+                Some(key) if query.q_type.is_key_arg(0) => format!("{key:?}"),
+                _ => "(none)".to_string(),
+            };
+            format!("type: {:?}\nkey: {}\nargs: {:?}", query.q_type, key, query.args)
+        }
+        Err(e) => format!("error: {}", describe_error(&e)),
+    }
+}
+
+// Records that key was just used, leaving its value alone. False if there's no such key
+fn mark_used<S: Storage>(store: &mut S, key: &str, config: &Config) -> bool {
+    match store.get_entry(key).cloned() {
+        Some(mut entry) => {
+            entry.accessed_at = Some(config.access_clock.tick());
+            store.insert_entry(key.to_string(), entry);
+            true
+        }
+        None => false,
+    }
+}
+
+// The interchange formats EXPORT and IMPORT understand, chosen by the file's extension
+enum ExportFormat {
+    Json,
+    Csv,
+}
+
+impl ExportFormat {
+    fn from_path(path: &str) -> Result<Self, RbdbError> {
+        let extension = Path::new(path).extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("json") => Ok(ExportFormat::Json),
+            Some("csv") => Ok(ExportFormat::Csv),
+            _ => Err(RbdbError::UnsupportedFormat(path.to_string())),
+        }
+    }
+}
+
+// Writes the whole store to path as a single JSON object of {"key": "value", ...}
+pub fn export_json<S: Storage>(path: &str, store: &S) -> Result<usize, RbdbError> {
+    let mut object = serde_json::Map::new();
+    for key in store.keys() {
+        if let Some(value) = store.get(key) {
+            object.insert(key.clone(), serde_json::Value::String(value.into_owned()));
+        }
+    }
+    let contents = serde_json::to_string_pretty(&object).map_err(|e| RbdbError::InvalidImport(e.to_string()))?;
+    fs::write(path, contents)?;
+
+    Ok(object.len())
+}
+
+// Merges the JSON object in the file at path into the store. Keys that aren't in the file are left alone, and
+// ones that are get their new value the way UPDATE would. As with LOAD, the file is fully validated before
+// anything is written
+pub fn import_json<S: Storage>(path: &str, store: &mut S, config: &Config) -> Result<usize, RbdbError> {
+    let contents = fs::read_to_string(path)?;
+    let parsed: serde_json::Value = serde_json::from_str(&contents).map_err(|e| RbdbError::InvalidImport(e.to_string()))?;
+    let serde_json::Value::Object(object) = parsed else {
+        return Err(RbdbError::InvalidImport("top level of the file must be a JSON object".to_string()));
+    };
+
+    let mut entries: Vec<(String, String)> = Vec::new();
+    for (key, value) in object {
+        match value {
+            serde_json::Value::String(value) => entries.push((key, value)),
+            _ => return Err(RbdbError::InvalidImport(format!("value for {} is not a string", key))),
+        }
+    }
+
+    check_entries(entries.iter().map(|(key, value)| (key, value)), config)?;
+    let count = entries.len();
+    let now = now_millis();
+    for (key, value) in entries {
+        overwrite(store, &key, value, now, config);
+    }
+
+    Ok(count)
+}
+
+// Writes the whole store to path as CSV, one key,value row per entry in key order
+pub fn export_csv<S: Storage>(path: &str, store: &S) -> Result<usize, RbdbError> {
+    let mut keys: Vec<&String> = store.keys().collect();
+    keys.sort();
+
+    let mut contents = String::new();
+    for key in &keys {
+        if let Some(value) = store.get(key) {
+            contents.push_str(&format!("{},{}\n", csv::escape_field(key), csv::escape_field(&value)));
+        }
+    }
+    fs::write(path, contents)?;
+
+    Ok(keys.len())
+}
+
+// Merges the key,value rows of the CSV file at path into the store, the same way import_json does
+pub fn import_csv<S: Storage>(path: &str, store: &mut S, config: &Config) -> Result<usize, RbdbError> {
+    let contents = fs::read_to_string(path)?;
+    let rows = csv::parse(&contents).map_err(|line| RbdbError::MalformedFile { path: path.to_string(), line })?;
+
+    let mut entries: Vec<(String, String)> = Vec::new();
+    for (line, fields) in rows {
+        let Ok([key, value]) = <[String; 2]>::try_from(fields) else {
+            return Err(RbdbError::MalformedFile { path: path.to_string(), line });
+        };
+        entries.push((key, value));
+    }
+
+    check_entries(entries.iter().map(|(key, value)| (key, value)), config)?;
+    let count = entries.len();
+    let now = now_millis();
+    for (key, value) in entries {
+        overwrite(store, &key, value, now, config);
+    }
+
+    Ok(count)
+}
+
+// Inserts the key<TAB>value lines of the file at path the way INSERT would, so keys that already exist keep
+// their values. Fields are escaped the same way as DUMP's. A line INSERT would refuse outright is skipped and its
+// number reported, or under config.strict fails the whole file before anything is written
+fn ingest_tsv<S: Storage>(path: &str, store: &mut S, config: &Config) -> Result<QueryResult, RbdbError> {
+    let contents = fs::read_to_string(path)?;
+
+    let mut entries: Vec<(String, String)> = Vec::new();
+    let mut malformed = Vec::new();
+    for (line_number, line) in contents.lines().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        let entry = line
+            .split_once('\t')
+            .and_then(|(key, value)| Some((unescape_dump_field(key)?, unescape_dump_field(value)?)))
+            .filter(|(key, value)| validate_key(key, config).is_ok() && check_value_size(value, config).is_ok());
+        match entry {
+            Some(entry) => entries.push(entry),
+            None if config.strict => return Err(RbdbError::MalformedFile { path: path.to_string(), line: line_number + 1 }),
+            None => malformed.push(line_number + 1),
+        }
+    }
+
+    let (mut inserted, mut skipped) = (0, 0);
+    let now = now_millis();
+    for (key, value) in entries {
+        if store.contains_key(&key) {
+            skipped += 1;
+        } else {
+            store.insert_entry(key, new_entry(value, now, config));
+            inserted += 1;
+        }
+    }
+
+    Ok(QueryResult::Ingested { path: path.to_string(), inserted, skipped, malformed })
+}
+
+// What SELECTWHERE compares each value against
+#[derive(Debug, PartialEq)]
+enum ValuePredicate {
+    // value=text: the whole value is text
+    Equals(String),
+    // value~text: text appears somewhere in the value
+    Contains(String),
+}
+
+impl ValuePredicate {
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            ValuePredicate::Equals(text) => value == text,
+            ValuePredicate::Contains(text) => value.contains(text.as_str()),
+        }
+    }
+}
+
+// Reads value=text or value~text. The text may be empty, so value= finds the keys set to ""
+fn parse_predicate(predicate: &str) -> Result<ValuePredicate, RbdbError> {
+    let Some(rest) = predicate.strip_prefix("value") else {
+        return Err(RbdbError::InvalidPredicate(predicate.to_string()));
+    };
+    if let Some(text) = rest.strip_prefix('=') {
+        Ok(ValuePredicate::Equals(text.to_string()))
+    } else if let Some(text) = rest.strip_prefix('~') {
+        Ok(ValuePredicate::Contains(text.to_string()))
+    } else {
+        Err(RbdbError::InvalidPredicate(predicate.to_string()))
+    }
+}
+
+// Matches key against a glob pattern, where * matches any run of characters (including none)
+// and ? matches exactly one character. Everything else must match literally
+fn glob_match(pattern: &str, key: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let key: Vec<char> = key.chars().collect();
+    let (mut p, mut k) = (0, 0);
+
+    // Where the most recent * sits in the pattern, and how far into the key it has consumed so far
+    let mut star: Option<(usize, usize)> = None;
+
+    while k < key.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == key[k]) {
+            p += 1;
+            k += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, k));
+            p += 1;
+        } else if let Some((star_p, star_k)) = star {
+            // Backtrack and let the last * swallow one more character
+            p = star_p + 1;
+            k = star_k + 1;
+            star = Some((star_p, star_k + 1));
+        } else {
+            return false;
+        }
+    }
+
+    // Any trailing *s can match the empty string
+    while p < pattern.len() && pattern[p] == '*' {
+        p += 1;
+    }
+
+    p == pattern.len()
+}
+
+// Milliseconds since the Unix epoch, the clock entry expiry is measured against
+fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+// Removes key if it has passed its expiry, so it looks exactly like a key that was never there
+fn expire_key<S: Storage>(store: &mut S, key: &str, now: u64) {
+    if store.get_entry(key).is_some_and(|entry| entry.is_expired(now)) {
+        store.remove_entry(key);
+    }
+}
+
+// Removes every expired entry, returning how many were dropped
+fn expire_all<S: Storage>(store: &mut S, now: u64) -> usize {
+    let expired: Vec<String> = store
+        .keys()
+        .filter(|key| store.get_entry(key).is_some_and(|entry| entry.is_expired(now)))
+        .cloned()
+        .collect();
+    for key in &expired {
+        store.remove_entry(key);
+    }
+    expired.len()
+}
+
+// Keys can't be empty, and with config.reject_control_keys they can't contain control characters either,
+// since those are invisible when printed and break line-based files like SAVE's
+pub fn validate_key(key: &str, config: &Config) -> Result<(), RbdbError> {
+    if key.is_empty() || (config.reject_control_keys && key.chars().any(char::is_control)) {
+        return Err(RbdbError::InvalidKey(key.to_string()));
+    }
+    Ok(())
+}
+
+// The entries a file is about to add to the store, checked the way they would be if typed at the prompt. Called
+// before anything is written, so a file with one bad key or oversized value leaves the store as it was
+fn check_entries<'a>(entries: impl IntoIterator<Item = (&'a String, &'a String)>, config: &Config) -> Result<(), RbdbError> {
+    for (key, value) in entries {
+        validate_key(key, config)?;
+        check_value_size(value, config)?;
+    }
+    Ok(())
+}
+
+// Every write goes through here first, so a value over config.max_value_bytes never reaches the store
+fn check_value_size(value: impl AsRef<[u8]>, config: &Config) -> Result<(), RbdbError> {
+    let value = value.as_ref();
+    match config.max_value_bytes {
+        Some(limit) if value.len() > limit => Err(RbdbError::ValueTooLarge { size: value.len(), limit }),
+        _ => Ok(()),
+    }
+}
+
+// The bytes of value from start to end inclusive, the way GETRANGE counts them: negative offsets count back
+// from the end, and anything past either end is pulled back in rather than being an error. A range that cuts
+// a character in half gets a replacement character in its place
+fn byte_range(value: &str, start: i64, end: i64) -> String {
+    let len = value.len() as i64;
+    let from_end = |offset: i64| if offset < 0 { len + offset } else { offset };
+    let start = from_end(start).max(0);
+    let end = from_end(end).min(len - 1);
+    if start > end {
+        return String::new();
+    }
+    String::from_utf8_lossy(&value.as_bytes()[start as usize..=end as usize]).into_owned()
+}
+
+// The one place that decides what counts as an integer, so INCR and TYPE always agree
+fn parse_integer(value: &str) -> Result<i64, RbdbError> {
+    value.parse().map_err(|_| RbdbError::NotAnInteger(value.to_string()))
+}
+
+// Any finite number, integer or not, the way SETMAX and SETMIN read their values
+fn parse_number(value: &str) -> Result<f64, RbdbError> {
+    value.parse().ok().filter(|n: &f64| n.is_finite()).ok_or_else(|| RbdbError::NotANumber(value.to_string()))
+}
+
+// Compares two numbers, exactly when both are integers, since large ones can round to the same float
+fn compare_numbers(a: &str, b: &str) -> Result<Ordering, RbdbError> {
+    if let (Ok(a), Ok(b)) = (parse_integer(a), parse_integer(b)) {
+        return Ok(a.cmp(&b));
+    }
+    Ok(parse_number(a)?.total_cmp(&parse_number(b)?))
+}
+
+// What a stored string looks like: "int" if INCR could work on it, "float" for other finite numbers, and
+// "string" for everything else
+fn infer_type(value: &str) -> &'static str {
+    if parse_integer(value).is_ok() {
+        "int"
+    } else if parse_number(value).is_ok() {
+        "float"
+    } else {
+        "string"
+    }
+}
+
+// How many keys SCAN returns at a time when it isn't given a COUNT
+const SCAN_COUNT: usize = 10;
+
+// SCAN's cursors are the last key a batch ended on, written as hex so that no key can be mistaken for the
+// 0 that starts and ends a scan
+fn encode_cursor(key: &str) -> String {
+    key.bytes().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_cursor(cursor: &str) -> Result<String, RbdbError> {
+    let invalid = || RbdbError::InvalidCursor(cursor.to_string());
+    if !cursor.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    let bytes = (0..cursor.len())
+        .step_by(2)
+        .map(|i| cursor.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(invalid)?;
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+fn parse_seconds(seconds: &str) -> Result<u64, RbdbError> {
+    seconds.parse().map_err(|_| RbdbError::NotAnInteger(seconds.to_string()))
+}
+
+// Marks the last line of a saved file, which holds the CRC-32 of everything above it
+const CHECKSUM_PREFIX: &str = "#crc32:";
+
+// The first line of a saved file whose keys and values are escaped the way DUMP escapes them. Files from before
+// there was escaping don't have it, and are read as they are
+const ESCAPED_HEADER: &str = "#rbdb:escaped\n";
+
+// Writes every entry in the store to the file at path, one "key\tvalue" per line, followed by a checksum line
+pub fn save_store<S: Storage>(path: &str, store: &S) -> Result<usize, RbdbError> {
+    // In key order like KEYS and DUMP, so the file doesn't change from one save to the next unless the data does
+    let mut keys: Vec<&String> = store.keys().collect();
+    keys.sort();
+
+    // Escaped, so a tab or line break inside a key or value can't be mistaken for the end of one
+    let mut contents = String::from(ESCAPED_HEADER);
+    for key in keys {
+        if let Some(value) = store.get(key) {
+            contents.push_str(&format!("{}\t{}\n", escape_dump_field(key), escape_dump_field(&value)));
+        }
+    }
+    let checksum = crc32::crc32(contents.as_bytes());
+    contents.push_str(&format!("{CHECKSUM_PREFIX}{checksum:08x}\n"));
+    write_atomically(path, &contents)?;
+
+    Ok(store.len())
+}
+
+// Writes contents to a temporary file next to path and renames it into place once it's safely on disk.
+// The rename replaces the old file in one step, so a crash partway through leaves either the old file or the
+// new one, never half of each
+fn write_atomically(path: &str, contents: &str) -> io::Result<()> {
+    let target = Path::new(path);
+    let file_name = target.file_name().and_then(|name| name.to_str()).unwrap_or("rbdb");
+    let temp = target.with_file_name(format!(".{file_name}.tmp"));
+
+    let result = (|| {
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp, target)
+    })();
+
+    // Don't leave a half-written temporary file behind when something went wrong
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+// Replaces the contents of the store with the entries read from the file at path.
+// The file is fully parsed and checked before the store is touched, so a bad file leaves the store as it was
+pub fn load_store<S: Storage>(path: &str, store: &mut S, config: &Config) -> Result<usize, RbdbError> {
+    let contents = fs::read_to_string(path)?;
+    let (body, checksum) = split_checksum(&contents);
+
+    // Files written by hand, or by older versions, have no checksum and are taken as they are
+    if let Some(checksum) = checksum
+        && u32::from_str_radix(checksum, 16).ok() != Some(crc32::crc32(body.as_bytes()))
+    {
+        return Err(RbdbError::CorruptData(path.to_string()));
+    }
+
+    let (body, escaped, first_line) = match body.strip_prefix(ESCAPED_HEADER) {
+        Some(entries) => (entries, true, 2),
+        None => (body, false, 1),
+    };
+    let mut loaded: HashMap<String, String> = HashMap::new();
+    for (line_number, line) in body.lines().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        let entry = match line.split_once('\t') {
+            Some((key, value)) if escaped => unescape_dump_field(key).zip(unescape_dump_field(value)),
+            Some((key, value)) => Some((key.to_string(), value.to_string())),
+            None => None,
+        };
+        match entry {
+            Some((key, value)) => {
+                loaded.insert(key, value);
+            }
+            None => return Err(RbdbError::MalformedFile { path: path.to_string(), line: line_number + first_line }),
+        }
+    }
+
+    check_entries(&loaded, config)?;
+    let count = loaded.len();
+    store.clear();
+    for (key, value) in loaded {
+        store.insert(key, value);
+    }
+
+    Ok(count)
+}
+
+// Separates a saved file's entries from the checksum on its last line, if it has one
+fn split_checksum(contents: &str) -> (&str, Option<&str>) {
+    let trimmed = contents.strip_suffix('\n').unwrap_or(contents);
+    let last_line = trimmed.rfind('\n').map_or(0, |i| i + 1);
+    match trimmed[last_line..].strip_prefix(CHECKSUM_PREFIX) {
+        Some(checksum) => (&contents[..last_line], Some(checksum)),
+        None => (contents, None),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum QueryType {
+    Insert,
+    Select,
+    Update,
+    Replace,
+    Upsert,
+    SetNx,
+    GetSet,
+    SetB,
+    GetB,
+    History,
+    Cas,
+    Append,
+    Delete,
+    PopDelete,
+    GetDel,
+    DelPattern,
+    Rename,
+    Copy,
+    Incr,
+    Decr,
+    IncrBy,
+    SetMax,
+    SetMin,
+    Expire,
+    ExpireAt,
+    Persist,
+    SetEx,
+    Ttl,
+    Exists,
+    Touch,
+    StrLen,
+    MemUsage,
+    GetRange,
+    Type,
+    MGet,
+    MSet,
+    Save,
+    Load,
+    Export,
+    Import,
+    Ingest,
+    Keys,
+    SelectWhere,
+    Scan,
+    Range,
+    Dump,
+    RandomKey,
+    Count,
+    Clear,
+    Begin,
+    Commit,
+    Rollback,
+    Undo,
+    Watch,
+    Use,
+    DbList,
+    Prefix,
+    Subscribe,
+    Stats,
+    Info,
+    Ping,
+    Debug,
+    Help,
+}
+
+impl QueryType {
+    // How many arguments must follow the keyword. Whole-store commands like KEYS are valid with none at all
+    fn min_args(&self) -> usize {
+        match self {
+            QueryType::Keys
+            | QueryType::Count
+            | QueryType::MemUsage
+            | QueryType::Clear
+            | QueryType::Prefix
+            | QueryType::Help
+            | QueryType::Dump
+            | QueryType::RandomKey
+            | QueryType::Begin
+            | QueryType::Commit
+            | QueryType::Rollback
+            | QueryType::Undo
+            | QueryType::DbList
+            | QueryType::Stats
+            | QueryType::Info
+            | QueryType::Ping
+            | QueryType::Debug => 0,
+            QueryType::Rename
+            | QueryType::Copy
+            | QueryType::IncrBy
+            | QueryType::SetMax
+            | QueryType::SetMin
+            | QueryType::Range
+            | QueryType::Expire
+            | QueryType::ExpireAt
+            | QueryType::MSet
+            | QueryType::SetNx
+            | QueryType::GetSet
+            | QueryType::SetB => 2,
+            QueryType::SetEx | QueryType::Cas | QueryType::GetRange => 3,
+            _ => 1,
+        }
+    }
+
+    // How many arguments the keyword can take before the extras must be a mistake
+    fn max_args(&self) -> usize {
+        match self {
+            QueryType::Clear
+            | QueryType::Dump
+            | QueryType::RandomKey
+            | QueryType::Begin
+            | QueryType::Commit
+            | QueryType::Rollback
+            | QueryType::Undo
+            | QueryType::DbList
+            | QueryType::Info => 0,
+            QueryType::Insert
+            | QueryType::Update
+            | QueryType::Replace
+            | QueryType::Upsert
+            | QueryType::SetNx
+            | QueryType::GetSet
+            | QueryType::SetB
+            | QueryType::Append
+            | QueryType::Rename
+            | QueryType::IncrBy
+            | QueryType::SetMax
+            | QueryType::SetMin
+            | QueryType::Expire
+            | QueryType::ExpireAt
+            | QueryType::Keys
+            | QueryType::Range => 2,
+            QueryType::SetEx | QueryType::Copy | QueryType::Cas | QueryType::GetRange | QueryType::Scan => 3,
+            QueryType::MGet | QueryType::MSet | QueryType::Touch | QueryType::Watch => usize::MAX,
+            _ => 1,
+        }
+    }
+
+    // Whether the argument at index names a key, as opposed to a value, a number or a path
+    fn is_key_arg(&self, index: usize) -> bool {
+        match self {
+            QueryType::Save | QueryType::Load | QueryType::Export | QueryType::Import | QueryType::Ingest => false,
+            QueryType::Help => false,
+            QueryType::Stats | QueryType::Info | QueryType::Ping | QueryType::Debug => false,
+            QueryType::Count | QueryType::Clear | QueryType::Dump | QueryType::RandomKey | QueryType::Scan => false,
+            QueryType::SelectWhere => false,
+            QueryType::Begin | QueryType::Commit | QueryType::Rollback => false,
+            QueryType::Use | QueryType::DbList | QueryType::Prefix | QueryType::Subscribe => false,
+            // Every argument is a key, or for RANGE something compared against keys
+            QueryType::Rename | QueryType::MGet | QueryType::Touch | QueryType::Range | QueryType::Watch => true,
+            QueryType::MSet => index.is_multiple_of(2),
+            QueryType::Copy => index < 2,
+            _ => index == 0,
+        }
+    }
+
+    // Commands that look at every entry rather than a single key
+    // SAVE, LOAD and the rest name a path on the machine rbdb runs on, which a --serve client may not be trusted with
+    fn uses_files(&self) -> bool {
+        matches!(self, QueryType::Save | QueryType::Load | QueryType::Export | QueryType::Import | QueryType::Ingest)
+    }
+
+    fn scans_store(&self) -> bool {
+        matches!(
+            self,
+            QueryType::Keys
+                | QueryType::Scan
+                | QueryType::SelectWhere
+                | QueryType::DelPattern
+                | QueryType::Range
+                | QueryType::Dump
+                | QueryType::RandomKey
+                | QueryType::Count
+                | QueryType::MemUsage
+                | QueryType::Clear
+                | QueryType::Info
+                | QueryType::Save
+                | QueryType::Export
+        )
+    }
+
+    // Commands that can change what's in the store, whether or not this particular run ends up doing so.
+    // Read-only mode and STATS both go by this. There's deliberately no catch-all arm, so a new command
+    // can't be added without deciding which side it falls on
+    fn is_mutating(&self) -> bool {
+        match self {
+            QueryType::Insert
+            | QueryType::Update
+            | QueryType::Replace
+            | QueryType::Upsert
+            | QueryType::SetNx
+            | QueryType::GetSet
+            | QueryType::SetB
+            | QueryType::Cas
+            | QueryType::Append
+            | QueryType::Delete
+            | QueryType::PopDelete
+            | QueryType::GetDel
+            | QueryType::DelPattern
+            | QueryType::Rename
+            | QueryType::Copy
+            | QueryType::Incr
+            | QueryType::Decr
+            | QueryType::IncrBy
+            | QueryType::SetMax
+            | QueryType::SetMin
+            | QueryType::Expire
+            | QueryType::ExpireAt
+            | QueryType::Persist
+            | QueryType::SetEx
+            | QueryType::MSet
+            | QueryType::Load
+            | QueryType::Import
+            | QueryType::Ingest
+            | QueryType::Clear
+            | QueryType::Undo => true,
+            // SAVE and EXPORT write a file, and TOUCH an access time, but they all leave the values as they were
+            QueryType::Select
+            | QueryType::GetB
+            | QueryType::History
+            | QueryType::Touch
+            | QueryType::Ttl
+            | QueryType::Exists
+            | QueryType::StrLen
+            | QueryType::MemUsage
+            | QueryType::GetRange
+            | QueryType::Type
+            | QueryType::MGet
+            | QueryType::Save
+            | QueryType::Export
+            | QueryType::Keys
+            | QueryType::SelectWhere
+            | QueryType::Scan
+            | QueryType::Range
+            | QueryType::Dump
+            | QueryType::RandomKey
+            | QueryType::Count
+            | QueryType::Stats
+            | QueryType::Info
+            | QueryType::Ping
+            | QueryType::Debug
+            | QueryType::Help => false,
+            // The writes made inside a transaction are checked one by one as they're made
+            QueryType::Begin | QueryType::Commit | QueryType::Rollback | QueryType::Watch => false,
+            // Switching databases or prefixes only changes what the session points at
+            QueryType::Use | QueryType::DbList | QueryType::Prefix => false,
+            QueryType::Subscribe => false,
+        }
+    }
+}
+
+// One row of the command table: the keyword, what it parses to, and what HELP says about it
+struct Command {
+    name: &'static str,
+    aliases: &'static [&'static str],
+    q_type: QueryType,
+    usage: &'static str,
+    help: &'static str,
+}
+
+impl Command {
+    fn usage_line(&self) -> String {
+        format!("{:<28}{}", self.usage, self.help)
+    }
+}
+
+// Every command the parser understands. build_query, HELP and tab completion all read from here, so a new
+// command only needs a row here (and an arm in process_query)
+const COMMANDS: &[Command] = &[
+    Command { name: "INSERT", aliases: &["SET"], q_type: QueryType::Insert, usage: "INSERT key value", help: "Add a new entry, failing if the key exists" },
+    Command { name: "SELECT", aliases: &["GET"], q_type: QueryType::Select, usage: "SELECT key", help: "Print the value stored at key" },
+    Command { name: "UPDATE", aliases: &[], q_type: QueryType::Update, usage: "UPDATE key value", help: "Change the value of an existing entry" },
+    Command { name: "REPLACE", aliases: &[], q_type: QueryType::Replace, usage: "REPLACE key value", help: "Change the value of an existing entry and print what it was" },
+    Command { name: "UPSERT", aliases: &["PUT"], q_type: QueryType::Upsert, usage: "UPSERT key value", help: "Set key to value whether or not it exists" },
+    Command { name: "SETNX", aliases: &[], q_type: QueryType::SetNx, usage: "SETNX key value", help: "Set key only if it doesn't exist, printing 1 if it did and 0 if not" },
+    Command { name: "GETSET", aliases: &[], q_type: QueryType::GetSet, usage: "GETSET key value", help: "Set key to value and print what it was before" },
+    Command { name: "SETB", aliases: &[], q_type: QueryType::SetB, usage: "SETB key base64", help: "Set key to the bytes encoded in base64" },
+    Command { name: "GETB", aliases: &[], q_type: QueryType::GetB, usage: "GETB key", help: "Print the value of key as base64" },
+    Command { name: "HISTORY", aliases: &[], q_type: QueryType::History, usage: "HISTORY key", help: "Print the values key held before, newest first" },
+    Command { name: "CAS", aliases: &[], q_type: QueryType::Cas, usage: "CAS key expected new", help: "Set key to new only if it currently holds expected" },
+    Command { name: "APPEND", aliases: &[], q_type: QueryType::Append, usage: "APPEND key value", help: "Append to the value at key and print its new length" },
+    Command { name: "DELETE", aliases: &["DEL"], q_type: QueryType::Delete, usage: "DELETE key", help: "Remove the entry at key" },
+    Command { name: "POPDELETE", aliases: &[], q_type: QueryType::PopDelete, usage: "POPDELETE key", help: "Remove the entry at key and print what its value was" },
+    Command { name: "GETDEL", aliases: &[], q_type: QueryType::GetDel, usage: "GETDEL key", help: "Print the value at key and remove it, or (nil)" },
+    Command { name: "DELPATTERN", aliases: &[], q_type: QueryType::DelPattern, usage: "DELPATTERN pattern", help: "Remove every key matching a glob pattern and print how many went" },
+    Command { name: "RENAME", aliases: &[], q_type: QueryType::Rename, usage: "RENAME key new_key", help: "Move an entry to a key that isn't taken" },
+    Command { name: "COPY", aliases: &[], q_type: QueryType::Copy, usage: "COPY key new_key [REPLACE]", help: "Copy an entry, overwriting new_key only with REPLACE" },
+    Command { name: "INCR", aliases: &[], q_type: QueryType::Incr, usage: "INCR key", help: "Add one to the integer at key" },
+    Command { name: "DECR", aliases: &[], q_type: QueryType::Decr, usage: "DECR key", help: "Subtract one from the integer at key" },
+    Command { name: "INCRBY", aliases: &[], q_type: QueryType::IncrBy, usage: "INCRBY key amount", help: "Add amount to the integer at key" },
+    Command { name: "SETMAX", aliases: &[], q_type: QueryType::SetMax, usage: "SETMAX key number", help: "Set key only if number is greater than its value, printing 1 if it was set" },
+    Command { name: "SETMIN", aliases: &[], q_type: QueryType::SetMin, usage: "SETMIN key number", help: "Set key only if number is less than its value, printing 1 if it was set" },
+    Command { name: "EXPIRE", aliases: &[], q_type: QueryType::Expire, usage: "EXPIRE key seconds", help: "Make key expire after a number of seconds" },
+    Command { name: "EXPIREAT", aliases: &[], q_type: QueryType::ExpireAt, usage: "EXPIREAT key unix_seconds", help: "Make key expire at a Unix time, in seconds" },
+    Command { name: "PERSIST", aliases: &[], q_type: QueryType::Persist, usage: "PERSIST key", help: "Remove key's expiry, printing 1 if it had one" },
+    Command { name: "SETEX", aliases: &[], q_type: QueryType::SetEx, usage: "SETEX key seconds value", help: "Set key to value with an expiry" },
+    Command { name: "TTL", aliases: &[], q_type: QueryType::Ttl, usage: "TTL key", help: "Print the seconds left before key expires" },
+    Command { name: "EXISTS", aliases: &[], q_type: QueryType::Exists, usage: "EXISTS key", help: "Print whether key is in the store" },
+    Command { name: "TOUCH", aliases: &[], q_type: QueryType::Touch, usage: "TOUCH key [key ...]", help: "Mark keys as just used and print how many exist" },
+    Command { name: "STRLEN", aliases: &[], q_type: QueryType::StrLen, usage: "STRLEN key", help: "Print the length of the value in bytes" },
+    Command { name: "MEMUSAGE", aliases: &[], q_type: QueryType::MemUsage, usage: "MEMUSAGE [key]", help: "Print roughly how many bytes key, or the whole store, takes up" },
+    Command { name: "GETRANGE", aliases: &[], q_type: QueryType::GetRange, usage: "GETRANGE key start end", help: "Print the bytes of the value from start to end" },
+    Command { name: "TYPE", aliases: &[], q_type: QueryType::Type, usage: "TYPE key", help: "Print whether the value looks like an int, float or string" },
+    Command { name: "MGET", aliases: &[], q_type: QueryType::MGet, usage: "MGET key [key ...]", help: "Print the value of each key, or (nil)" },
+    Command { name: "MSET", aliases: &[], q_type: QueryType::MSet, usage: "MSET key value [key value ...]", help: "Set several keys at once" },
+    Command { name: "SAVE", aliases: &[], q_type: QueryType::Save, usage: "SAVE path", help: "Write the whole store to a file" },
+    Command { name: "LOAD", aliases: &[], q_type: QueryType::Load, usage: "LOAD path", help: "Replace the store with the entries in a file" },
+    Command { name: "EXPORT", aliases: &[], q_type: QueryType::Export, usage: "EXPORT path", help: "Write the store as .json or .csv" },
+    Command { name: "IMPORT", aliases: &[], q_type: QueryType::Import, usage: "IMPORT path", help: "Merge a .json or .csv file into the store" },
+    Command { name: "INGEST", aliases: &[], q_type: QueryType::Ingest, usage: "INGEST path", help: "Insert the key<TAB>value lines of a file, skipping existing keys" },
+    Command { name: "KEYS", aliases: &[], q_type: QueryType::Keys, usage: "KEYS [pattern [ALL]]", help: "List the keys matching a glob pattern" },
+    Command { name: "SELECTWHERE", aliases: &[], q_type: QueryType::SelectWhere, usage: "SELECTWHERE value=text|value~text", help: "List the keys whose value is, or contains, text" },
+    Command { name: "SCAN", aliases: &[], q_type: QueryType::Scan, usage: "SCAN cursor [COUNT n]", help: "List keys a batch at a time, starting from cursor 0" },
+    Command { name: "RANGE", aliases: &[], q_type: QueryType::Range, usage: "RANGE start end", help: "Print the entries with keys between start and end" },
+    Command { name: "DUMP", aliases: &[], q_type: QueryType::Dump, usage: "DUMP", help: "Print every key and value, sorted by key" },
+    Command { name: "RANDOMKEY", aliases: &[], q_type: QueryType::RandomKey, usage: "RANDOMKEY", help: "Print a key picked at random, or (nil)" },
+    Command { name: "COUNT", aliases: &[], q_type: QueryType::Count, usage: "COUNT [ALL]", help: "Print the number of entries" },
+    Command { name: "CLEAR", aliases: &["FLUSH"], q_type: QueryType::Clear, usage: "CLEAR", help: "Remove every entry" },
+    Command { name: "BEGIN", aliases: &[], q_type: QueryType::Begin, usage: "BEGIN", help: "Start a transaction, holding back writes until COMMIT" },
+    Command { name: "COMMIT", aliases: &[], q_type: QueryType::Commit, usage: "COMMIT", help: "Apply every write made since BEGIN" },
+    Command { name: "ROLLBACK", aliases: &[], q_type: QueryType::Rollback, usage: "ROLLBACK", help: "Throw away every write made since BEGIN" },
+    Command { name: "UNDO", aliases: &[], q_type: QueryType::Undo, usage: "UNDO", help: "Reverse the most recent change made in this session" },
+    Command { name: "USE", aliases: &[], q_type: QueryType::Use, usage: "USE name", help: "Switch to another database, creating it if it's new" },
+    Command { name: "DBLIST", aliases: &[], q_type: QueryType::DbList, usage: "DBLIST", help: "List every database, in name order" },
+    Command { name: "PREFIX", aliases: &[], q_type: QueryType::Prefix, usage: "PREFIX [prefix]", help: "Put prefix in front of every key from now on, or stop" },
+    Command { name: "SUBSCRIBE", aliases: &[], q_type: QueryType::Subscribe, usage: "SUBSCRIBE pattern", help: "Get an EVENT line whenever a matching key changes" },
+    Command { name: "WATCH", aliases: &[], q_type: QueryType::Watch, usage: "WATCH key [key ...]", help: "Make the next COMMIT fail if any of the keys change first" },
+    Command { name: "STATS", aliases: &[], q_type: QueryType::Stats, usage: "STATS [RESET]", help: "Print the hit, miss, read and write counters, or zero them" },
+    Command { name: "PING", aliases: &[], q_type: QueryType::Ping, usage: "PING [message]", help: "Print PONG, or echo message back" },
+    Command { name: "DEBUG", aliases: &[], q_type: QueryType::Debug, usage: "DEBUG line", help: "Show how a line parses, without running it" },
+    Command { name: "INFO", aliases: &[], q_type: QueryType::Info, usage: "INFO", help: "Print the version, uptime, key count and backend" },
+    Command { name: "HELP", aliases: &[], q_type: QueryType::Help, usage: "HELP [command]", help: "List the commands, or show how to use one" },
+];
+
+// Looks a keyword up in the command table, ignoring case
+fn find_command(name: &str) -> Option<&'static Command> {
+    let name = name.to_uppercase();
+    COMMANDS.iter().find(|command| command.name == name || command.aliases.contains(&name.as_str()))
+}
+
+// Like find_command, but falls back to the user's own aliases. The table always wins, so an alias can't take
+// over a command's name
+fn resolve_command(name: &str, aliases: &HashMap<String, String>) -> Option<&'static Command> {
+    find_command(name).or_else(|| {
+        let (_, target) = aliases.iter().find(|(alias, _)| alias.eq_ignore_ascii_case(name))?;
+        find_command(target)
+    })
+}
+
+// The text HELP prints: every command with its arguments and a short description, or just one of them
+fn help_text(topic: Option<&str>) -> Result<String, RbdbError> {
+    match topic {
+        Some(name) => find_command(name)
+            .map(Command::usage_line)
+            .ok_or_else(|| RbdbError::InvalidQueryType(name.to_string())),
+        None => Ok(command_summary()),
+    }
+}
+
+// Every command's usage line, one per line, for HELP and rbdb --help
+pub fn command_summary() -> String {
+    COMMANDS.iter().map(Command::usage_line).collect::<Vec<String>>().join("\n")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Query {
+    q_type: QueryType,
+    // Every token after the keyword, in order. What each one means is up to the command
+    args: Vec<String>,
+}
+
+impl Query {
+    fn arg(&self, index: usize) -> Option<&String> {
+        self.args.get(index)
+    }
+
+    // Most commands start with a key. Whole-store commands may have no arguments at all, which reads as ""
+    fn key(&self) -> &str {
+        self.arg(0).map_or("", String::as_str)
+    }
+
+    // The second argument, which most commands that take one treat as the value
+    fn value(&self) -> Result<&String, RbdbError> {
+        self.arg(1).ok_or(RbdbError::MissingValue)
+    }
+
+    fn validate_keys(&self, config: &Config) -> Result<(), RbdbError> {
+        // KEYS patterns and RANGE bounds are compared against keys rather than stored, so anything goes
+        if matches!(self.q_type, QueryType::Keys | QueryType::Range) {
+            return Ok(());
+        }
+        for (index, arg) in self.args.iter().enumerate() {
+            if self.q_type.is_key_arg(index) {
+                validate_key(arg, config)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Whether the argument at index is ALL, which KEYS and COUNT take to look past any PREFIX at the whole store
+    fn all_option(&self, index: usize) -> Result<bool, RbdbError> {
+        match self.arg(index) {
+            None => Ok(false),
+            Some(option) if option.eq_ignore_ascii_case("ALL") => Ok(true),
+            Some(option) => Err(RbdbError::UnknownOption(option.clone())),
+        }
+    }
+
+    // The n in a COUNT n option starting at index, which has to be a positive number, or default without one
+    fn count_option(&self, index: usize, default: usize) -> Result<usize, RbdbError> {
+        match (self.arg(index), self.arg(index + 1)) {
+            (None, _) => Ok(default),
+            (Some(option), Some(n)) if option.eq_ignore_ascii_case("COUNT") => {
+                n.parse().ok().filter(|&n| n > 0).ok_or_else(|| RbdbError::NotAnInteger(n.clone()))
+            }
+            (Some(option), None) if option.eq_ignore_ascii_case("COUNT") => Err(RbdbError::MissingValue),
+            (Some(option), _) => Err(RbdbError::UnknownOption(option.clone())),
+        }
+    }
+
+    // A copy of the query with prefix put in front of every key argument. KEYS patterns are left alone,
+    // since KEYS applies the prefix itself
+    fn with_prefix(&self, prefix: &str) -> Query {
+        let args = self
+            .args
+            .iter()
+            .enumerate()
+            .map(|(index, arg)| {
+                if self.q_type != QueryType::Keys && self.q_type.is_key_arg(index) {
+                    format!("{prefix}{arg}")
+                } else {
+                    arg.clone()
+                }
+            })
+            .collect();
+        Query { q_type: self.q_type, args }
+    }
+
+    // The query with its keys as the store knows them: lowercased with --ci-keys, and under PREFIX in the
+    // session's namespace
+    fn scoped(&self, config: &Config) -> Cow<'_, Query> {
+        let mut query = Cow::Borrowed(self);
+        if config.case_insensitive_keys {
+            query = Cow::Owned(query.with_lowercase_keys());
+        }
+        if let Some(prefix) = &config.key_prefix {
+            query = Cow::Owned(query.with_prefix(prefix));
+        }
+        query
+    }
+
+    // A copy of the query with every key argument lowercased and everything else left as it was
+    fn with_lowercase_keys(&self) -> Query {
+        let args = self
+            .args
+            .iter()
+            .enumerate()
+            .map(|(index, arg)| if self.q_type.is_key_arg(index) { arg.to_lowercase() } else { arg.clone() })
+            .collect();
+        Query { q_type: self.q_type, args }
+    }
+}
+
+// Everything that can go wrong while parsing or processing a query
+#[derive(Debug)]
+pub enum RbdbError {
+    NotEnoughArguments,
+    TooManyArguments(String),
+    InvalidQueryType(String),
+    UnterminatedQuote,
+    UnterminatedHeredoc(String),
+    LineTooLong(usize),
+    InvalidKey(String),
+    MissingValue,
+    UnpairedArguments,
+    UnknownOption(String),
+    NoWildcard(String),
+    InvalidPredicate(String),
+    InvalidCursor(String),
+    ValueTooLarge { size: usize, limit: usize },
+    KeyNotFound(String),
+    KeyExists(String),
+    NotAnInteger(String),
+    NotANumber(String),
+    InvalidBase64(String),
+    IntegerOverflow(String),
+    InvalidRange { start: String, end: String },
+    MalformedFile { path: String, line: usize },
+    InvalidImport(String),
+    CorruptData(String),
+    UnsupportedFormat(String),
+    TransactionInProgress,
+    NoTransaction,
+    NothingToUndo,
+    NoSession,
+    WatchedKeyChanged(String),
+    NotServing,
+    FilesNotServed,
+    ReadOnly,
+    Io(io::Error),
+}
+
+impl fmt::Display for RbdbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RbdbError::NotEnoughArguments => write!(f, "Not enough arguments"),
+            RbdbError::TooManyArguments(command) => write!(f, "Too many arguments for {}", command),
+            RbdbError::InvalidQueryType(q_type) => write!(f, "Invalid query type: {}", q_type),
+            RbdbError::UnterminatedQuote => write!(f, "Unterminated quote"),
+            RbdbError::UnterminatedHeredoc(terminator) => write!(f, "Input ended before the {} line closing the value", terminator),
+            RbdbError::LineTooLong(limit) => write!(f, "Line is longer than the limit of {} bytes", limit),
+            RbdbError::InvalidKey(key) => write!(f, "Invalid key: {:?}", key),
+            RbdbError::MissingValue => write!(f, "Query requires a value, but none was provided"),
+            RbdbError::UnpairedArguments => write!(f, "Arguments must come in key value pairs"),
+            RbdbError::UnknownOption(option) => write!(f, "Unknown option: {}", option),
+            RbdbError::InvalidCursor(cursor) => write!(f, "Invalid cursor: {}, start a scan with 0", cursor),
+            RbdbError::NoWildcard(pattern) => write!(f, "Pattern {} has no wildcard, use DELETE to remove a single key", pattern),
+            RbdbError::InvalidPredicate(predicate) => write!(f, "Expected value=text or value~text, got {}", predicate),
+            RbdbError::ValueTooLarge { size, limit } => write!(f, "Value is {} bytes, over the limit of {}", size, limit),
+            RbdbError::KeyNotFound(key) => write!(f, "No entry found for key = {}", key),
+            RbdbError::KeyExists(key) => write!(f, "Key {} already exists. Use UPDATE query instead", key),
+            RbdbError::NotAnInteger(value) => write!(f, "Value is not an integer: {}", value),
+            RbdbError::NotANumber(value) => write!(f, "Value is not a number: {}", value),
+            RbdbError::InvalidBase64(value) => write!(f, "Value is not valid base64: {}", value),
+            RbdbError::IntegerOverflow(key) => write!(f, "Integer overflow while changing {}", key),
+            RbdbError::InvalidRange { start, end } => write!(f, "Invalid range: {} comes after {}", start, end),
+            RbdbError::MalformedFile { path, line } => write!(f, "Malformed entry on line {} of {}", line, path),
+            RbdbError::CorruptData(path) => write!(f, "Checksum mismatch in {}, the file is corrupt", path),
+            RbdbError::InvalidImport(reason) => write!(f, "Invalid import file: {}", reason),
+            RbdbError::UnsupportedFormat(path) => write!(f, "Can't tell the format of {}, expected a .json or .csv file", path),
+            RbdbError::TransactionInProgress => write!(f, "A transaction is already open, COMMIT or ROLLBACK it first"),
+            RbdbError::NoTransaction => write!(f, "No transaction is open, start one with BEGIN"),
+            RbdbError::NothingToUndo => write!(f, "Nothing to undo"),
+            RbdbError::NoSession => write!(f, "That command can only be used from a session"),
+            RbdbError::WatchedKeyChanged(key) => write!(f, "Transaction aborted, {} changed after WATCH", key),
+            RbdbError::NotServing => write!(f, "SUBSCRIBE only works over a --serve connection"),
+            RbdbError::FilesNotServed => write!(f, "Commands that read or write files need --serve-files over a --serve connection"),
+            RbdbError::ReadOnly => write!(f, "The database is read-only"),
+            RbdbError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for RbdbError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RbdbError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RbdbError {
+    fn from(e: io::Error) -> Self {
+        RbdbError::Io(e)
+    }
+}
+
+// The outcome of a successfully processed query. Presentation is left to the Display impl,
+// so callers that want the raw data can match on the variants instead
+#[derive(Debug, PartialEq)]
+pub enum QueryResult {
+    Inserted { key: String, value: String },
+    Updated { key: String, value: String },
+    Deleted(String),
+    Popped { key: String, value: String },
+    Renamed { from: String, to: String },
+    Copied { from: String, to: String },
+    // What SELECT found, shown as just the value
+    Found { key: String, value: String },
+    Value(String),
+    NotFound(String),
+    Integer(i64),
+    Length(usize),
+    Bool(bool),
+    Keys(Vec<String>),
+    // One slot per requested key, None where the key wasn't found
+    Values(Vec<Option<String>>),
+    // The earlier values of a key, newest first, shown one per line
+    History(Vec<String>),
+    // A single value that may not exist, like what GETSET overwrote or the key RANDOMKEY picked. Shown as (nil) when None
+    Previous(Option<String>),
+    Entries(Vec<(String, String)>),
+    // A batch of keys from SCAN and the cursor to pass back for the next one, 0 once there are no more.
+    // Shown as the cursor on the first line, then the keys
+    Scan { cursor: String, keys: Vec<String> },
+    // Every entry in key order, shown as key<TAB>value lines
+    Dump(Vec<(String, String)>),
+    Count(usize),
+    Cleared(usize),
+    PairsSet(usize),
+    Saved { path: String, count: usize },
+    Loaded { path: String, count: usize },
+    Exported { path: String, count: usize },
+    Imported { path: String, count: usize },
+    // malformed holds the line numbers that were skipped for not being a valid key<TAB>value
+    Ingested { path: String, inserted: usize, skipped: usize, malformed: Vec<usize> },
+    Began,
+    // How many keys the transaction wrote to, or would have
+    Committed(usize),
+    RolledBack(usize),
+    // The statement whose change UNDO reversed
+    Undone(String),
+    // The database USE switched to
+    Using(String),
+    // What PREFIX set, or None when it cleared it
+    PrefixSet(Option<String>),
+    // The pattern SUBSCRIBE started watching
+    Subscribed(String),
+    // The keys WATCH added
+    Watching(Vec<String>),
+    // Shown as name:value lines, one per counter
+    Stats(StatsSnapshot),
+    StatsReset,
+    // How DEBUG saw a line, already laid out as type, key and args lines or the parse error
+    Parsed(String),
+    // Facts about the running instance, shown as name:value lines in this order
+    Info(Vec<(&'static str, String)>),
+    Help(String),
+}
+
+impl fmt::Display for QueryResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryResult::Inserted { key, value } => write!(f, "SUCCESS: Inserted {}:{} into database", key, value),
+            QueryResult::Updated { key, value } => write!(f, "SUCCESS: Updated {} with {}", key, value),
+            QueryResult::Deleted(key) => write!(f, "SUCCESS: Deleted {}", key),
+            QueryResult::Popped { key, value } => write!(f, "SUCCESS: Deleted {}:{} from database", key, value),
+            QueryResult::Renamed { from, to } => write!(f, "SUCCESS: Renamed {} to {}", from, to),
+            QueryResult::Copied { from, to } => write!(f, "SUCCESS: Copied {} to {}", from, to),
+            QueryResult::Found { value, .. } => write!(f, "{}", value),
+            QueryResult::Value(value) => write!(f, "{}", value),
+            QueryResult::NotFound(key) => write!(f, "No entry found for key = {}", key),
+            QueryResult::Integer(n) => write!(f, "{}", n),
+            QueryResult::Length(len) => write!(f, "{}", len),
+            QueryResult::Bool(b) => write!(f, "{}", b),
+            QueryResult::Keys(keys) => write!(f, "{}", keys.join("\n")),
+            QueryResult::History(values) => write!(f, "{}", values.join("\n")),
+            QueryResult::Scan { cursor, keys } => {
+                write!(f, "{}", cursor)?;
+                keys.iter().try_for_each(|key| write!(f, "\n{}", key))
+            }
+            QueryResult::Previous(value) => write!(f, "{}", value.as_deref().unwrap_or("(nil)")),
+            QueryResult::Values(values) => {
+                let lines: Vec<&str> = values.iter().map(|value| value.as_deref().unwrap_or("(nil)")).collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            QueryResult::Entries(entries) => {
+                let lines: Vec<String> = entries.iter().map(|(key, value)| format!("{} {}", key, value)).collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            QueryResult::Dump(entries) => {
+                let lines: Vec<String> = entries
+                    .iter()
+                    .map(|(key, value)| format!("{}\t{}", escape_dump_field(key), escape_dump_field(value)))
+                    .collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            QueryResult::Count(count) => write!(f, "{}", count),
+            QueryResult::Cleared(count) => write!(f, "SUCCESS: Cleared {} entries", count),
+            QueryResult::PairsSet(count) => write!(f, "SUCCESS: Set {} keys", count),
+            QueryResult::Saved { path, count } => write!(f, "SUCCESS: Saved {} entries to {}", count, path),
+            QueryResult::Loaded { path, count } => write!(f, "SUCCESS: Loaded {} entries from {}", count, path),
+            QueryResult::Exported { path, count } => write!(f, "SUCCESS: Exported {} entries to {}", count, path),
+            QueryResult::Imported { path, count } => write!(f, "SUCCESS: Imported {} entries from {}", count, path),
+            QueryResult::Ingested { path, inserted, skipped, malformed } => {
+                write!(f, "SUCCESS: Ingested {} entries from {}, skipped {} existing keys", inserted, path, skipped)?;
+                if !malformed.is_empty() {
+                    let lines: Vec<String> = malformed.iter().map(usize::to_string).collect();
+                    write!(f, " and malformed lines {}", lines.join(", "))?;
+                }
+                Ok(())
+            }
+            QueryResult::Began => write!(f, "SUCCESS: Started a transaction"),
+            QueryResult::Committed(count) => write!(f, "SUCCESS: Committed changes to {} keys", count),
+            QueryResult::RolledBack(count) => write!(f, "SUCCESS: Rolled back changes to {} keys", count),
+            QueryResult::Undone(statement) => write!(f, "SUCCESS: Undid {}", statement),
+            QueryResult::Stats(stats) => write!(
+                f,
+                "hits:{}\nmisses:{}\nreads:{}\nwrites:{}\nevictions:{}",
+                stats.hits, stats.misses, stats.reads, stats.writes, stats.evictions
+            ),
+            QueryResult::Using(name) => write!(f, "SUCCESS: Using database {}", name),
+            QueryResult::PrefixSet(Some(prefix)) => write!(f, "SUCCESS: Keys are now prefixed with {}", prefix),
+            QueryResult::PrefixSet(None) => write!(f, "SUCCESS: Cleared the key prefix"),
+            QueryResult::Subscribed(pattern) => write!(f, "SUCCESS: Subscribed to {}", pattern),
+            QueryResult::Watching(keys) => write!(f, "SUCCESS: Watching {}", keys.join(", ")),
+            QueryResult::StatsReset => write!(f, "SUCCESS: Reset the statistics"),
+            QueryResult::Info(fields) => {
+                let lines: Vec<String> = fields.iter().map(|(name, value)| format!("{}:{}", name, value)).collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+            QueryResult::Help(text) | QueryResult::Parsed(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+// Backslash-escapes tabs, line breaks and backslashes, so each DUMP record stays on one line with exactly one tab
+fn escape_dump_field(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// The reverse of escape_dump_field. None if a backslash is followed by anything it wouldn't have written
+fn unescape_dump_field(field: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => unescaped.push('\\'),
+            't' => unescaped.push('\t'),
+            'n' => unescaped.push('\n'),
+            'r' => unescaped.push('\r'),
+            _ => return None,
+        }
+    }
+    Some(unescaped)
+}
+
+// Splits a line into tokens on whitespace, treating anything inside double quotes as part of a single token.
+// Inside quotes, \" is a literal quote and \\ is a literal backslash
+fn tokenize(input: &str) -> Result<Vec<String>, RbdbError> {
+    let mut tokens: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut in_token = false;
+    let mut in_quotes = false;
+    let mut chars = input.chars();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' => in_quotes = false,
+                '\\' => match chars.next() {
+                    Some(escaped @ ('"' | '\\')) => current.push(escaped),
+                    Some(other) => {
+                        current.push('\\');
+                        current.push(other);
+                    }
+                    None => break,
+                },
+                _ => current.push(c),
+            }
+        } else if c == '"' {
+            // An opening quote always starts a token, so "" produces an empty token
+            in_quotes = true;
+            in_token = true;
+        } else if c.is_ascii_whitespace() {
+            if in_token {
+                tokens.push(std::mem::take(&mut current));
+                in_token = false;
+            }
+        } else {
+            current.push(c);
+            in_token = true;
+        }
+    }
+
+    if in_quotes {
+        return Err(RbdbError::UnterminatedQuote);
+    }
+    if in_token {
+        tokens.push(current);
+    }
+
+    Ok(tokens)
+}
+
+impl Query {
+    // Parses with only the built-in keywords, which is all most tests need
+    #[cfg(test)]
+    fn build_query(input: &str) -> Result<Self, RbdbError> {
+        Query::build_query_with(input, &HashMap::new())
+    }
+
+    // Parses a line into a query, recognising the user's aliases from the config as well as the built-in keywords
+    fn build_query_with(input: &str, aliases: &HashMap<String, String>) -> Result<Self, RbdbError> {
+        // DEBUG takes the rest of the line untouched, since the whole point is to see how it gets tokenized
+        let trimmed = input.trim();
+        let keyword_end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+        if resolve_command(&trimmed[..keyword_end], aliases).is_some_and(|command| command.q_type == QueryType::Debug) {
+            return Ok(Query { q_type: QueryType::Debug, args: vec![trimmed[keyword_end..].trim_start().to_string()] });
+        }
+
+        let tokens = tokenize(input)?;
+
+        if tokens.is_empty() {
+            return Err(RbdbError::NotEnoughArguments);
+        }
+
+        // Convert the first token into a QueryType
+        let command = match resolve_command(&tokens[0], aliases) {
+            Some(command) => command,
+            None => return Err(RbdbError::InvalidQueryType(tokens[0].to_uppercase())),
+        };
+        let q_type = command.q_type;
+
+        // Commands that work on a single entry need at least a key after the keyword
+        if tokens.len() - 1 < q_type.min_args() {
+            return Err(RbdbError::NotEnoughArguments);
+        }
+
+        // Stray tokens are almost always a typo, so refuse them rather than quietly ignoring them
+        if tokens.len() - 1 > q_type.max_args() {
+            return Err(RbdbError::TooManyArguments(command.name.to_string()));
+        }
+
+        let query = Query { q_type, args: tokens[1..].to_vec() };
+        // Settings like reject_control_keys aren't known yet, process_query checks again with them
+        query.validate_keys(&Config::default())?;
+        Ok(query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn good_query() {
+        let query = Query::build_query("insert key value").unwrap();
+        assert_eq!(query.q_type, QueryType::Insert);
+        assert_eq!(query.key(), "key");
+        let value = query.value().unwrap();
+        assert_eq![value, "value"];
+    }
+
+    #[test]
+    fn bad_query() {
+        let query = Query::build_query("delete");
+        assert!(query.is_err());
+        let query_err = query.unwrap_err();
+        assert!(matches!(query_err, RbdbError::NotEnoughArguments));
+
+        let query_err = Query::build_query("frobnicate key").unwrap_err();
+        assert!(matches!(query_err, RbdbError::InvalidQueryType(q_type) if q_type == "FROBNICATE"));
+    }
+
+    #[test]
+    fn quoted_value_query() {
+        let query = Query::build_query(r#"INSERT greeting "hello world""#).unwrap();
+        assert_eq!(query.key(), "greeting");
+        assert_eq!(query.value().unwrap(), "hello world");
+    }
+
+    #[test]
+    fn escaped_quote_tokens() {
+        let tokens = tokenize(r#"INSERT quote "she said \"hi\"""#).unwrap();
+        assert_eq!(tokens, vec!["INSERT", "quote", r#"she said "hi""#]);
+        let tokens = tokenize(r#"INSERT empty """#).unwrap();
+        assert_eq!(tokens, vec!["INSERT", "empty", ""]);
+    }
+
+    #[test]
+    fn unterminated_quote() {
+        let query_err = Query::build_query(r#"INSERT greeting "hello world"#).unwrap_err();
+        assert!(matches!(query_err, RbdbError::UnterminatedQuote));
+    }
+
+    #[test]
+    fn insert_query() {
+        let query = Query{ q_type: QueryType::Insert, args: vec!["some_key".to_string(), "some_value".to_string()] };
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Inserted { key: "some_key".to_string(), value: "some_value".to_string() });
+        assert_eq!(query_result.to_string(), "SUCCESS: Inserted some_key:some_value into database");
+    }
+
+    #[test]
+    fn insert_existing_key() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let query = Query{ q_type: QueryType::Insert, args: vec!["some_key".to_string(), "first_value".to_string()] };
+        process_query(&query, &mut store, &Config::default()).unwrap();
+        let query = Query{ q_type: QueryType::Insert, args: vec!["some_key".to_string(), "second_value".to_string()] };
+        let query_err = process_query(&query, &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::KeyExists(key) if key == "some_key"));
+        assert_eq!(store.get("some_key").unwrap().value, "first_value");
+    }
+
+    #[test]
+    fn select_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("some_key".to_string(), Entry::new("some_value"));
+        let query = Query{ q_type: QueryType::Select, args: vec!["some_key".to_string()] };
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Found { key: "some_key".to_string(), value: "some_value".to_string() });
+
+        let query = Query{ q_type: QueryType::Select, args: vec!["missing_key".to_string()] };
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::NotFound("missing_key".to_string()));
+    }
+
+    #[test]
+    fn update_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("some_key".to_string(), Entry::new("some_value"));
+        let query = Query{ q_type: QueryType::Update, args: vec!["some_key".to_string(), "new_value".to_string()] };
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Updated { key: "some_key".to_string(), value: "new_value".to_string() });
+
+        let query = Query{ q_type: QueryType::Update, args: vec!["missing_key".to_string(), "new_value".to_string()] };
+        let query_err = process_query(&query, &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::KeyNotFound(key) if key == "missing_key"));
+
+        let query = Query{ q_type: QueryType::Update, args: vec!["some_key".to_string()] };
+        let query_err = process_query(&query, &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::MissingValue));
+    }
+
+    #[test]
+    fn replace_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("status".to_string(), Entry::new("draft"));
+        let query_result = execute_line("REPLACE status published", &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Previous(Some("draft".to_string())));
+        assert_eq!(query_result.to_string(), "draft");
+        assert_eq!(store.get("status").unwrap().value, "published");
+
+        // Like UPDATE, it won't create a key
+        let query_err = execute_line("REPLACE missing value", &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::KeyNotFound(key) if key == "missing"));
+        assert!(!store.contains_key("missing"));
+    }
+
+    #[test]
+    fn upsert_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let query = Query::build_query("UPSERT some_key first_value").unwrap();
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Inserted { key: "some_key".to_string(), value: "first_value".to_string() });
+
+        let query = Query::build_query("UPSERT some_key second_value").unwrap();
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Updated { key: "some_key".to_string(), value: "second_value".to_string() });
+        assert_eq!(store.get("some_key").unwrap().value, "second_value");
+
+        let query = Query::build_query("UPSERT some_key").unwrap();
+        let query_err = process_query(&query, &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::MissingValue));
+    }
+
+    #[test]
+    fn append_existing_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("log".to_string(), Entry::new("first"));
+        let query = Query::build_query(r#"APPEND log " second line""#).unwrap();
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Length(17));
+        assert_eq!(store.get("log").unwrap().value, "first second line");
+    }
+
+    #[test]
+    fn append_missing_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let query = Query::build_query("APPEND log hello").unwrap();
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Length(5));
+        assert_eq!(store.get("log").unwrap().value, "hello");
+    }
+
+    #[test]
+    fn delete_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("some_key".to_string(), Entry::new("some_value"));
+        let query = Query{ q_type: QueryType::Delete, args: vec!["some_key".to_string()] };
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Deleted("some_key".to_string()));
+    }
+
+    #[test]
+    fn popdelete_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("draft".to_string(), Entry::new("first try"));
+        let config = Config::default();
+
+        let query_result = execute_line("POPDELETE draft", &mut store, &config).unwrap();
+        assert_eq!(query_result, QueryResult::Popped { key: "draft".to_string(), value: "first try".to_string() });
+        assert_eq!(query_result.to_string(), "SUCCESS: Deleted draft:first try from database");
+        assert!(!store.contains_key("draft"));
+
+        // Unlike GETDEL, a missing key is an error, the same as for DELETE
+        let query_err = execute_line("POPDELETE draft", &mut store, &config).unwrap_err();
+        assert!(matches!(query_err, RbdbError::KeyNotFound(key) if key == "draft"));
+    }
+
+    #[test]
+    fn memusage_scales_with_value_length() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("short".to_string(), Entry::new("x".repeat(10)));
+        store.insert("long".to_string(), Entry::new("x".repeat(1010)));
+        let config = Config::default();
+        let usage = |line: &str, store: &mut HashMap<String, Entry>| match execute_line(line, store, &config).unwrap() {
+            QueryResult::Length(bytes) => bytes,
+            other => panic!("{line} gave {other:?}"),
+        };
+
+        let short = usage("MEMUSAGE short", &mut store);
+        let long = usage("MEMUSAGE long", &mut store);
+        assert_eq!(short, ENTRY_OVERHEAD + "short".len() + 10);
+        // Each extra byte of value is an extra byte used, and the key's length counts too
+        assert_eq!(long - short, 1000 - 1);
+        assert_eq!(usage("MEMUSAGE", &mut store), short + long);
+
+        store.insert("empty".to_string(), Entry::new(""));
+        assert_eq!(usage("MEMUSAGE empty", &mut store), ENTRY_OVERHEAD + "empty".len());
+        assert_eq!(usage("MEMUSAGE", &mut store), short + long + ENTRY_OVERHEAD + "empty".len());
+        assert_eq!(execute_line("MEMUSAGE missing", &mut store, &config).unwrap(), QueryResult::NotFound("missing".to_string()));
+
+        // A compressed value is counted at the size it's actually kept at
+        let compressed = Entry { value: Value::from("x".repeat(1010)).compressed(COMPRESS_THRESHOLD), ..Entry::new("") };
+        assert!(compressed.value.is_compressed());
+        store.insert("long".to_string(), compressed);
+        assert!(usage("MEMUSAGE long", &mut store) < long);
+    }
+
+    #[test]
+    fn getdel_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("job".to_string(), Entry::new("resize"));
+        let config = Config::default();
+
+        assert_eq!(execute_line("GETDEL job", &mut store, &config).unwrap(), QueryResult::Previous(Some("resize".to_string())));
+        assert!(!store.contains_key("job"));
+
+        let result = execute_line("GETDEL job", &mut store, &config).unwrap();
+        assert_eq!(result, QueryResult::Previous(None));
+        assert_eq!(result.to_string(), "(nil)");
+    }
+
+    #[test]
+    fn rename_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("old_key".to_string(), Entry::new("some_value"));
+        let query = Query::build_query("RENAME old_key new_key").unwrap();
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Renamed { from: "old_key".to_string(), to: "new_key".to_string() });
+        assert!(!store.contains_key("old_key"));
+        assert_eq!(store.get("new_key").unwrap().value, "some_value");
+
+        let query_err = Query::build_query("RENAME new_key").unwrap_err();
+        assert!(matches!(query_err, RbdbError::NotEnoughArguments));
+    }
+
+    #[test]
+    fn rename_missing_source() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let query = Query::build_query("RENAME old_key new_key").unwrap();
+        let query_err = process_query(&query, &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::KeyNotFound(key) if key == "old_key"));
+    }
+
+    #[test]
+    fn rename_existing_destination() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("old_key".to_string(), Entry::new("old_value"));
+        store.insert("new_key".to_string(), Entry::new("new_value"));
+        let query = Query::build_query("RENAME old_key new_key").unwrap();
+        let query_err = process_query(&query, &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::KeyExists(key) if key == "new_key"));
+        assert_eq!(store.get("old_key").unwrap().value, "old_value");
+        assert_eq!(store.get("new_key").unwrap().value, "new_value");
+    }
+
+    #[test]
+    fn incr_decr_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let query = Query::build_query("INCR visits").unwrap();
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Integer(1));
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Integer(2));
+        assert_eq!(store.get("visits").unwrap().value, "2");
+
+        let query = Query::build_query("DECR visits").unwrap();
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Integer(1));
+
+        let query = Query::build_query("INCRBY visits 10").unwrap();
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Integer(11));
+
+        let query = Query::build_query("INCRBY visits -20").unwrap();
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Integer(-9));
+
+        let query_err = Query::build_query("INCRBY visits").unwrap_err();
+        assert!(matches!(query_err, RbdbError::NotEnoughArguments));
+    }
+
+    #[test]
+    fn incr_non_integer() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("name".to_string(), Entry::new("alice"));
+        let query = Query::build_query("INCR name").unwrap();
+        let query_err = process_query(&query, &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::NotAnInteger(value) if value == "alice"));
+        assert_eq!(store.get("name").unwrap().value, "alice");
+
+        let query = Query::build_query("INCRBY counter many").unwrap();
+        let query_err = process_query(&query, &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::NotAnInteger(value) if value == "many"));
+    }
+
+    #[test]
+    fn setmax_and_setmin() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let config = Config::default();
+
+        // A missing key always takes the value
+        assert_eq!(execute_line("SETMAX peak 10", &mut store, &config).unwrap(), QueryResult::Integer(1));
+        assert_eq!(execute_line("SETMIN low 10", &mut store, &config).unwrap(), QueryResult::Integer(1));
+
+        // A greater value wins for SETMAX, a smaller one for SETMIN, and a tie changes nothing
+        assert_eq!(execute_line("SETMAX peak 9", &mut store, &config).unwrap(), QueryResult::Integer(0));
+        assert_eq!(execute_line("SETMAX peak 10", &mut store, &config).unwrap(), QueryResult::Integer(0));
+        assert_eq!(execute_line("SETMAX peak 11", &mut store, &config).unwrap(), QueryResult::Integer(1));
+        assert_eq!(store.get("peak").unwrap().value, "11");
+        assert_eq!(execute_line("SETMIN low 11", &mut store, &config).unwrap(), QueryResult::Integer(0));
+        assert_eq!(execute_line("SETMIN low -2.5", &mut store, &config).unwrap(), QueryResult::Integer(1));
+        assert_eq!(store.get("low").unwrap().value, "-2.5");
+
+        // Integers compare exactly, even where floats would round them to the same number
+        store.insert("big".to_string(), Entry::new("9007199254740992"));
+        assert_eq!(execute_line("SETMAX big 9007199254740993", &mut store, &config).unwrap(), QueryResult::Integer(1));
+
+        // Anything that isn't a number is an error, and the stored value is left alone
+        store.insert("name".to_string(), Entry::new("alice"));
+        let query_err = execute_line("SETMAX name 5", &mut store, &config).unwrap_err();
+        assert!(matches!(query_err, RbdbError::NotANumber(value) if value == "alice"));
+        assert_eq!(store.get("name").unwrap().value, "alice");
+        let query_err = execute_line("SETMIN fresh many", &mut store, &config).unwrap_err();
+        assert!(matches!(query_err, RbdbError::NotANumber(value) if value == "many"));
+        assert!(!store.contains_key("fresh"));
+    }
+
+    #[test]
+    fn incr_overflow() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("big".to_string(), Entry::new(i64::MAX.to_string()));
+        store.insert("small".to_string(), Entry::new(i64::MIN.to_string()));
+
+        let query = Query::build_query("INCR big").unwrap();
+        let query_err = process_query(&query, &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::IntegerOverflow(key) if key == "big"));
+        assert_eq!(store.get("big").unwrap().value, i64::MAX.to_string());
+
+        let query = Query::build_query("DECR small").unwrap();
+        let query_err = process_query(&query, &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::IntegerOverflow(key) if key == "small"));
+    }
+
+    #[test]
+    fn setex_and_ttl_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let query = Query::build_query("SETEX session 100 abc").unwrap();
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Inserted { key: "session".to_string(), value: "abc".to_string() });
+        let query = Query::build_query("TTL session").unwrap();
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Integer(100));
+
+        store.insert("forever".to_string(), Entry::new("value"));
+        let query = Query::build_query("TTL forever").unwrap();
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Integer(-1));
+
+        let query = Query::build_query("TTL missing").unwrap();
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Integer(-2));
+    }
+
+    #[test]
+    fn expire_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("some_key".to_string(), Entry::new("some_value"));
+        let query = Query::build_query("EXPIRE some_key 60").unwrap();
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Bool(true));
+        let query = Query::build_query("TTL some_key").unwrap();
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Integer(60));
+
+        let query = Query::build_query("EXPIRE missing_key 60").unwrap();
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Bool(false));
+
+        let query = Query::build_query("EXPIRE some_key soon").unwrap();
+        let query_err = process_query(&query, &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::NotAnInteger(value) if value == "soon"));
+    }
+
+    #[test]
+    fn expireat_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("future".to_string(), Entry::new("a"));
+        store.insert("past".to_string(), Entry::new("b"));
+
+        let in_an_hour = now_millis() / 1000 + 3600;
+        let query = Query::build_query(&format!("EXPIREAT future {in_an_hour}")).unwrap();
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Bool(true));
+        assert_eq!(store.get_entry("future").unwrap().expires_at, Some(in_an_hour * 1000));
+        let query = Query::build_query("TTL future").unwrap();
+        assert!(matches!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Integer(3599..=3600)));
+
+        let query = Query::build_query("EXPIREAT past 1000").unwrap();
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Deleted("past".to_string()));
+        assert!(!store.contains_key("past"));
+
+        let query = Query::build_query("EXPIREAT missing_key 1000").unwrap();
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Bool(false));
+    }
+
+    #[test]
+    fn persist_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("session".to_string(), Entry::new("abc"));
+        execute_line("EXPIRE session 60", &mut store, &Config::default()).unwrap();
+        assert_eq!(execute_line("TTL session", &mut store, &Config::default()).unwrap(), QueryResult::Integer(60));
+
+        assert_eq!(execute_line("PERSIST session", &mut store, &Config::default()).unwrap(), QueryResult::Integer(1));
+        assert_eq!(execute_line("TTL session", &mut store, &Config::default()).unwrap(), QueryResult::Integer(-1));
+        // Nothing left to remove, and nothing there at all
+        assert_eq!(execute_line("PERSIST session", &mut store, &Config::default()).unwrap(), QueryResult::Integer(0));
+        assert_eq!(execute_line("PERSIST missing", &mut store, &Config::default()).unwrap(), QueryResult::Integer(0));
+        assert_eq!(store.get("session").unwrap().value, "abc");
+    }
+
+    #[test]
+    fn default_ttl_applies_to_new_keys() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("kept".to_string(), Entry::new("old"));
+        let config = Config { default_ttl: Some(60), ..Config::default() };
+        // Whichever command creates the key
+        let lines = [
+            "INSERT a 1",
+            "UPSERT b 2",
+            "SETNX c 3",
+            "GETSET d 4",
+            "APPEND e 5",
+            "INCR f",
+            "MSET g 7 h 8",
+            "CAS i \"\" 9",
+            "SETMAX j 10",
+            "SETB k MTE=",
+        ];
+        for line in lines {
+            execute_line(line, &mut store, &config).unwrap();
+        }
+        for key in ["a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k"] {
+            assert_eq!(execute_line(&format!("TTL {key}"), &mut store, &config).unwrap(), QueryResult::Integer(60), "{key}");
+        }
+
+        // A key that was already there isn't given one, and one that had an expiry keeps it
+        execute_line("UPSERT kept new", &mut store, &config).unwrap();
+        assert_eq!(execute_line("TTL kept", &mut store, &config).unwrap(), QueryResult::Integer(-1));
+        execute_line("EXPIRE a 100", &mut store, &config).unwrap();
+        execute_line("UPSERT a z", &mut store, &config).unwrap();
+        assert_eq!(execute_line("TTL a", &mut store, &config).unwrap(), QueryResult::Integer(100));
+        assert_eq!(execute_line("SETEX l 5 3", &mut store, &config).unwrap().to_string(), "SUCCESS: Inserted l:3 into database");
+        assert_eq!(execute_line("TTL l", &mut store, &config).unwrap(), QueryResult::Integer(5));
+
+        // And keys that come from a file
+        let dir = std::env::temp_dir().join("rbdb_default_ttl_applies_to_new_keys");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let ingested = dir.join("ingested.tsv");
+        let imported = dir.join("imported.json");
+        fs::write(&ingested, "m\t1\n").unwrap();
+        fs::write(&imported, r#"{"n": "2"}"#).unwrap();
+        execute_line(&format!("INGEST {}", ingested.to_str().unwrap()), &mut store, &config).unwrap();
+        execute_line(&format!("IMPORT {}", imported.to_str().unwrap()), &mut store, &config).unwrap();
+        assert_eq!(execute_line("TTL m", &mut store, &config).unwrap(), QueryResult::Integer(60));
+        assert_eq!(execute_line("TTL n", &mut store, &config).unwrap(), QueryResult::Integer(60));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_keep_the_ttl() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let config = Config::default();
+        execute_line("SETEX log 100 a", &mut store, &config).unwrap();
+        execute_line("SETEX visits 100 1", &mut store, &config).unwrap();
+        let lines = ["APPEND log b", "CAS log ab abc", "UPDATE log abcd", "GETSET log abcde", "MSET log abcdef", "INCR visits", "DECR visits"];
+        for line in lines {
+            execute_line(line, &mut store, &config).unwrap();
+        }
+        assert_eq!(execute_line("TTL log", &mut store, &config).unwrap(), QueryResult::Integer(100));
+        assert_eq!(execute_line("TTL visits", &mut store, &config).unwrap(), QueryResult::Integer(100));
+        assert_eq!(store.get("log").unwrap().value, "abcdef");
+        assert_eq!(store.get("visits").unwrap().value, "1");
+    }
+
+    #[test]
+    fn expired_key_is_absent() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("stale".to_string(), Entry { expires_at: Some(1), ..Entry::new("old") });
+        store.insert("fresh".to_string(), Entry::new("new"));
+
+        let query = Query::build_query("EXISTS stale").unwrap();
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Bool(false));
+        let query = Query::build_query("SELECT stale").unwrap();
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::NotFound("stale".to_string()));
+        assert!(!store.contains_key("stale"));
+
+        // Whole-store commands never see expired entries either
+        store.insert("stale".to_string(), Entry { expires_at: Some(1), ..Entry::new("old") });
+        let query = Query::build_query("KEYS").unwrap();
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Keys(vec!["fresh".to_string()]));
+
+        // SETEX with zero seconds is expired as soon as it's written
+        let query = Query::build_query("SETEX brief 0 value").unwrap();
+        process_query(&query, &mut store, &Config::default()).unwrap();
+        let query = Query::build_query("SELECT brief").unwrap();
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::NotFound("brief".to_string()));
+    }
+
+    #[test]
+    fn exists_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("some_key".to_string(), Entry::new("some_value"));
+        let query = Query{ q_type: QueryType::Exists, args: vec!["some_key".to_string()] };
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Bool(true));
+
+        let query = Query{ q_type: QueryType::Exists, args: vec!["missing_key".to_string()] };
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Bool(false));
+    }
+
+    #[test]
+    fn keys_query() {
+        let query = Query::build_query("KEYS").unwrap();
+        assert_eq!(query.q_type, QueryType::Keys);
+
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Keys(vec![]));
+
+        store.insert("banana".to_string(), Entry::new("yellow"));
+        store.insert("cherry".to_string(), Entry::new("red"));
+        store.insert("apple".to_string(), Entry::new("green"));
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Keys(vec!["apple".to_string(), "banana".to_string(), "cherry".to_string()]));
+    }
+
+    #[test]
+    fn listings_are_sorted_whatever_the_backend() {
+        let path = std::env::temp_dir().join("rbdb_listings_are_sorted.db");
+        let path = path.to_str().unwrap().to_string();
+        let scrambled = ["kiwi", "apple", "mango", "banana", "cherry", "fig", "date", "lemon", "grape", "elderberry"];
+        let mut sorted = scrambled.map(str::to_string);
+        sorted.sort();
+
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        for key in scrambled {
+            store.insert(key.to_string(), Entry::new(key.len().to_string()));
+        }
+        let config = Config::default();
+        assert_eq!(execute_line("KEYS", &mut store, &config).unwrap(), QueryResult::Keys(sorted.to_vec()));
+        let expected: Vec<(String, String)> = sorted.iter().map(|key| (key.clone(), key.len().to_string())).collect();
+        assert_eq!(execute_line("DUMP", &mut store, &config).unwrap(), QueryResult::Dump(expected.clone()));
+
+        // SAVE writes the same order, so saving the same data twice gives the same file
+        execute_line(&format!("SAVE {path}"), &mut store, &config).unwrap();
+        let saved: Vec<String> = fs::read_to_string(&path).unwrap().lines().map(str::to_string).collect();
+        let lines: Vec<String> = expected.iter().map(|(key, value)| format!("{key}\t{value}")).collect();
+        // Between the header and the checksum
+        assert_eq!(saved[1..saved.len() - 1], lines[..]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn keys_pattern_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("user:1".to_string(), Entry::new("alice"));
+        store.insert("user:2".to_string(), Entry::new("bob"));
+        store.insert("session:1".to_string(), Entry::new("abc"));
+        let query = Query::build_query("KEYS user:*").unwrap();
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Keys(vec!["user:1".to_string(), "user:2".to_string()]));
+    }
+
+    #[test]
+    fn delpattern_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        for key in ["user:1", "user:2", "users", "session:1"] {
+            store.insert(key.to_string(), Entry::new("x"));
+        }
+        let query_result = execute_line("DELPATTERN user:*", &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Count(2));
+        let mut left: Vec<&String> = store.keys().collect();
+        left.sort();
+        assert_eq!(left, ["session:1", "users"]);
+
+        assert_eq!(execute_line("DELPATTERN nothing:*", &mut store, &Config::default()).unwrap(), QueryResult::Count(0));
+        // A bare key is refused rather than treated as DELETE
+        let query_err = execute_line("DELPATTERN users", &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::NoWildcard(pattern) if pattern == "users"));
+        assert!(store.contains_key("users"));
+    }
+
+    // Follows the cursors from 0 back to 0, returning every key seen along the way
+    fn scan_all<S: Storage>(store: &mut S, count: usize, config: &Config) -> Vec<String> {
+        let mut seen = Vec::new();
+        let mut cursor = "0".to_string();
+        loop {
+            let query_result = execute_line(&format!("SCAN {cursor} COUNT {count}"), store, config).unwrap();
+            let QueryResult::Scan { cursor: next, keys } = query_result else {
+                panic!("SCAN returned {query_result:?}");
+            };
+            assert!(keys.len() <= count);
+            seen.extend(keys);
+            if next == "0" {
+                return seen;
+            }
+            cursor = next;
+        }
+    }
+
+    #[test]
+    fn scan_visits_every_key_once() {
+        let mut hash: HashMap<String, Entry> = HashMap::new();
+        let mut btree: BTreeMap<String, Entry> = BTreeMap::new();
+        // Includes a key that looks like the cursor that ends a scan
+        for key in (0..25).map(|n| n.to_string()).chain(["user:x".to_string()]) {
+            hash.insert(key.clone(), Entry::new("v"));
+            btree.insert(key, Entry::new("v"));
+        }
+        let mut expected: Vec<String> = hash.keys().cloned().collect();
+        expected.sort();
+
+        for count in [1, 3, 10, 100] {
+            assert_eq!(scan_all(&mut hash, count, &Config::default()), expected);
+            assert_eq!(scan_all(&mut btree, count, &Config::default()), expected);
+        }
+
+        // Under PREFIX only the namespace is scanned, with the prefix left off
+        let config = Config { key_prefix: Some("user:".to_string()), ..Config::default() };
+        assert_eq!(scan_all(&mut btree, 1, &config), ["x"]);
+    }
+
+    #[test]
+    fn scan_rejects_bad_arguments() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("a".to_string(), Entry::new("1"));
+        let query_err = execute_line("SCAN nothex", &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::InvalidCursor(cursor) if cursor == "nothex"));
+        let query_err = execute_line("SCAN 0 COUNT 0", &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::NotAnInteger(n) if n == "0"));
+        let query_err = execute_line("SCAN 0 LIMIT 5", &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::UnknownOption(option) if option == "LIMIT"));
+        let query_result = execute_line("SCAN 0", &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result.to_string(), "0\na");
+    }
+
+    #[test]
+    fn setb_and_getb() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let config = Config::default();
+
+        // 0xFF 0xFE 0x00 0x80 is nowhere near valid UTF-8, and comes back byte for byte
+        let query_result = execute_line("SETB blob //4AgA==", &mut store, &config).unwrap();
+        assert_eq!(query_result, QueryResult::Inserted { key: "blob".to_string(), value: "//4AgA==".to_string() });
+        assert_eq!(store.get("blob").unwrap().value.as_bytes(), &[0xFF, 0xFE, 0x00, 0x80][..]);
+        let query_result = execute_line("GETB blob", &mut store, &config).unwrap();
+        assert_eq!(query_result, QueryResult::Found { key: "blob".to_string(), value: "//4AgA==".to_string() });
+        assert_eq!(execute_line("STRLEN blob", &mut store, &config).unwrap(), QueryResult::Length(4));
+
+        // Text values can be read as base64 too, and SETB replaces whatever was there
+        store.insert("text".to_string(), Entry::new("foobar"));
+        assert_eq!(execute_line("GETB text", &mut store, &config).unwrap().to_string(), "Zm9vYmFy");
+        let query_result = execute_line("SETB text Zm9v", &mut store, &config).unwrap();
+        assert_eq!(query_result, QueryResult::Updated { key: "text".to_string(), value: "Zm9v".to_string() });
+        assert_eq!(execute_line("SELECT text", &mut store, &config).unwrap().to_string(), "foo");
+        assert_eq!(execute_line("GETB missing", &mut store, &config).unwrap(), QueryResult::NotFound("missing".to_string()));
+
+        let query_err = execute_line("SETB blob not*base64", &mut store, &config).unwrap_err();
+        assert!(matches!(query_err, RbdbError::InvalidBase64(value) if value == "not*base64"));
+        assert_eq!(store.get("blob").unwrap().value.as_bytes(), &[0xFF, 0xFE, 0x00, 0x80][..]);
+    }
+
+    #[test]
+    fn history_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let config = Config { history: 3, ..Config::default() };
+        for line in ["INSERT color red", "UPDATE color green", "UPSERT color blue", "GETSET color cyan", "UPDATE color pink"] {
+            execute_line(line, &mut store, &config).unwrap();
+        }
+        // Newest first, and only the last three
+        let query_result = execute_line("HISTORY color", &mut store, &config).unwrap();
+        assert_eq!(query_result, QueryResult::History(vec!["cyan".to_string(), "blue".to_string(), "green".to_string()]));
+        assert_eq!(query_result.to_string(), "cyan\nblue\ngreen");
+        assert_eq!(execute_line("HISTORY missing", &mut store, &config).unwrap(), QueryResult::History(vec![]));
+
+        // Nothing is kept without --history
+        execute_line("UPSERT plain 1", &mut store, &Config::default()).unwrap();
+        execute_line("UPSERT plain 2", &mut store, &Config::default()).unwrap();
+        assert_eq!(execute_line("HISTORY plain", &mut store, &Config::default()).unwrap(), QueryResult::History(vec![]));
+    }
+
+    #[test]
+    fn import_keeps_the_history() {
+        let path = std::env::temp_dir().join("rbdb_import_keeps_the_history.json");
+        fs::write(&path, r#"{"k": "3"}"#).unwrap();
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let config = Config { history: 5, ..Config::default() };
+        execute_line("INSERT k 1", &mut store, &config).unwrap();
+        execute_line("UPDATE k 2", &mut store, &config).unwrap();
+        execute_line(&format!("IMPORT {}", path.to_str().unwrap()), &mut store, &config).unwrap();
+        assert_eq!(execute_line("HISTORY k", &mut store, &config).unwrap().to_string(), "2\n1");
+        assert_eq!(store.get("k").unwrap().value, "3");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn every_write_keeps_the_history() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let config = Config { history: 10, ..Config::default() };
+        let lines = ["INSERT k 1", "UPDATE k 2", "APPEND k 0", "INCR k", "INCRBY k 9", "CAS k 30 31", "MSET k 40", "SETEX k 60 50"];
+        for line in lines {
+            execute_line(line, &mut store, &config).unwrap();
+        }
+        let query_result = execute_line("HISTORY k", &mut store, &config).unwrap();
+        assert_eq!(query_result.to_string(), "40\n31\n30\n21\n20\n2\n1");
+        assert_eq!(store.get("k").unwrap().value, "50");
+    }
+
+    #[test]
+    fn debug_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let query_result = execute_line(r#"DEBUG insert greeting "hello \"world\"""#, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result.to_string(), r#"type: Insert
+key: "greeting"
+args: ["greeting", "hello \"world\""]"#);
+        // Nothing ran
+        assert!(store.is_empty());
+
+        let query_result = execute_line(r#"DEBUG INSERT greeting "hello"#, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result.to_string(), "error: Query is malformed: Unterminated quote");
+        let query_result = execute_line("debug KEYS", &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result.to_string(), "type: Keys\nkey: (none)\nargs: []");
+
+        // Keys are shown the way they'd reach the store
+        let config = Config { key_prefix: Some("app:".to_string()), case_insensitive_keys: true, ..Config::default() };
+        let query_result = execute_line("DEBUG SELECT User", &mut store, &config).unwrap();
+        assert_eq!(query_result.to_string(), "type: Select\nkey: \"app:user\"\nargs: [\"app:user\"]");
+    }
+
+    #[test]
+    fn glob_matching() {
+        assert!(glob_match("a?c", "abc"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(glob_match("*end", "the end"));
+        assert!(glob_match("*end", "end"));
+        assert!(!glob_match("*end", "ending"));
+        assert!(glob_match("literal", "literal"));
+        assert!(!glob_match("literal", "literally"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXXbYYc"));
+        assert!(!glob_match("a*b*c", "aXXbYY"));
+    }
+
+    #[test]
+    fn range_query() {
+        let mut store: BTreeMap<String, Entry> = BTreeMap::new();
+        for (key, value) in [("apple", "1"), ("kiwi", "2"), ("mango", "3"), ("melon", "4")] {
+            store.insert(key.to_string(), Entry::new(value));
+        }
+        let query = Query::build_query("RANGE a m").unwrap();
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Entries(vec![("apple".to_string(), "1".to_string()), ("kiwi".to_string(), "2".to_string())]));
+        assert_eq!(query_result.to_string(), "apple 1\nkiwi 2");
+
+        let query = Query::build_query("RANGE mango melon").unwrap();
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result.to_string(), "mango 3\nmelon 4");
+    }
+
+    #[test]
+    fn inverted_range_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let query = Query::build_query("RANGE m a").unwrap();
+        let query_err = process_query(&query, &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::InvalidRange { .. }));
+    }
+
+    #[test]
+    fn count_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let query = Query::build_query("COUNT").unwrap();
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap().to_string(), "0");
+
+        for input in ["INSERT a 1", "INSERT b 2", "INSERT c 3"] {
+            process_query(&Query::build_query(input).unwrap(), &mut store, &Config::default()).unwrap();
+        }
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Count(3));
+        assert_eq!(query_result.to_string(), "3");
+    }
+
+    #[test]
+    fn clear_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("a".to_string(), Entry::new("1"));
+        store.insert("b".to_string(), Entry::new("2"));
+        let query = Query::build_query("CLEAR").unwrap();
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Cleared(2));
+        assert_eq!(query_result.to_string(), "SUCCESS: Cleared 2 entries");
+        assert!(store.is_empty());
+
+        let query = Query::build_query("flush").unwrap();
+        assert_eq!(query.q_type, QueryType::Clear);
+    }
+
+    #[test]
+    fn save_and_load_query() {
+        let path = std::env::temp_dir().join("rbdb_save_and_load_query.db");
+        let path = path.to_str().unwrap().to_string();
+
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("first_key".to_string(), Entry::new("first_value"));
+        store.insert("second_key".to_string(), Entry::new("second_value"));
+        let query = Query{ q_type: QueryType::Save, args: vec![path.clone()] };
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Saved { path: path.clone(), count: 2 });
+
+        let mut loaded: HashMap<String, Entry> = HashMap::new();
+        loaded.insert("stale_key".to_string(), Entry::new("stale_value"));
+        let query = Query{ q_type: QueryType::Load, args: vec![path.clone()] };
+        let query_result = process_query(&query, &mut loaded, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Loaded { path: path.clone(), count: 2 });
+        assert_eq!(loaded, store);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn save_and_load_round_trip_any_text() {
+        let path = std::env::temp_dir().join("rbdb_save_and_load_round_trip_any_text.db");
+        let path = path.to_str().unwrap();
+
+        // The characters the format has to be careful with, along with some it doesn't
+        let alphabet = ['\t', '\n', '\r', '\\', 't', 'n', '#', ' ', '"', 'é', '日', '🦀', '\u{0}'];
+        let mut rng = rng::Rng::seeded(96);
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        for (key, value) in [("empty", ""), ("tab", "\t"), ("escaped looking", "\\n\\t\\\\"), ("crlf", "a\r\nb")] {
+            store.insert(key.to_string(), Entry::new(value));
+        }
+        store.insert("#crc32:00000000".to_string(), Entry::new("looks like a checksum"));
+        store.insert("#rbdb:escaped".to_string(), Entry::new("looks like the header"));
+        for i in 0..200 {
+            let mut random_text = |max_len| (0..rng.below(max_len)).map(|_| alphabet[rng.below(alphabet.len() as u64) as usize]).collect::<String>();
+            let key = format!("{i}{}", random_text(6));
+            store.insert(key, Entry::new(random_text(20)));
+        }
+
+        assert_eq!(save_store(path, &store).unwrap(), store.len());
+        let mut loaded: HashMap<String, Entry> = HashMap::new();
+        assert_eq!(load_store(path, &mut loaded, &Config::default()).unwrap(), store.len());
+        assert_eq!(loaded, store);
+        assert_eq!(loaded.get("empty").unwrap().value, "");
+
+        // Files from before values were escaped have no header, and are still read as they are
+        fs::write(path, "path\tC:\\new\\table\n").unwrap();
+        load_store(path, &mut loaded, &Config::default()).unwrap();
+        assert_eq!(loaded.get("path").unwrap().value, "C:\\new\\table");
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn save_leaves_no_temp_file() {
+        let dir = std::env::temp_dir().join("rbdb_save_leaves_no_temp_file");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("snapshot.db");
+        let path = path.to_str().unwrap();
+
+        // Saving over an existing snapshot replaces it completely
+        fs::write(path, "old_key\told_value\n").unwrap();
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("new_key".to_string(), Entry::new("new_value"));
+        save_store(path, &store).unwrap();
+
+        let mut loaded: HashMap<String, Entry> = HashMap::new();
+        load_store(path, &mut loaded, &Config::default()).unwrap();
+        assert_eq!(loaded, store);
+
+        let files: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(files, vec!["snapshot.db"]);
+
+        // A failed save reports the error and cleans up after itself too
+        let unwritable = dir.join("missing_dir").join("snapshot.db");
+        assert!(save_store(unwritable.to_str().unwrap(), &store).is_err());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_checks_the_checksum() {
+        let path = std::env::temp_dir().join("rbdb_load_checks_the_checksum.db");
+        let path = path.to_str().unwrap();
+
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("balance".to_string(), Entry::new("100"));
+        save_store(path, &store).unwrap();
+        assert!(fs::read_to_string(path).unwrap().lines().last().unwrap().starts_with("#crc32:"));
+
+        let mut loaded: HashMap<String, Entry> = HashMap::new();
+        assert_eq!(load_store(path, &mut loaded, &Config::default()).unwrap(), 1);
+        assert_eq!(loaded, store);
+
+        // Flip one digit of the value and the load is refused, leaving the store alone
+        let tampered = fs::read_to_string(path).unwrap().replace("100", "900");
+        fs::write(path, tampered).unwrap();
+        assert!(matches!(load_store(path, &mut loaded, &Config::default()), Err(RbdbError::CorruptData(_))));
+        assert_eq!(loaded.get("balance").unwrap().value, "100");
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn load_missing_file() {
+        let path = std::env::temp_dir().join("rbdb_load_missing_file.db");
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("some_key".to_string(), Entry::new("some_value"));
+        let query = Query{ q_type: QueryType::Load, args: vec![path.to_str().unwrap().to_string()] };
+        let query_err = process_query(&query, &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::Io(_)));
+        assert_eq!(store.get("some_key").unwrap().value, "some_value");
+    }
+
+    #[test]
+    fn export_and_import_json() {
+        let path = std::env::temp_dir().join("rbdb_export_and_import_json.json");
+        let path = path.to_str().unwrap().to_string();
+
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("greeting".to_string(), Entry::new("hello world"));
+        store.insert("quote".to_string(), Entry::new("she said \"hi\""));
+        let query = Query{ q_type: QueryType::Export, args: vec![path.clone()] };
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Exported { path: path.clone(), count: 2 });
+
+        // Importing merges, so keys that aren't in the file survive
+        let mut imported: HashMap<String, Entry> = HashMap::new();
+        imported.insert("other".to_string(), Entry::new("untouched"));
+        imported.insert("greeting".to_string(), Entry::new("overwritten"));
+        let query = Query{ q_type: QueryType::Import, args: vec![path.clone()] };
+        let query_result = process_query(&query, &mut imported, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Imported { path: path.clone(), count: 2 });
+        assert_eq!(imported.len(), 3);
+        assert_eq!(imported.get("other").unwrap().value, "untouched");
+        assert_eq!(imported.get("greeting").unwrap().value, "hello world");
+        assert_eq!(imported.get("quote").unwrap().value, "she said \"hi\"");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn import_json_non_object() {
+        let path = std::env::temp_dir().join("rbdb_import_json_non_object.json");
+        fs::write(&path, "[\"a\", \"b\"]").unwrap();
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let query = Query{ q_type: QueryType::Import, args: vec![path.to_str().unwrap().to_string()] };
+        let query_err = process_query(&query, &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::InvalidImport(_)));
+        assert!(store.is_empty());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn files_are_checked_before_anything_is_written() {
+        let dir = std::env::temp_dir().join("rbdb_files_are_checked");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let file = |name: &str, contents: &str| {
+            let path = dir.join(name);
+            fs::write(&path, contents).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        let config = Config { reject_control_keys: true, ..Config::default() };
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("kept".to_string(), Entry::new("value"));
+
+        // An empty key is refused by every path, a control character only under --reject-control-keys
+        let cases = [
+            format!("IMPORT {}", file("empty.json", r#"{"fine": "1", "": "x"}"#)),
+            format!("IMPORT {}", file("control.csv", "fine,1\n\"bell\u{7}\",x\n")),
+            format!("LOAD {}", file("control.db", "fine\t1\nbell\u{7}\tx\n")),
+        ];
+        for line in &cases {
+            let query_err = execute_line(line, &mut store, &config).unwrap_err();
+            assert!(matches!(query_err, RbdbError::InvalidKey(_)), "{line}");
+            assert_eq!(store.keys().collect::<Vec<_>>(), vec!["kept"], "{line}");
+        }
+        assert!(execute_line(&cases[1], &mut store, &Config::default()).is_ok());
+
+        // So is a value over --max-value-bytes
+        let config = Config { max_value_bytes: Some(3), ..Config::default() };
+        let path = file("long.json", r#"{"short": "abc", "long": "abcd"}"#);
+        let query_err = execute_line(&format!("IMPORT {path}"), &mut store, &config).unwrap_err();
+        assert!(matches!(query_err, RbdbError::ValueTooLarge { size: 4, limit: 3 }));
+        assert!(!store.contains_key("short"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn export_and_import_csv() {
+        let path = std::env::temp_dir().join("rbdb_export_and_import_csv.csv");
+        let path = path.to_str().unwrap().to_string();
+
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("plain".to_string(), Entry::new("value"));
+        store.insert("comma".to_string(), Entry::new("one, two"));
+        store.insert("quote".to_string(), Entry::new("she said \"hi\""));
+        store.insert("multi,line".to_string(), Entry::new("first\nsecond"));
+        let query = Query{ q_type: QueryType::Export, args: vec![path.clone()] };
+        let query_result = process_query(&query, &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Exported { path: path.clone(), count: 4 });
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("comma,\"one, two\"\n"));
+        assert!(contents.contains("quote,\"she said \"\"hi\"\"\"\n"));
+
+        let mut imported: HashMap<String, Entry> = HashMap::new();
+        let query = Query{ q_type: QueryType::Import, args: vec![path.clone()] };
+        let query_result = process_query(&query, &mut imported, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Imported { path: path.clone(), count: 4 });
+        assert_eq!(imported, store);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ingest_tsv() {
+        let path = std::env::temp_dir().join("rbdb_ingest_tsv.tsv");
+        let path = path.to_str().unwrap().to_string();
+        fs::write(&path, "new\tfresh\nold\tclobbered\nno tab here\n\nmulti\tline\\none\nnew\tagain\nbad\tescape\\x\n").unwrap();
+
+        // Existing keys are skipped the way INSERT would refuse them, and so is a key repeated in the file
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("old".to_string(), Entry::new("kept"));
+        let query_result = execute_line(&format!("INGEST {path}"), &mut store, &Config::default()).unwrap();
+        assert_eq!(
+            query_result,
+            QueryResult::Ingested { path: path.clone(), inserted: 2, skipped: 2, malformed: vec![3, 7] }
+        );
+        assert_eq!(
+            query_result.to_string(),
+            format!("SUCCESS: Ingested 2 entries from {path}, skipped 2 existing keys and malformed lines 3, 7")
+        );
+        assert_eq!(store.get("new").unwrap().value, "fresh");
+        assert_eq!(store.get("old").unwrap().value, "kept");
+        assert_eq!(store.get("multi").unwrap().value, "line\none");
+
+        // Under --strict the first malformed line fails the whole file before anything is written
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let config = Config { strict: true, ..Config::default() };
+        let query_err = execute_line(&format!("INGEST {path}"), &mut store, &config).unwrap_err();
+        assert!(matches!(query_err, RbdbError::MalformedFile { line: 3, .. }));
+        assert!(store.is_empty());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn import_malformed_csv() {
+        let path = std::env::temp_dir().join("rbdb_import_malformed_csv.csv");
+        fs::write(&path, "a,1\nb,2\nc,3,extra\n").unwrap();
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let query = Query{ q_type: QueryType::Import, args: vec![path.to_str().unwrap().to_string()] };
+        let query_err = process_query(&query, &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::MalformedFile { line: 3, .. }));
+        assert!(store.is_empty());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn export_unknown_extension() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let query = Query::build_query("EXPORT data.xml").unwrap();
+        let query_err = process_query(&query, &mut store, &Config::default()).unwrap_err();
+        assert!(matches!(query_err, RbdbError::UnsupportedFormat(path) if path == "data.xml"));
+    }
+
+    // A deliberately naive backend, to prove the engine only relies on the Storage trait
+    #[derive(Default)]
+    struct VecStore {
+        entries: Vec<(String, Entry)>,
+    }
+
+    impl Storage for VecStore {
+        fn get_entry(&self, key: &str) -> Option<&Entry> {
+            self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+        }
+
+        fn insert_entry(&mut self, key: String, entry: Entry) -> Option<Entry> {
+            match self.entries.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, v)) => Some(std::mem::replace(v, entry)),
+                None => {
+                    self.entries.push((key, entry));
+                    None
+                }
+            }
+        }
+
+        fn remove_entry(&mut self, key: &str) -> Option<Entry> {
+            let index = self.entries.iter().position(|(k, _)| k == key)?;
+            Some(self.entries.remove(index).1)
+        }
+
+        fn len(&self) -> usize {
+            self.entries.len()
+        }
+
+        fn keys(&self) -> Box<dyn Iterator<Item = &String> + '_> {
+            Box::new(self.entries.iter().map(|(k, _)| k))
+        }
+
+        fn clear(&mut self) {
+            self.entries.clear()
+        }
+    }
+
+    #[test]
+    fn custom_storage_backend() {
+        let mut store = VecStore::default();
+        for input in ["INSERT b 2", "INSERT a 1", "UPDATE a 10", "RENAME b c", "APPEND c 0"] {
+            process_query(&Query::build_query(input).unwrap(), &mut store, &Config::default()).unwrap();
+        }
+        let query_result = process_query(&Query::build_query("SELECT a").unwrap(), &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Found { key: "a".to_string(), value: "10".to_string() });
+        let query_result = process_query(&Query::build_query("KEYS").unwrap(), &mut store, &Config::default()).unwrap();
+        assert_eq!(query_result, QueryResult::Keys(vec!["a".to_string(), "c".to_string()]));
+        assert_eq!(store.get("c").unwrap(), "20");
+    }
+
+    #[test]
+    fn script_query() {
+        let script = "# Seed some data\nINSERT a 1\nINSERT b 2\n\nINSERT a 3\nUPDATE b 20\nFROBNICATE a\nDELETE a\n";
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let mut out: Vec<u8> = Vec::new();
+        run_script(io::Cursor::new(script), &mut out, &store, &Config::default()).unwrap();
+        let store = store.into_inner().unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get("b").unwrap().value, "20");
+    }
+
+    #[test]
+    fn splits_off_redirects() {
+        let to = |path: &str, append| Some(Redirect { path: path.to_string(), append });
+        assert_eq!(split_redirect("SELECT key > out.txt"), ("SELECT key ", to("out.txt", false)));
+        assert_eq!(split_redirect("DUMP >> all.tsv "), ("DUMP ", to("all.tsv", true)));
+        assert_eq!(split_redirect(r#"SELECT key > "my results.txt""#), ("SELECT key ", to("my results.txt", false)));
+        assert_eq!(split_redirect(r#"INSERT k "a > b" > out"#), (r#"INSERT k "a > b" "#, to("out", false)));
+
+        // Anything else is left for the command to make sense of
+        for statement in ["INSERT k a>b", "INSERT k >", "INSERT k > a b", "SELECT key", r#"INSERT k "> x""#, "> out", "INSERT k >>> x"] {
+            assert_eq!(split_redirect(statement), (statement, None), "{statement}");
+        }
+    }
+
+    #[test]
+    fn redirected_results_go_to_the_file() {
+        let dir = std::env::temp_dir().join("rbdb_redirected_results_go_to_the_file");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("out.txt");
+        let path = path.to_str().unwrap();
+
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let mut out: Vec<u8> = Vec::new();
+        let script = format!("INSERT greeting \"hello world\"\nSELECT greeting > {path}\nCOUNT\n");
+        run_script(io::Cursor::new(script), &mut out, &store, &Config::default()).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "hello world\n");
+        // Only the statement with the redirect is sent to the file
+        assert_eq!(String::from_utf8(out).unwrap(), "SUCCESS: Inserted greeting:hello world into database\n1\n");
+
+        // > replaces what the file held, >> adds to it
+        let mut out: Vec<u8> = Vec::new();
+        let script = format!("SELECT greeting > {path}; COUNT >> {path}; KEYS >> {path}\n");
+        run_script(io::Cursor::new(script), &mut out, &store, &Config::default()).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "hello world\n1\ngreeting\n");
+        assert!(out.is_empty());
+
+        // A file that can't be opened fails the statement without running it
+        let unwritable = dir.join("missing_dir").join("out.txt");
+        let script = format!("DELETE greeting > {}\n", unwritable.to_str().unwrap());
+        let status = run_script(io::Cursor::new(script), &mut io::sink(), &store, &Config::default()).unwrap();
+        assert_eq!(status, Status::Failed);
+        assert!(store.lock().unwrap().contains_key("greeting"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn strict_script_query() {
+        let script = "INSERT a 1\nFROBNICATE a\nINSERT b 2\n";
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let script_err = run_script(io::Cursor::new(script), &mut io::sink(), &store, &Config { strict: true, ..Config::default() }).unwrap_err();
+        let store = store.into_inner().unwrap();
+        assert_eq!(script_err.to_string(), "Script stopped on line 2: Invalid query type: FROBNICATE");
+        assert_eq!(store.get("a").unwrap().value, "1");
+        assert!(!store.contains_key("b"));
+    }
+
+    #[test]
+    fn script_status_is_the_last_query() {
+        let status = |script: &str| {
+            let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+            run_script(io::Cursor::new(script), &mut io::sink(), &store, &Config::default()).unwrap()
+        };
+        assert_eq!(status(""), Status::Success);
+        assert_eq!(status("FROBNICATE\nINSERT a 1\n"), Status::Success);
+        assert_eq!(status("INSERT a 1\nSELECT missing\n# trailing comment\n"), Status::NotFound);
+        assert_eq!(status("INSERT a 1; DELETE missing\n"), Status::Failed);
+    }
+
+    #[test]
+    fn heredoc_values() {
+        // Only a line that is exactly the terminator ends the value, and quotes and backslashes survive as typed
+        let script = "INSERT poem <<END\nroses are \"red\"\n  END \nC:\\path\nEND\nINSERT after 1\n# not a <<MARKER\n";
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let status = run_script(io::Cursor::new(script), &mut io::sink(), &store, &Config::default()).unwrap();
+        assert_eq!(status, Status::Success);
+        assert_eq!(lock(&store).get("poem").unwrap().value, "roses are \"red\"\n  END \nC:\\path");
+        assert_eq!(lock(&store).get("after").unwrap().value, "1");
+
+        // The REPL reads them the same way
+        let mut out: Vec<u8> = Vec::new();
+        run_repl(&mut io::Cursor::new("UPSERT poem <<EOF\r\none\r\ntwo\r\nEOF\r\nSELECT poem\n"), &mut out, &store, &Config::default(), false).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "SUCCESS: Updated poem with one\ntwo\none\ntwo\n");
+
+        // Lines are still counted from the start of the file
+        let script = "INSERT a <<X\n1\nX\nFROBNICATE\n";
+        let script_err = run_script(io::Cursor::new(script), &mut io::sink(), &store, &Config { strict: true, ..Config::default() }).unwrap_err();
+        assert_eq!(script_err.to_string(), "Script stopped on line 4: Invalid query type: FROBNICATE");
+    }
+
+    #[test]
+    fn over_long_lines_are_refused() {
+        // A small buffer, so the long line arrives in many pieces and is never held whole
+        let input = format!("short\n{}\r\nexact ten\r\nnext", "x".repeat(10_000));
+        let mut reader = io::BufReader::with_capacity(16, input.as_bytes());
+        assert_eq!(read_next_line(&mut reader, Some(10)).unwrap().as_deref(), Some("short\n"));
+        assert!(matches!(read_next_line(&mut reader, Some(10)), Err(RbdbError::LineTooLong(10))));
+        assert_eq!(read_next_line(&mut reader, Some(10)).unwrap().as_deref(), Some("exact ten\r\n"));
+        assert_eq!(read_next_line(&mut reader, Some(10)).unwrap().as_deref(), Some("next"));
+        assert_eq!(read_next_line(&mut reader, Some(10)).unwrap(), None);
+
+        // A script reports the line and carries on, unless it's strict
+        let config = Config { max_line_bytes: Some(16), ..Config::default() };
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let script = format!("INSERT a 1\nINSERT b {}\nINSERT c 3\n", "2".repeat(100));
+        let mut out: Vec<u8> = Vec::new();
+        let status = run_script(io::Cursor::new(&script), &mut out, &store, &config).unwrap();
+        assert_eq!(status, Status::Success);
+        assert_eq!(lock(&store).len(), 2);
+        assert!(!lock(&store).contains_key("b"));
+
+        let config = Config { strict: true, ..config };
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let e = run_script(io::Cursor::new(&script), &mut out, &store, &config).unwrap_err();
+        assert_eq!(e.to_string(), "Script stopped on line 2: Line is longer than the limit of 16 bytes");
+        assert_eq!(lock(&store).len(), 1);
+
+        // The lines of a here-doc count one by one, and all of them are read even when one is too long
+        let script = format!("INSERT d <<END\n{}\nINSERT e 5\nEND\nINSERT f 6\n", "4".repeat(100));
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let config = Config { strict: false, ..config };
+        run_script(io::Cursor::new(&script), &mut out, &store, &config).unwrap();
+        assert_eq!(lock(&store).keys().collect::<Vec<_>>(), vec!["f"]);
+    }
+
+    #[test]
+    fn unterminated_heredoc() {
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let script = "INSERT poem <<END\nno end in sight\n";
+        let status = run_script(io::Cursor::new(script), &mut io::sink(), &store, &Config::default()).unwrap();
+        assert_eq!(status, Status::Failed);
+        assert!(lock(&store).is_empty());
+
+        let script_err = run_script(io::Cursor::new(script), &mut io::sink(), &store, &Config { strict: true, ..Config::default() }).unwrap_err();
+        assert_eq!(script_err.to_string(), "Script stopped on line 1: Input ended before the END line closing the value");
+    }
+
+    #[test]
+    fn repl_stops_at_eof() {
+        let mut input = io::Cursor::new("INSERT some_key some_value\n");
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        run_repl(&mut input, &mut io::sink(), &store, &Config::default(), true).unwrap();
+        let store = store.into_inner().unwrap();
+        assert_eq!(store.get("some_key").unwrap().value, "some_value");
+    }
+
+    #[test]
+    fn repl_prompt_only_when_interactive() {
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let mut out: Vec<u8> = Vec::new();
+        run_repl(&mut io::Cursor::new("INSERT a b\n"), &mut out, &store, &Config::default(), false).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "SUCCESS: Inserted a:b into database\n");
+
+        let mut out: Vec<u8> = Vec::new();
+        run_repl(&mut io::Cursor::new("SELECT a\n"), &mut out, &store, &Config::default(), true).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "RBDB -> b\nRBDB -> \nGoodbye\n");
+    }
+
+    #[test]
+    fn prompt_substitutions() {
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let mut session = Session::new(&store);
+        assert_eq!(prompt(&Config::default(), &session, 1), "RBDB -> ");
+
+        let config = Config { prompt: Some("[%n #%c] 100%% %x> ".to_string()), ..Config::default() };
+        assert_eq!(prompt(&config, &session, 7), "[default #7] 100% %x> ");
+        session.execute("USE cache", &config).unwrap();
+        assert_eq!(prompt(&config, &session, 8), "[cache #8] 100% %x> ");
+
+        // The REPL shows it before every line
+        let config = Config { prompt: Some("%c> ".to_string()), ..Config::default() };
+        let mut out: Vec<u8> = Vec::new();
+        run_repl(&mut io::Cursor::new("PING\n"), &mut out, &store, &config, true).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "1> PONG\n2> \nGoodbye\n");
+    }
+
+    #[test]
+    fn timing_toggle() {
+        let mut config = Config::default();
+        assert!(!config.timing);
+        assert_eq!(meta_command("TIMING ON", &mut config).unwrap(), "Timing is on");
+        assert!(config.timing);
+        assert_eq!(meta_command("  timing off ", &mut config).unwrap(), "Timing is off");
+        assert!(!config.timing);
+
+        // A bad setting leaves things as they were, and queries aren't meta-commands at all
+        assert_eq!(meta_command("TIMING maybe", &mut config).unwrap(), "Usage: TIMING ON|OFF");
+        assert!(!config.timing);
+        assert!(meta_command("SELECT timing", &mut config).is_none());
+    }
+
+    #[test]
+    fn stats_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let config = Config::default();
+        for input in ["INSERT a 1", "SELECT a", "SELECT a", "SELECT missing", "UPDATE a 2", "COUNT", "HELP"] {
+            let _ = execute_line(input, &mut store, &config);
+        }
+
+        let expected = StatsSnapshot { hits: 2, misses: 1, reads: 4, writes: 2, evictions: 0 };
+        assert_eq!(execute_line("STATS", &mut store, &config).unwrap(), QueryResult::Stats(expected));
+        assert_eq!(QueryResult::Stats(expected).to_string(), "hits:2\nmisses:1\nreads:4\nwrites:2\nevictions:0");
+
+        // A failed write still counts, it was still asked for
+        let _ = execute_line("INSERT a 3", &mut store, &config);
+        assert_eq!(config.stats.snapshot().writes, 3);
+    }
+
+    #[test]
+    fn stats_reset() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let config = Config::default();
+        execute_line("SELECT missing", &mut store, &config).unwrap();
+        assert_eq!(execute_line("STATS RESET", &mut store, &config).unwrap(), QueryResult::StatsReset);
+        assert_eq!(config.stats.snapshot(), StatsSnapshot::default());
+
+        // Clones of the config share the counters
+        let shared = config.clone();
+        execute_line("SELECT missing", &mut store, &shared).unwrap();
+        assert_eq!(config.stats.snapshot().misses, 1);
+
+        let query_err = execute_line("STATS FORGET", &mut store, &config).unwrap_err();
+        assert!(matches!(query_err, RbdbError::UnknownOption(option) if option == "FORGET"));
+    }
+
+    #[test]
+    fn max_keys_evicts_least_recently_used() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let config = Config { max_keys: Some(3), ..Config::default() };
+        for input in ["INSERT a 1", "INSERT b 2", "INSERT c 3", "SELECT a", "UPDATE b 20"] {
+            execute_line(input, &mut store, &config).unwrap();
+        }
+
+        // c was written before a was read and b was rewritten, so it's the one that goes
+        execute_line("INSERT d 4", &mut store, &config).unwrap();
+        let mut keys: Vec<&String> = store.keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["a", "b", "d"]);
+        assert_eq!(config.stats.snapshot().evictions, 1);
+
+        execute_line("MSET e 5 f 6", &mut store, &config).unwrap();
+        assert_eq!(store.len(), 3);
+        assert!(store.contains_key("d"));
+        assert_eq!(config.stats.snapshot().evictions, 3);
+    }
+
+    #[test]
+    fn ping_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        assert_eq!(execute_line("PING", &mut store, &Config::default()).unwrap(), QueryResult::Value("PONG".to_string()));
+        let result = execute_line(r#"PING "are you there?""#, &mut store, &Config::default()).unwrap();
+        assert_eq!(result, QueryResult::Value("are you there?".to_string()));
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn info_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("a".to_string(), Entry::new("1"));
+        let info = execute_line("INFO", &mut store, &Config::default()).unwrap().to_string();
+        let lines: Vec<&str> = info.lines().collect();
+        assert_eq!(lines[0], format!("version:{}", env!("CARGO_PKG_VERSION")));
+        assert_eq!(lines[1], "uptime_seconds:0");
+        assert_eq!(lines[2..], ["keys:1", "backend:hash"]);
+
+        let mut store: BTreeMap<String, Entry> = BTreeMap::new();
+        let info = execute_line("INFO", &mut store, &Config::default()).unwrap().to_string();
+        assert!(info.ends_with("backend:btree"));
+    }
+
+    #[test]
+    fn touch_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("a".to_string(), Entry::new("1"));
+        store.insert("b".to_string(), Entry::new("2"));
+        let config = Config::default();
+
+        assert_eq!(execute_line("TOUCH a", &mut store, &config).unwrap(), QueryResult::Count(1));
+        assert!(store.get("a").unwrap().accessed_at.is_some());
+        assert_eq!(store.get("a").unwrap().value, "1");
+        assert!(store.get("b").unwrap().accessed_at.is_none());
+
+        assert_eq!(execute_line("TOUCH missing", &mut store, &config).unwrap(), QueryResult::Count(0));
+        assert!(!store.contains_key("missing"));
+        assert_eq!(execute_line("TOUCH a missing b", &mut store, &config).unwrap(), QueryResult::Count(2));
+        assert!(store.get("b").unwrap().accessed_at.is_some());
+    }
+
+    #[test]
+    fn setnx_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let config = Config::default();
+        assert_eq!(execute_line("SETNX lock owner1", &mut store, &config).unwrap(), QueryResult::Integer(1));
+        assert_eq!(store.get("lock").unwrap().value, "owner1");
+
+        // Already held, so nothing changes and it isn't an error either
+        let result = execute_line("SETNX lock owner2", &mut store, &config).unwrap();
+        assert_eq!(result, QueryResult::Integer(0));
+        assert_eq!(result.to_string(), "0");
+        assert_eq!(store.get("lock").unwrap().value, "owner1");
+    }
+
+    #[test]
+    fn compressed_values_read_back_unchanged() {
+        let mut store = CompressedStore::new(HashMap::<String, Entry>::new(), Some(COMPRESS_THRESHOLD));
+        let config = Config::default();
+        let blob = "the quick brown fox jumps over the lazy dog ".repeat(500);
+
+        execute_line(&format!("INSERT blob \"{blob}\""), &mut store, &config).unwrap();
+        assert!(store.get_entry("blob").unwrap().value.is_compressed());
+        assert_eq!(execute_line("SELECT blob", &mut store, &config).unwrap(), QueryResult::Found { key: "blob".to_string(), value: blob.clone() });
+        assert_eq!(execute_line("STRLEN blob", &mut store, &config).unwrap(), QueryResult::Length(blob.len()));
+
+        execute_line("APPEND blob !", &mut store, &config).unwrap();
+        assert_eq!(execute_line("SELECT blob", &mut store, &config).unwrap(), QueryResult::Found { key: "blob".to_string(), value: format!("{blob}!") });
+    }
+
+    #[test]
+    fn getset_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("counter".to_string(), Entry::new("41"));
+
+        assert_eq!(execute_line("GETSET counter 0", &mut store, &Config::default()).unwrap(), QueryResult::Previous(Some("41".to_string())));
+        assert_eq!(store.get("counter").unwrap().value, "0");
+
+        let result = execute_line("GETSET fresh 1", &mut store, &Config::default()).unwrap();
+        assert_eq!(result.to_string(), "(nil)");
+        assert_eq!(store.get("fresh").unwrap().value, "1");
+    }
+
+    #[test]
+    fn cas_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("version".to_string(), Entry::new("1"));
+
+        assert_eq!(execute_line("CAS version 1 2", &mut store, &Config::default()).unwrap(), QueryResult::Bool(true));
+        assert_eq!(store.get("version").unwrap().value, "2");
+
+        // Someone else got there first, so nothing changes
+        assert_eq!(execute_line("CAS version 1 3", &mut store, &Config::default()).unwrap(), QueryResult::Bool(false));
+        assert_eq!(store.get("version").unwrap().value, "2");
+    }
+
+    #[test]
+    fn cas_missing_key() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        assert_eq!(execute_line("CAS lock 1 2", &mut store, &Config::default()).unwrap(), QueryResult::Bool(false));
+        assert!(!store.contains_key("lock"));
+
+        // Only an empty expected value matches a key that isn't there
+        assert_eq!(execute_line(r#"CAS lock "" taken"#, &mut store, &Config::default()).unwrap(), QueryResult::Bool(true));
+        assert_eq!(store.get("lock").unwrap().value, "taken");
+    }
+
+    #[test]
+    fn strlen_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("ascii".to_string(), Entry::new("hello"));
+        store.insert("accent".to_string(), Entry::new("café"));
+
+        assert_eq!(execute_line("STRLEN ascii", &mut store, &Config::default()).unwrap(), QueryResult::Length(5));
+        // Four characters, but é takes two bytes in UTF-8
+        assert_eq!(execute_line("STRLEN accent", &mut store, &Config::default()).unwrap(), QueryResult::Length(5));
+        assert_eq!(execute_line("STRLEN missing", &mut store, &Config::default()).unwrap(), QueryResult::Length(0));
+    }
+
+    #[test]
+    fn getrange_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("greeting".to_string(), Entry::new("Hello, world"));
+        let config = Config::default();
+        let getrange = |store: &mut HashMap<String, Entry>, line: &str| execute_line(line, store, &config).unwrap().to_string();
+
+        assert_eq!(getrange(&mut store, "GETRANGE greeting 0 4"), "Hello");
+        assert_eq!(getrange(&mut store, "GETRANGE greeting 7 7"), "w");
+        // Negative offsets count back from the end
+        assert_eq!(getrange(&mut store, "GETRANGE greeting -5 -1"), "world");
+        assert_eq!(getrange(&mut store, "GETRANGE greeting 0 -1"), "Hello, world");
+
+        // Out of range offsets are clamped, and a range that ends up backwards is empty
+        assert_eq!(getrange(&mut store, "GETRANGE greeting -100 4"), "Hello");
+        assert_eq!(getrange(&mut store, "GETRANGE greeting 7 100"), "world");
+        assert_eq!(getrange(&mut store, "GETRANGE greeting 50 100"), "");
+        assert_eq!(getrange(&mut store, "GETRANGE greeting 5 2"), "");
+        assert_eq!(getrange(&mut store, "GETRANGE missing 0 10"), "");
+
+        assert!(matches!(execute_line("GETRANGE greeting a 1", &mut store, &config), Err(RbdbError::NotAnInteger(_))));
+    }
+
+    #[test]
+    fn type_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        for (key, value) in [("int", "42"), ("negative", "-7"), ("float", "3.14"), ("string", "hello"), ("nan", "NaN")] {
+            store.insert(key.to_string(), Entry::new(value));
+        }
+
+        let type_of = |store: &mut HashMap<String, Entry>, key: &str| {
+            execute_line(&format!("TYPE {key}"), store, &Config::default()).unwrap().to_string()
+        };
+        assert_eq!(type_of(&mut store, "int"), "int");
+        assert_eq!(type_of(&mut store, "negative"), "int");
+        assert_eq!(type_of(&mut store, "float"), "float");
+        assert_eq!(type_of(&mut store, "string"), "string");
+        assert_eq!(type_of(&mut store, "nan"), "string");
+        assert_eq!(type_of(&mut store, "missing"), "none");
+    }
+
+    #[test]
+    fn case_insensitive_keys() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        execute_line("INSERT User x", &mut store, &Config::default()).unwrap();
+        assert!(matches!(execute_line("SELECT user", &mut store, &Config::default()), Ok(QueryResult::NotFound(_))));
+
+        let config = Config { case_insensitive_keys: true, ..Config::default() };
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        execute_line("INSERT User x", &mut store, &config).unwrap();
+        assert_eq!(execute_line("SELECT user", &mut store, &config).unwrap(), QueryResult::Found { key: "user".to_string(), value: "x".to_string() });
+        assert!(matches!(execute_line("INSERT USER y", &mut store, &config), Err(RbdbError::KeyExists(_))));
+
+        // Values and paths keep their case, only keys are folded
+        execute_line("MSET Alpha One BETA Two", &mut store, &config).unwrap();
+        assert_eq!(store.get("alpha").unwrap().value, "One");
+        assert_eq!(execute_line("KEYS B*", &mut store, &config).unwrap(), QueryResult::Keys(vec!["beta".to_string()]));
+        execute_line("RENAME ALPHA Gamma", &mut store, &config).unwrap();
+        assert!(store.contains_key("gamma"));
+    }
+
+    #[test]
+    fn invalid_keys() {
+        assert!(matches!(Query::build_query(r#"INSERT "" value"#), Err(RbdbError::InvalidKey(_))));
+        assert!(matches!(Query::build_query(r#"MSET a 1 "" 2"#), Err(RbdbError::InvalidKey(_))));
+        // An empty value is fine, it's only keys that can't be empty
+        assert!(Query::build_query("INSERT key \"\"").is_ok());
+
+        // Queries built by hand get the same check
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let query = Query{ q_type: QueryType::Insert, args: vec!["".to_string(), "value".to_string()] };
+        assert!(matches!(process_query(&query, &mut store, &Config::default()), Err(RbdbError::InvalidKey(_))));
+        assert!(store.is_empty());
+
+        // Control characters are only refused when asked
+        let query = Query{ q_type: QueryType::Insert, args: vec!["bell\x07".to_string(), "value".to_string()] };
+        let config = Config { reject_control_keys: true, ..Config::default() };
+        assert!(matches!(process_query(&query, &mut store, &config), Err(RbdbError::InvalidKey(_))));
+        assert!(process_query(&query, &mut store, &Config::default()).is_ok());
+    }
+
+    #[test]
+    fn max_value_size() {
+        let config = Config { max_value_bytes: Some(4), ..Config::default() };
+        let mut store: HashMap<String, Entry> = HashMap::new();
+
+        assert!(execute_line("INSERT a 1234", &mut store, &config).is_ok());
+        assert!(matches!(
+            execute_line("INSERT b 12345", &mut store, &config),
+            Err(RbdbError::ValueTooLarge { size: 5, limit: 4 })
+        ));
+        assert!(!store.contains_key("b"));
+        assert!(matches!(execute_line("UPDATE a 12345", &mut store, &config), Err(RbdbError::ValueTooLarge { .. })));
+        assert!(matches!(execute_line("UPSERT a 12345", &mut store, &config), Err(RbdbError::ValueTooLarge { .. })));
+        assert_eq!(store.get("a").unwrap().value, "1234");
+
+        // APPEND is judged on the whole value afterwards, even though the fragment itself is tiny
+        execute_line("INSERT c 12", &mut store, &config).unwrap();
+        assert_eq!(execute_line("APPEND c 34", &mut store, &config).unwrap(), QueryResult::Length(4));
+        assert!(matches!(execute_line("APPEND c 5", &mut store, &config), Err(RbdbError::ValueTooLarge { .. })));
+        assert_eq!(store.get("c").unwrap().value, "1234");
+
+        // One oversized pair stops the whole MSET
+        assert!(execute_line("MSET d 1 e 12345", &mut store, &config).is_err());
+        assert!(!store.contains_key("d"));
+    }
+
+    #[test]
+    fn mutating_commands() {
+        let classified = [
+            (QueryType::Insert, true),
+            (QueryType::Select, false),
+            (QueryType::Update, true),
+            (QueryType::Replace, true),
+            (QueryType::Upsert, true),
+            (QueryType::SetNx, true),
+            (QueryType::GetSet, true),
+            (QueryType::SetB, true),
+            (QueryType::GetB, false),
+            (QueryType::History, false),
+            (QueryType::Cas, true),
+            (QueryType::Append, true),
+            (QueryType::Delete, true),
+            (QueryType::PopDelete, true),
+            (QueryType::GetDel, true),
+            (QueryType::DelPattern, true),
+            (QueryType::Rename, true),
+            (QueryType::Copy, true),
+            (QueryType::Incr, true),
+            (QueryType::Decr, true),
+            (QueryType::IncrBy, true),
+            (QueryType::SetMax, true),
+            (QueryType::SetMin, true),
+            (QueryType::Expire, true),
+            (QueryType::ExpireAt, true),
+            (QueryType::Persist, true),
+            (QueryType::SetEx, true),
+            (QueryType::Ttl, false),
+            (QueryType::Exists, false),
+            (QueryType::Touch, false),
+            (QueryType::StrLen, false),
+            (QueryType::MemUsage, false),
+            (QueryType::GetRange, false),
+            (QueryType::Type, false),
+            (QueryType::MGet, false),
+            (QueryType::MSet, true),
+            (QueryType::Save, false),
+            (QueryType::Load, true),
+            (QueryType::Export, false),
+            (QueryType::Import, true),
+            (QueryType::Ingest, true),
+            (QueryType::Keys, false),
+            (QueryType::SelectWhere, false),
+            (QueryType::Scan, false),
+            (QueryType::Range, false),
+            (QueryType::Dump, false),
+            (QueryType::RandomKey, false),
+            (QueryType::Count, false),
+            (QueryType::Clear, true),
+            (QueryType::Begin, false),
+            (QueryType::Commit, false),
+            (QueryType::Rollback, false),
+            (QueryType::Undo, true),
+            (QueryType::Use, false),
+            (QueryType::DbList, false),
+            (QueryType::Prefix, false),
+            (QueryType::Subscribe, false),
+            (QueryType::Watch, false),
+            (QueryType::Stats, false),
+            (QueryType::Info, false),
+            (QueryType::Ping, false),
+            (QueryType::Debug, false),
+            (QueryType::Help, false),
+        ];
+        for (q_type, mutating) in classified {
+            assert_eq!(q_type.is_mutating(), mutating, "{q_type:?}");
+        }
+
+        // Every command in the table is covered above
+        for command in COMMANDS {
+            assert!(classified.iter().any(|(q_type, _)| *q_type == command.q_type), "{} is missing", command.name);
+        }
+    }
+
+    #[test]
+    fn read_only_refuses_writes() {
+        let path = std::env::temp_dir().join("rbdb_read_only_import.json");
+        std::fs::write(&path, r#"{"a": "2"}"#).unwrap();
+        let import = format!("IMPORT {}", path.display());
+        let load = format!("LOAD {}", path.display());
+
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("a".to_string(), Entry::new("1"));
+        let before = store.clone();
+        let config = Config { read_only: true, ..Config::default() };
+
+        let writes = [
+            "INSERT b 1", "UPDATE a 2", "UPSERT a 2", "GETSET a 2", "CAS a 1 2", "APPEND a 2", "DELETE a",
+            "RENAME a b", "COPY a b", "INCR a", "DECR a", "INCRBY a 2", "EXPIRE a 10", "SETEX a 10 2",
+            "MSET a 2 b 3", "CLEAR", load.as_str(), import.as_str(),
+        ];
+        for input in writes {
+            let query_err = execute_line(input, &mut store, &config).unwrap_err();
+            assert!(matches!(query_err, RbdbError::ReadOnly), "{input} was let through");
+        }
+        assert_eq!(store, before);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn parses_value_predicates() {
+        assert_eq!(parse_predicate("value=foo").unwrap(), ValuePredicate::Equals("foo".to_string()));
+        assert_eq!(parse_predicate("value=a=b").unwrap(), ValuePredicate::Equals("a=b".to_string()));
+        assert_eq!(parse_predicate("value=").unwrap(), ValuePredicate::Equals(String::new()));
+        assert_eq!(parse_predicate("value~oo").unwrap(), ValuePredicate::Contains("oo".to_string()));
+        assert_eq!(parse_predicate("value~=x").unwrap(), ValuePredicate::Contains("=x".to_string()));
+        for bad in ["foo", "value", "value>3", "key=foo", "Value=foo"] {
+            assert!(matches!(parse_predicate(bad), Err(RbdbError::InvalidPredicate(p)) if p == bad), "{bad}");
+        }
+
+        assert!(ValuePredicate::Equals("foo".to_string()).matches("foo"));
+        assert!(!ValuePredicate::Equals("foo".to_string()).matches("food"));
+        assert!(ValuePredicate::Contains("oo".to_string()).matches("food"));
+        assert!(!ValuePredicate::Contains("oo".to_string()).matches("fod"));
+    }
+
+    #[test]
+    fn select_where_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        for (key, value) in [("b", "foo"), ("a", "foo"), ("c", "food"), ("d", "bar"), ("e", "")] {
+            store.insert(key.to_string(), Entry::new(value));
+        }
+        store.insert("stale".to_string(), Entry { expires_at: Some(1), ..Entry::new("foo") });
+        let keys = |names: &[&str]| QueryResult::Keys(names.iter().map(|name| name.to_string()).collect());
+
+        assert_eq!(execute_line("SELECTWHERE value=foo", &mut store, &Config::default()).unwrap(), keys(&["a", "b"]));
+        assert_eq!(execute_line("SELECTWHERE value~oo", &mut store, &Config::default()).unwrap(), keys(&["a", "b", "c"]));
+        assert_eq!(execute_line("SELECTWHERE value=", &mut store, &Config::default()).unwrap(), keys(&["e"]));
+        assert_eq!(execute_line(r#"SELECTWHERE "value~o f""#, &mut store, &Config::default()).unwrap(), keys(&[]));
+        assert_eq!(execute_line("SELECTWHERE value=fo", &mut store, &Config::default()).unwrap(), keys(&[]));
+
+        let query_err = execute_line("SELECTWHERE foo", &mut store, &Config::default()).unwrap_err();
+        assert_eq!(describe_error(&query_err), "Query is malformed: Expected value=text or value~text, got foo");
+    }
+
+    #[test]
+    fn aliases_build_the_same_query() {
+        let pairs = [("GET k", "SELECT k"), ("set k v", "INSERT k v"), ("Del k", "DELETE k"), ("PUT k v", "UPSERT k v")];
+        for (alias, canonical) in pairs {
+            let (alias_query, canonical_query) = (Query::build_query(alias).unwrap(), Query::build_query(canonical).unwrap());
+            assert_eq!(alias_query, canonical_query, "{alias}");
+        }
+        // Errors name the command by its canonical keyword
+        assert!(matches!(Query::build_query("GET k extra"), Err(RbdbError::TooManyArguments(name)) if name == "SELECT"));
+
+        // User aliases come from the config, and can point at a built-in alias too
+        let aliases = HashMap::from([("fetch".to_string(), "SELECT".to_string()), ("Grab".to_string(), "get".to_string())]);
+        for line in ["FETCH k", "fetch k", "grab k"] {
+            assert_eq!(Query::build_query_with(line, &aliases).unwrap(), Query::build_query("SELECT k").unwrap(), "{line}");
+        }
+        assert!(matches!(Query::build_query("FETCH k"), Err(RbdbError::InvalidQueryType(name)) if name == "FETCH"));
+
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let config = Config { aliases, ..Config::default() };
+        execute_line("SET a 1", &mut store, &config).unwrap();
+        assert_eq!(execute_line("fetch a", &mut store, &config).unwrap(), execute_line("SELECT a", &mut store, &config).unwrap());
+    }
+
+    #[test]
+    fn read_only_allows_reads() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("a".to_string(), Entry::new("1"));
+        let config = Config { read_only: true, ..Config::default() };
+
+        let reads = [
+            "SELECT a", "EXISTS a", "KEYS", "COUNT", "TTL a", "STRLEN a", "TYPE a", "MEMUSAGE a", "MEMUSAGE", "MGET a b", "RANGE a z",
+            "DUMP", "RANDOMKEY", "SCAN 0", "SELECTWHERE value=1", "STATS", "INFO", "PING", "HELP",
+        ];
+        for input in reads {
+            assert!(execute_line(input, &mut store, &config).is_ok(), "{input} was refused");
+        }
+        assert_eq!(execute_line("SELECT a", &mut store, &config).unwrap(), QueryResult::Found { key: "a".to_string(), value: "1".to_string() });
+    }
+
+    #[test]
+    fn dump_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        assert_eq!(execute_line("DUMP", &mut store, &Config::default()).unwrap().to_string(), "");
+
+        store.insert("b".to_string(), Entry::new("two\tcolumns\nand lines"));
+        store.insert("a".to_string(), Entry::new("back\\slash"));
+        let dump = execute_line("DUMP", &mut store, &Config::default()).unwrap().to_string();
+        assert_eq!(dump, "a\tback\\\\slash\nb\ttwo\\tcolumns\\nand lines");
+        assert!(dump.lines().all(|line| line.matches('\t').count() == 1));
+    }
+
+    #[test]
+    fn randomkey_query() {
+        let config = Config { rng: SharedRng::seeded(42), ..Config::default() };
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        assert_eq!(execute_line("RANDOMKEY", &mut store, &config).unwrap().to_string(), "(nil)");
+
+        for key in ["a", "b", "c", "d", "e"] {
+            store.insert(key.to_string(), Entry::new("v"));
+        }
+        let picks = |config: &Config, store: &mut HashMap<String, Entry>| -> Vec<String> {
+            (0..10).map(|_| execute_line("RANDOMKEY", store, config).unwrap().to_string()).collect()
+        };
+
+        // The same seed gives the same picks, and a BTreeMap holding the same keys agrees
+        let first = picks(&config, &mut store);
+        let second = picks(&Config { rng: SharedRng::seeded(42), ..Config::default() }, &mut store);
+        assert_eq!(first, second);
+        assert!(first.iter().all(|key| store.contains_key(key.as_str())));
+
+        let mut btree: BTreeMap<String, Entry> = store.clone().into_iter().collect();
+        let config = Config { rng: SharedRng::seeded(42), ..Config::default() };
+        let from_btree: Vec<String> =
+            (0..10).map(|_| execute_line("RANDOMKEY", &mut btree, &config).unwrap().to_string()).collect();
+        assert_eq!(first, from_btree);
+    }
+
+    #[test]
+    fn copy_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("src".to_string(), Entry { expires_at: Some(u64::MAX), ..Entry::new("v") });
+        store.insert("taken".to_string(), Entry::new("old"));
+
+        let result = execute_line("COPY src dst", &mut store, &Config::default()).unwrap();
+        assert!(matches!(result, QueryResult::Copied { .. }));
+        assert_eq!(store.get("dst"), store.get("src"));
+
+        assert!(matches!(execute_line("COPY missing dst2", &mut store, &Config::default()), Err(RbdbError::KeyNotFound(_))));
+
+        // An existing destination is only overwritten when asked
+        assert!(matches!(execute_line("COPY src taken", &mut store, &Config::default()), Err(RbdbError::KeyExists(_))));
+        assert_eq!(store.get("taken").unwrap().value, "old");
+        execute_line("COPY src taken replace", &mut store, &Config::default()).unwrap();
+        assert_eq!(store.get("taken").unwrap().value, "v");
+
+        assert!(matches!(execute_line("COPY src x FORCE", &mut store, &Config::default()), Err(RbdbError::UnknownOption(_))));
+    }
+
+    #[test]
+    fn mget_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("a".to_string(), Entry::new("1"));
+        store.insert("c".to_string(), Entry::new("3"));
+
+        let result = execute_line("MGET c missing a a", &mut store, &Config::default()).unwrap();
+        assert_eq!(
+            result,
+            QueryResult::Values(vec![Some("3".to_string()), None, Some("1".to_string()), Some("1".to_string())])
+        );
+        let lines: Vec<String> = result.to_string().lines().map(str::to_string).collect();
+        assert_eq!(lines, vec!["3", "(nil)", "1", "1"]);
+    }
+
+    #[test]
+    fn mset_query() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        store.insert("a".to_string(), Entry { expires_at: Some(u64::MAX), ..Entry::new("old") });
+
+        let result = execute_line("MSET a 1 b 2", &mut store, &Config::default()).unwrap();
+        assert_eq!(result, QueryResult::PairsSet(2));
+        // Like any other write, MSET leaves an existing expiry alone
+        assert_eq!(store.get("a").unwrap(), &Entry { expires_at: Some(u64::MAX), ..Entry::new("1") });
+        assert_eq!(store.get("b").unwrap().value, "2");
+
+        // A dangling key fails the whole command, including the pairs before it
+        assert!(matches!(execute_line("MSET c 3 d", &mut store, &Config::default()), Err(RbdbError::UnpairedArguments)));
+        assert!(!store.contains_key("c"));
+    }
+
+    #[test]
+    fn multi_arg_query_literals() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>();
+
+        let query = Query{ q_type: QueryType::MSet, args: args(&["a", "1", "b", "2", "c", "3"]) };
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::PairsSet(3));
+
+        let query = Query{ q_type: QueryType::Rename, args: args(&["c", "d"]) };
+        assert!(matches!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Renamed { .. }));
+
+        let query = Query{ q_type: QueryType::SetEx, args: args(&["e", "60", "5"]) };
+        assert!(matches!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Inserted { .. }));
+        assert!(store.get("e").unwrap().expires_at.is_some());
+
+        let query = Query{ q_type: QueryType::MGet, args: args(&["a", "b", "c", "d", "e"]) };
+        let values = ["1", "2", "", "3", "5"].map(|v| Some(v.to_string()).filter(|v| !v.is_empty()));
+        assert_eq!(process_query(&query, &mut store, &Config::default()).unwrap(), QueryResult::Values(values.to_vec()));
+
+        // Commands that need a second argument still say so when it's missing
+        let query = Query{ q_type: QueryType::SetEx, args: args(&["f"]) };
+        assert!(matches!(process_query(&query, &mut store, &Config::default()), Err(RbdbError::MissingValue)));
+    }
+
+    #[test]
+    fn splitting_statements() {
+        assert_eq!(split_statements("INSERT a 1; INSERT b 2; SELECT a"), vec!["INSERT a 1", " INSERT b 2", " SELECT a"]);
+        assert_eq!(split_statements("INSERT a \"x;y\"; SELECT a;"), vec!["INSERT a \"x;y\"", " SELECT a"]);
+        assert_eq!(split_statements(r#"INSERT a "q\";"; COUNT"#), vec![r#"INSERT a "q\";""#, " COUNT"]);
+        assert!(split_statements(" ; ").is_empty());
+    }
+
+    #[test]
+    fn comments_and_blank_lines() {
+        let input = "# seed the data\n-- in SQL style too; INSERT b 2\n\n   \nINSERT tag \"#rust\"\nINSERT channel #general\nSELECT tag\n";
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let mut out: Vec<u8> = Vec::new();
+        run_repl(&mut io::Cursor::new(input), &mut out, &store, &Config::default(), false).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "SUCCESS: Inserted tag:#rust into database\nSUCCESS: Inserted channel:#general into database\n#rust\n"
+        );
+
+        // A statement on a comment line doesn't run
+        let store = store.into_inner().unwrap();
+        assert!(!store.contains_key("b"));
+
+        // Batch mode skips the same lines
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let status = run_script(io::Cursor::new(input), &mut io::sink(), &store, &Config { strict: true, ..Config::default() }).unwrap();
+        assert_eq!(status, Status::Success);
+        assert_eq!(store.into_inner().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn multi_statement_lines() {
+        let script = "INSERT a 1; FROBNICATE; INSERT b \"2;3\"\nSELECT a; SELECT b\n";
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let mut out: Vec<u8> = Vec::new();
+        run_script(io::Cursor::new(script), &mut out, &store, &Config::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "SUCCESS: Inserted a:1 into database\nSUCCESS: Inserted b:2;3 into database\n1\n2;3\n"
+        );
+
+        // Strict mode stops at the bad statement, so the one after it never runs
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let script_err = run_script(io::Cursor::new(script), &mut io::sink(), &store, &Config { strict: true, ..Config::default() }).unwrap_err();
+        assert_eq!(script_err.to_string(), "Script stopped on line 1, statement 2: Invalid query type: FROBNICATE");
+        let store = store.into_inner().unwrap();
+        assert!(store.contains_key("a"));
+        assert!(!store.contains_key("b"));
+    }
+
+    #[test]
+    fn empty_results_print_nothing() {
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let mut out: Vec<u8> = Vec::new();
+        run_repl(&mut io::Cursor::new("KEYS\nRANGE a z\nSELECT missing\nDELETE missing\nCOUNT\n"), &mut out, &store, &Config::default(), false).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "0\n");
+    }
+
+    #[test]
+    fn wrong_argument_counts() {
+        let too_many = [
+            "INSERT k v extra", "SELECT k extra", "UPDATE k v extra", "REPLACE k v extra", "UPSERT k v extra", "SETNX k v extra", "GETSET k v extra", "SETB k v extra", "GETB k extra", "HISTORY k extra", "APPEND k v extra",
+            "DELETE key oops extra", "POPDELETE k extra", "GETDEL k extra", "DELPATTERN a* b", "RENAME a b c", "COPY a b REPLACE c", "INCR k 1", "DECR k 1", "INCRBY k 1 2", "SETMAX k 1 2", "SETMIN k 1 2", "EXPIRE k 1 2", "EXPIREAT k 1 2", "PERSIST k extra",
+            "SETEX k 1 v extra", "TTL k extra", "EXISTS k extra", "TYPE k extra", "STRLEN k extra", "MEMUSAGE k extra", "GETRANGE k 0 1 2", "SAVE a b", "LOAD a b", "EXPORT a b",
+            "IMPORT a b", "INGEST a b", "KEYS a ALL b", "SELECTWHERE value=a b", "SCAN 0 COUNT 1 x", "RANGE a b c", "COUNT ALL x", "DUMP x", "RANDOMKEY x", "CLEAR x", "FLUSH x", "UNDO x", "HELP a b",
+        ];
+        for input in too_many {
+            let name = input.split(' ').next().unwrap();
+            let name = find_command(name).unwrap().name;
+            match Query::build_query(input) {
+                Err(RbdbError::TooManyArguments(command)) => assert_eq!(command, name, "{input}"),
+                other => panic!("{input} should have too many arguments, got {other:?}"),
+            }
+        }
+
+        let too_few = [
+            "INSERT", "SELECT", "UPDATE", "REPLACE", "UPSERT", "SETNX k", "GETSET", "GETSET k", "SETB k", "GETB", "HISTORY", "APPEND", "DELETE", "POPDELETE", "GETDEL", "DELPATTERN", "RENAME a", "WATCH", "COPY a", "INCR", "DECR", "INCRBY k", "SETMAX k", "SETMIN k",
+            "EXPIRE k", "EXPIREAT k", "PERSIST", "SETEX k 1", "TTL", "EXISTS", "TOUCH", "STRLEN", "GETRANGE k 0", "TYPE", "MGET", "MSET a", "SAVE", "LOAD", "EXPORT", "IMPORT", "INGEST", "RANGE a", "SELECTWHERE", "SCAN",
+        ];
+        for input in too_few {
+            assert!(matches!(Query::build_query(input), Err(RbdbError::NotEnoughArguments)), "{input}");
+        }
+    }
+
+    #[test]
+    fn help_lists_every_command() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let help = execute_line("HELP", &mut store, &Config::default()).unwrap().to_string();
+        let variants = [
+            QueryType::Insert, QueryType::Select, QueryType::Update, QueryType::Upsert, QueryType::Append,
+            QueryType::Delete, QueryType::Rename, QueryType::Incr, QueryType::Decr, QueryType::IncrBy,
+            QueryType::Expire, QueryType::SetEx, QueryType::Ttl, QueryType::Exists, QueryType::Save,
+            QueryType::Load, QueryType::Export, QueryType::Import, QueryType::Keys, QueryType::Range,
+            QueryType::Count, QueryType::Clear, QueryType::Help, QueryType::MGet,
+            QueryType::MSet, QueryType::Type, QueryType::StrLen,
+            QueryType::GetSet, QueryType::Dump,
+            QueryType::RandomKey, QueryType::Copy,
+        ];
+        for variant in variants {
+            // The keywords are just the variant names in capitals
+            let name = format!("{:?}", variant).to_uppercase();
+            assert!(help.lines().any(|line| line.starts_with(&format!("{name} "))), "HELP is missing {name}");
+        }
+    }
+
+    #[test]
+    fn help_for_one_command() {
+        let mut store: HashMap<String, Entry> = HashMap::new();
+        let help = execute_line("HELP setex", &mut store, &Config::default()).unwrap().to_string();
+        assert!(help.starts_with("SETEX key seconds value"));
+        assert_eq!(help.lines().count(), 1);
+        assert!(matches!(execute_line("HELP FROBNICATE", &mut store, &Config::default()), Err(RbdbError::InvalidQueryType(_))));
+    }
+}
@@ -0,0 +1,53 @@
+use log::{LevelFilter, Log, Metadata, Record};
+
+// Sends rbdb's log records to stderr as "[LEVEL] message", where they can't get mixed in with query results.
+// Records from the libraries rbdb uses are left out, they're only noise to someone debugging a query
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level() && metadata.target().starts_with("rbdb")
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{}] {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+// How much gets logged for each -v: only warnings by default, every statement and its outcome at -v, and what
+// each one parsed to as well at -vv
+pub fn log_level(verbosity: u8) -> LevelFilter {
+    match verbosity {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+// Starts logging at the level verbosity picks. Only the first call does anything, since a program gets one logger
+pub fn init_logger(verbosity: u8) {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(log_level(verbosity));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_v_logs_more() {
+        assert!(log_level(0) < log_level(1));
+        assert!(log_level(1) < log_level(2));
+        assert_eq!(log_level(0), LevelFilter::Warn);
+        assert_eq!(log_level(2), LevelFilter::Debug);
+        assert_eq!(log_level(9), LevelFilter::Trace);
+    }
+}
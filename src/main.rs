@@ -1,25 +1,355 @@
 use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, IsTerminal};
+use std::net::TcpListener;
 use std::process;
-use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::collections::{BTreeMap, HashMap};
 
-use rbdb::rbdb_run;
+use rbdb::{command_summary, init_logger, load_store, rbdb_run, read_config_file, run_script, serve, CompressedStore, Config, ConfigFile, Databases, Entry, OutputFormat, RbdbError, SharedRng, Status, Storage, Sweeper, WalStore, COMPRESS_THRESHOLD, DEFAULT_CONFIG_FILE};
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut backend: Option<String> = None;
+    let mut config_path: Option<String> = None;
+    let mut data_file: Option<String> = None;
+    let mut script: Option<String> = None;
+    let mut command: Option<String> = None;
+    let mut sweep_interval: Option<Duration> = None;
+    let mut listen_addr: Option<String> = None;
+    let mut wal: Option<String> = None;
+    let mut autosave = true;
+    let mut compress = false;
+    let mut color = true;
+    let mut prompt: Option<String> = None;
+    let mut verbosity = 0;
+    let mut config = Config::default();
 
-    for arg in args {
-        println!("{arg}");
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // Answered straight away, whatever else is on the command line
+            "-V" | "--version" => {
+                println!("rbdb {}", env!("CARGO_PKG_VERSION"));
+                process::exit(0);
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}\n{}", command_summary());
+                process::exit(0);
+            }
+            "-v" | "--verbose" => verbosity += 1,
+            "-vv" => verbosity += 2,
+            "--backend" => match args.next() {
+                Some(name) => backend = Some(name),
+                None => {
+                    eprintln!("--backend requires hash or btree");
+                    process::exit(1);
+                }
+            },
+            "--config" => match args.next() {
+                Some(path) => config_path = Some(path),
+                None => {
+                    eprintln!("--config requires a file path");
+                    process::exit(1);
+                }
+            },
+            "--script" => match args.next() {
+                Some(path) => script = Some(path),
+                None => {
+                    eprintln!("--script requires a file path");
+                    process::exit(1);
+                }
+            },
+            "-c" | "--command" => match args.next() {
+                Some(query) => command = Some(query),
+                None => {
+                    eprintln!("-c requires a query, e.g. -c \"SELECT key\"");
+                    process::exit(1);
+                }
+            },
+            "--wal" => match args.next() {
+                Some(path) => wal = Some(path),
+                None => {
+                    eprintln!("--wal requires a file path");
+                    process::exit(1);
+                }
+            },
+            "--strict" => config.strict = true,
+            "--ci-keys" => config.case_insensitive_keys = true,
+            "--reject-control-keys" => config.reject_control_keys = true,
+            "--timing" => config.timing = true,
+            "--no-autosave" => autosave = false,
+            "--compress" => compress = true,
+            "--no-color" => color = false,
+            "--format" => match args.next().as_deref() {
+                Some("plain") => config.format = OutputFormat::Plain,
+                Some("json") => config.format = OutputFormat::Json,
+                Some("tsv") => config.format = OutputFormat::Tsv,
+                _ => {
+                    eprintln!("--format requires plain, json or tsv");
+                    process::exit(1);
+                }
+            },
+            "--readonly" => config.read_only = true,
+            "--serve-files" => config.serve_files = true,
+            "--seed" => match args.next().and_then(|seed| seed.parse::<u64>().ok()) {
+                Some(seed) => config.rng = SharedRng::seeded(seed),
+                None => {
+                    eprintln!("--seed requires a number");
+                    process::exit(1);
+                }
+            },
+            "--max-value-bytes" => match args.next().and_then(|bytes| bytes.parse::<usize>().ok()) {
+                Some(bytes) => config.max_value_bytes = Some(bytes),
+                None => {
+                    eprintln!("--max-value-bytes requires a number of bytes");
+                    process::exit(1);
+                }
+            },
+            "--max-line-bytes" => match args.next().and_then(|bytes| bytes.parse::<usize>().ok()) {
+                Some(bytes) if bytes > 0 => config.max_line_bytes = Some(bytes),
+                _ => {
+                    eprintln!("--max-line-bytes requires a positive number of bytes");
+                    process::exit(1);
+                }
+            },
+            "--default-ttl" => match args.next().and_then(|seconds| seconds.parse::<u64>().ok()) {
+                Some(seconds) if seconds > 0 => config.default_ttl = Some(seconds),
+                _ => {
+                    eprintln!("--default-ttl requires a positive number of seconds");
+                    process::exit(1);
+                }
+            },
+            "--max-keys" => match args.next().and_then(|keys| keys.parse::<usize>().ok()) {
+                Some(keys) if keys > 0 => config.max_keys = Some(keys),
+                _ => {
+                    eprintln!("--max-keys requires a positive number of keys");
+                    process::exit(1);
+                }
+            },
+            "--prompt" => match args.next() {
+                Some(text) => prompt = Some(text),
+                None => {
+                    eprintln!("--prompt requires the text to show, e.g. --prompt \"%n> \"");
+                    process::exit(1);
+                }
+            },
+            "--history" => match args.next().and_then(|count| count.parse::<usize>().ok()) {
+                Some(count) => config.history = count,
+                None => {
+                    eprintln!("--history requires a number of values to keep");
+                    process::exit(1);
+                }
+            },
+            "--serve" => match args.next() {
+                Some(addr) => listen_addr = Some(addr),
+                None => {
+                    eprintln!("--serve requires an address, e.g. 127.0.0.1:6400");
+                    process::exit(1);
+                }
+            },
+            "--sweep-interval" => match args.next().and_then(|seconds| seconds.parse::<u64>().ok()) {
+                Some(seconds) if seconds > 0 => sweep_interval = Some(Duration::from_secs(seconds)),
+                _ => {
+                    eprintln!("--sweep-interval requires a positive number of seconds");
+                    process::exit(1);
+                }
+            },
+            other if other.starts_with('-') => {
+                eprintln!("Unknown argument: {other}");
+                process::exit(1);
+            }
+            // The first bare argument is the database file to load on startup
+            other if data_file.is_none() => data_file = Some(other.to_string()),
+            other => {
+                eprintln!("Unexpected argument: {other}");
+                process::exit(1);
+            }
+        }
     }
 
-    // Here we create the main storage for the application
-    // NOTE: Later, this will be selectable from data stored on-disk. So for now it is best placed here
-    let mut store: HashMap<String, String> = HashMap::new();
+    init_logger(verbosity);
 
-    println!("Database has started...");
-    if let Err(e) = rbdb_run(&mut store) {
-        eprintln!("Application Error: {e}");
-        process::exit(1);
+    // The config file only fills in what the flags left unset. Without a file in the current directory there's
+    // nothing to fill in, but a file named with --config has to be there
+    let file = match read_config_file(config_path.as_deref().unwrap_or(DEFAULT_CONFIG_FILE)) {
+        Ok(Some(file)) => file,
+        Ok(None) if config_path.is_some() => {
+            eprintln!("Could not read config file {}: it does not exist", config_path.unwrap_or_default());
+            process::exit(1);
+        }
+        Ok(None) => ConfigFile::default(),
+        Err(e) => {
+            eprintln!("Could not read config file {e}");
+            process::exit(1);
+        }
+    };
+    // Default to the unordered HashMap backend, --backend btree keeps keys sorted instead
+    let backend = backend.or(file.backend).unwrap_or_else(|| "hash".to_string());
+    let data_file = data_file.or(file.data_file);
+    config.max_value_bytes = config.max_value_bytes.or(file.max_value_bytes);
+    config.default_ttl = config.default_ttl.or(file.default_ttl);
+    config.aliases.extend(file.aliases);
+
+    // The flag wins over the environment, which wins over the config file, and with none of them the prompt
+    // stays the default
+    config.prompt = prompt.or_else(|| env::var("RBDB_PROMPT").ok()).or(file.prompt);
+
+    // Escape codes would only get in the way of whatever is reading a pipe or a file
+    config.color = color && io::stdout().is_terminal() && io::stderr().is_terminal();
+
+    // A read-only session has nothing to write back
+    if autosave && !config.read_only {
+        config.autosave = data_file.clone();
+    }
+
+    let mode = match (script, command, listen_addr) {
+        (Some(path), None, None) => Mode::Script(path),
+        (None, Some(query), None) => Mode::Command(query),
+        (None, None, Some(addr)) => Mode::Serve(addr),
+        (None, None, None) => Mode::Repl,
+        _ => {
+            eprintln!("Only one of --script, -c and --serve can be used at a time");
+            process::exit(1);
+        }
+    };
+
+    // Here we create the main storage for the application. Databases made later with USE get the same backend.
+    // With --compress, large values are compressed on their way into it
+    let threshold = compress.then_some(COMPRESS_THRESHOLD);
+    let result = match backend.as_str() {
+        "hash" => {
+            let store = CompressedStore::new(HashMap::<String, Entry>::new(), threshold);
+            start(store, data_file.as_deref(), wal.as_deref(), mode, config, sweep_interval)
+        }
+        "btree" => {
+            config.databases = Databases::new(|| Box::new(BTreeMap::<String, Entry>::new()));
+            let store = CompressedStore::new(BTreeMap::<String, Entry>::new(), threshold);
+            start(store, data_file.as_deref(), wal.as_deref(), mode, config, sweep_interval)
+        }
+        other => {
+            eprintln!("Unknown backend: {other} (expected hash or btree)");
+            process::exit(1);
+        }
+    };
+
+    // Batch runs exit with the status of their last query, so rbdb can be used as a check in a pipeline
+    match result {
+        Ok(status) => process::exit(status.exit_code()),
+        Err(e) => {
+            eprintln!("Application Error: {e}");
+            process::exit(1);
+        }
     }
 }
 
+// What --help prints before the list of commands
+const USAGE: &str = "\
+Usage: rbdb [flags] [data-file]
+
+Starts a prompt on the store, loading data-file first if it's given and saving back to it on exit.
+
+Flags:
+  --config <file>              Read settings from file instead of rbdb.toml
+  --backend <hash|btree>       Storage backend, hash by default
+  --script <file>              Run each line of file as a query, then exit
+  -c, --command <query>        Run a single line, print its result and exit
+  --serve <addr>               Answer queries over TCP, e.g. 127.0.0.1:6400
+  --serve-files                Let --serve clients run SAVE, LOAD, EXPORT, IMPORT and INGEST
+  --wal <file>                 Keep a write-ahead log in file and replay it on startup
+  --no-autosave                Don't save data-file back on exit
+  --readonly                   Refuse every command that would change the store
+  --strict                     Stop a script at its first failing line
+  --ci-keys                    Make keys case-insensitive
+  --reject-control-keys        Refuse keys with control characters in them
+  --max-value-bytes <bytes>    Refuse writes that leave a longer value
+  --max-line-bytes <bytes>     Refuse longer input lines
+  --default-ttl <seconds>      Expire keys this long after a command creates them
+  --max-keys <number>          Evict the least recently used keys past this many
+  --history <number>           Keep this many earlier values of each key for HISTORY
+  --compress                   Compress large values in memory
+  --sweep-interval <seconds>   Remove expired keys in the background this often
+  --seed <number>              Seed the random number generator behind RANDOMKEY
+  --format <plain|json|tsv>    How results are printed
+  --prompt <text>              The prompt to show, with %n for the database and %c for the command count
+  --no-color                   Never color the output
+  --timing                     Print how long each query took
+  -v, -vv                      Log each statement and its outcome to stderr, and with -vv how it parsed
+  -V, --version                Print the version and exit
+  -h, --help                   Print this help and exit
 
+Commands:";
+
+// How the queries reach the database
+enum Mode {
+    Repl,
+    Script(String),
+    // A single line passed with -c
+    Command(String),
+    Serve(String),
+}
+
+// Fills the store from the data file, if one was given, replays the write-ahead log on top of it, and then
+// hands it to whichever mode was asked for
+fn start<S: Storage + Send + 'static>(
+    mut store: S,
+    data_file: Option<&str>,
+    wal: Option<&str>,
+    mode: Mode,
+    config: Config,
+    sweep_interval: Option<Duration>,
+) -> Result<Status, Box<dyn Error>> {
+    if let Some(path) = data_file {
+        match load_store(path, &mut store, &config) {
+            // Notices go to stderr so they never get mixed in with piped query results
+            Ok(count) => eprintln!("Loaded {count} entries from {path}"),
+            // A file that doesn't exist yet is fine, it just means we're starting fresh
+            Err(RbdbError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+                eprintln!("{path} does not exist, starting with an empty store");
+            }
+            Err(e) => return Err(format!("Could not load {path}: {e}").into()),
+        }
+    }
+
+    match wal {
+        Some(path) => {
+            let store = WalStore::open(path, store).map_err(|e| format!("Could not replay {path}: {e}"))?;
+            run(store, mode, config, sweep_interval)
+        }
+        None => run(store, mode, config, sweep_interval),
+    }
+}
+
+fn run<S: Storage + Send + 'static>(
+    store: S,
+    mode: Mode,
+    config: Config,
+    sweep_interval: Option<Duration>,
+) -> Result<Status, Box<dyn Error>> {
+    let store = Arc::new(Mutex::new(store));
+
+    // The sweeper is stopped when it goes out of scope, whichever way we leave this function
+    let _sweeper = sweep_interval.map(|interval| Sweeper::start(Arc::clone(&store), config.notifier.clone(), interval));
+
+    match mode {
+        Mode::Script(path) => {
+            let file = File::open(&path).map_err(|e| format!("Could not open script {path}: {e}"))?;
+            run_script(BufReader::new(file), &mut io::stdout().lock(), &store, &config)
+        }
+        Mode::Command(query) => run_script(io::Cursor::new(query), &mut io::stdout().lock(), &store, &config),
+        Mode::Serve(addr) => {
+            let listener = TcpListener::bind(&addr).map_err(|e| format!("Could not listen on {addr}: {e}"))?;
+            eprintln!("Listening on {}", listener.local_addr()?);
+            serve(listener, store, config)?;
+            Ok(Status::Success)
+        }
+        Mode::Repl => {
+            if io::stdin().is_terminal() {
+                println!("Database has started...");
+            }
+            rbdb_run(&store, &config)?;
+            Ok(Status::Success)
+        }
+    }
+}
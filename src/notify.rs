@@ -0,0 +1,294 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use crate::{glob_match, lock, Entry, Storage};
+
+// Tells SUBSCRIBEd connections about changes to keys matching their patterns, and counts the changes to
+// WATCHed keys. Kept in Config, and clones share the same subscribers, so a write on any connection reaches
+// every one of them
+#[derive(Debug, Clone, Default)]
+pub struct Notifier(Arc<Mutex<Subscribers>>);
+
+#[derive(Debug, Default)]
+struct Subscribers {
+    next_id: u64,
+    // Each pattern a connection subscribed to, with the id of its Subscription and where to send the events
+    patterns: Vec<(u64, String, Sender<String>)>,
+    // Every key some session is watching, by database and key: how many sessions are, and how many times it
+    // has changed since the first of them started. Forgotten once nobody is watching, so unwatched keys cost nothing
+    watched: HashMap<(String, String), (usize, u64)>,
+}
+
+// What happened to a key
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Inserted,
+    Updated,
+    Deleted,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Inserted => write!(f, "inserted"),
+            Event::Updated => write!(f, "updated"),
+            Event::Deleted => write!(f, "deleted"),
+        }
+    }
+}
+
+impl Notifier {
+    // Starts a connection's subscriptions, with every event for it going to events
+    pub fn subscription(&self, events: Sender<String>) -> Subscription {
+        let mut subscribers = lock(&self.0);
+        let id = subscribers.next_id;
+        subscribers.next_id += 1;
+        Subscription { notifier: self.clone(), id, events }
+    }
+
+    // Starts an empty set of watched keys for one session
+    pub fn watch(&self) -> Watch {
+        Watch { notifier: self.clone(), keys: Vec::new() }
+    }
+
+    // How many patterns are subscribed to across every connection
+    pub fn pattern_count(&self) -> usize {
+        lock(&self.0).patterns.len()
+    }
+
+    // Sends an "EVENT <event> <database> <key>" line to every subscriber whose pattern matches key, whichever
+    // database it's in. A subscriber whose receiving end has gone away is dropped rather than sent to again
+    pub(crate) fn notify(&self, event: Event, database: &str, key: &str) {
+        let mut subscribers = lock(&self.0);
+        if let Some((_, version)) = subscribers.watched.get_mut(&(database.to_string(), key.to_string())) {
+            *version += 1;
+        }
+        subscribers
+            .patterns
+            .retain(|(_, pattern, events)| !glob_match(pattern, key) || events.send(format!("EVENT {event} {database} {key}")).is_ok());
+    }
+
+    // Whether nobody is subscribed to or watching anything
+    fn is_empty(&self) -> bool {
+        let subscribers = lock(&self.0);
+        subscribers.patterns.is_empty() && subscribers.watched.is_empty()
+    }
+}
+
+// One connection's hold on the notifier. Dropping it, which happens when the connection closes, removes
+// every pattern it subscribed to
+pub struct Subscription {
+    notifier: Notifier,
+    id: u64,
+    events: Sender<String>,
+}
+
+impl Subscription {
+    pub fn add(&self, pattern: &str) {
+        lock(&self.notifier.0).patterns.push((self.id, pattern.to_string(), self.events.clone()));
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        lock(&self.notifier.0).patterns.retain(|(id, _, _)| *id != self.id);
+    }
+}
+
+// The keys one session has WATCHed, each with the database it's in and how many changes it had seen when it
+// was watched. Dropping it, after a COMMIT or ROLLBACK or when the session ends, stops watching them
+pub struct Watch {
+    notifier: Notifier,
+    keys: Vec<((String, String), u64)>,
+}
+
+impl Watch {
+    pub fn add(&mut self, database: &str, key: &str) {
+        let watched = (database.to_string(), key.to_string());
+        if self.keys.iter().any(|(already, _)| *already == watched) {
+            return;
+        }
+        let mut subscribers = lock(&self.notifier.0);
+        let (watchers, version) = subscribers.watched.entry(watched.clone()).or_default();
+        *watchers += 1;
+        self.keys.push((watched, *version));
+    }
+
+    // The first watched key that has changed since it was watched, if any has. Only changes made through
+    // a Session count, which is every change a client can make
+    pub fn changed(&self) -> Option<&str> {
+        let subscribers = lock(&self.notifier.0);
+        self.keys
+            .iter()
+            .find(|(watched, seen)| subscribers.watched.get(watched).is_some_and(|(_, version)| version != seen))
+            .map(|((_, key), _)| key.as_str())
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        let mut subscribers = lock(&self.notifier.0);
+        for (watched, _) in &self.keys {
+            if let Some((watchers, _)) = subscribers.watched.get_mut(watched) {
+                *watchers -= 1;
+                if *watchers == 0 {
+                    subscribers.watched.remove(watched);
+                }
+            }
+        }
+    }
+}
+
+// A store that tells the notifier about every change made through it, as changes to the database it's named
+// by. Changes that leave the value and expiry as they were, like TOUCH recording an access time, aren't worth
+// an event
+pub(crate) struct Notifying<'a, S: Storage> {
+    pub base: &'a mut S,
+    pub notifier: &'a Notifier,
+    pub database: &'a str,
+}
+
+impl<S: Storage> Storage for Notifying<'_, S> {
+    fn get_entry(&self, key: &str) -> Option<&Entry> {
+        self.base.get_entry(key)
+    }
+
+    fn insert_entry(&mut self, key: String, entry: Entry) -> Option<Entry> {
+        let event = match self.base.get_entry(&key) {
+            None => Some(Event::Inserted),
+            Some(previous) if previous.value != entry.value || previous.expires_at != entry.expires_at => Some(Event::Updated),
+            Some(_) => None,
+        };
+        if let Some(event) = event {
+            self.notifier.notify(event, self.database, &key);
+        }
+        self.base.insert_entry(key, entry)
+    }
+
+    fn remove_entry(&mut self, key: &str) -> Option<Entry> {
+        let previous = self.base.remove_entry(key);
+        if previous.is_some() {
+            self.notifier.notify(Event::Deleted, self.database, key);
+        }
+        previous
+    }
+
+    fn contains_key(&self, key: &str) -> bool {
+        self.base.contains_key(key)
+    }
+
+    fn len(&self) -> usize {
+        self.base.len()
+    }
+
+    fn keys(&self) -> Box<dyn Iterator<Item = &String> + '_> {
+        self.base.keys()
+    }
+
+    fn clear(&mut self) {
+        // Only worth listing the keys when somebody is listening
+        if !self.notifier.is_empty() {
+            let keys: Vec<String> = self.base.keys().cloned().collect();
+            for key in keys {
+                self.notifier.notify(Event::Deleted, self.database, &key);
+            }
+        }
+        self.base.clear()
+    }
+
+    fn range(&self, start: &str, end: &str) -> Vec<(&String, Cow<'_, str>)> {
+        self.base.range(start, end)
+    }
+
+    fn keys_after(&self, after: &str, count: usize) -> Vec<&String> {
+        self.base.keys_after(after, count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.base.flush()
+    }
+
+    fn backend_name(&self) -> &'static str {
+        self.base.backend_name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::mpsc;
+
+    #[test]
+    fn matching_changes_are_sent() {
+        let notifier = Notifier::default();
+        let (events, received) = mpsc::channel();
+        let subscription = notifier.subscription(events);
+        subscription.add("user:*");
+
+        let mut map: HashMap<String, Entry> = HashMap::new();
+        let mut store = Notifying { base: &mut map, notifier: &notifier, database: "default" };
+        store.insert("user:1".to_string(), "a".to_string());
+        store.insert("user:1".to_string(), "b".to_string());
+        store.insert("other".to_string(), "c".to_string());
+        store.remove("user:1");
+        // Same value, so nothing to report
+        store.insert("user:2".to_string(), "d".to_string());
+        store.insert("user:2".to_string(), "d".to_string());
+
+        // Another database's keys match too, and the event says which database it was
+        let mut other: HashMap<String, Entry> = HashMap::new();
+        Notifying { base: &mut other, notifier: &notifier, database: "cache" }.insert("user:1".to_string(), "e".to_string());
+
+        let events: Vec<String> = received.try_iter().collect();
+        let expected = [
+            "EVENT inserted default user:1",
+            "EVENT updated default user:1",
+            "EVENT deleted default user:1",
+            "EVENT inserted default user:2",
+            "EVENT inserted cache user:1",
+        ];
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn watches_see_changes_to_their_keys() {
+        let notifier = Notifier::default();
+        let mut watch = notifier.watch();
+        watch.add("default", "a");
+        let mut map: HashMap<String, Entry> = HashMap::new();
+        let mut other: HashMap<String, Entry> = HashMap::new();
+
+        Notifying { base: &mut map, notifier: &notifier, database: "default" }.insert("b".to_string(), "1".to_string());
+        assert_eq!(watch.changed(), None);
+        // The same key in another database is a different key
+        Notifying { base: &mut other, notifier: &notifier, database: "other" }.insert("a".to_string(), "1".to_string());
+        assert_eq!(watch.changed(), None);
+        let mut store = Notifying { base: &mut map, notifier: &notifier, database: "default" };
+        store.insert("a".to_string(), "1".to_string());
+        assert_eq!(watch.changed(), Some("a"));
+
+        // A key is only tracked while somebody watches it
+        drop(watch);
+        assert!(lock(&notifier.0).watched.is_empty());
+    }
+
+    #[test]
+    fn dropping_the_subscription_unsubscribes() {
+        let notifier = Notifier::default();
+        let (events, received) = mpsc::channel();
+        let subscription = notifier.subscription(events);
+        subscription.add("*");
+        subscription.add("a");
+        assert_eq!(notifier.pattern_count(), 2);
+
+        drop(subscription);
+        assert_eq!(notifier.pattern_count(), 0);
+        // Every sender went with it, so the receiving end sees the channel close
+        assert!(received.recv().is_err());
+    }
+}
@@ -0,0 +1,88 @@
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// A small SplitMix64 generator. Nothing here needs cryptographic randomness, just keys picked evenly
+// and a way to get the same picks again in tests
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn seeded(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    // Seeded from the clock, for when nobody asked for a particular sequence
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+        Rng::seeded(nanos)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // A number in 0..n with every value equally likely. Plain % n would favour the low numbers slightly,
+    // so draws from the uneven tail at the top of the range are thrown away
+    pub fn below(&mut self, n: u64) -> u64 {
+        assert!(n > 0, "Rng::below needs a non-empty range");
+        let zone = u64::MAX - u64::MAX % n;
+        loop {
+            let x = self.next_u64();
+            if x < zone {
+                return x % n;
+            }
+        }
+    }
+}
+
+// The generator kept in Config. Clones share the same generator, so every connection and query draws
+// from one sequence instead of each starting it over
+#[derive(Debug, Clone)]
+pub struct SharedRng(Arc<Mutex<Rng>>);
+
+impl SharedRng {
+    pub fn seeded(seed: u64) -> Self {
+        SharedRng(Arc::new(Mutex::new(Rng::seeded(seed))))
+    }
+
+    pub fn below(&self, n: u64) -> u64 {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).below(n)
+    }
+}
+
+impl Default for SharedRng {
+    fn default() -> Self {
+        SharedRng(Arc::new(Mutex::new(Rng::from_time())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let mut a = Rng::seeded(42);
+        let mut b = Rng::seeded(42);
+        let first: Vec<u64> = (0..5).map(|_| a.next_u64()).collect();
+        let second: Vec<u64> = (0..5).map(|_| b.next_u64()).collect();
+        assert_eq!(first, second);
+        assert_ne!(Rng::seeded(43).next_u64(), first[0]);
+    }
+
+    #[test]
+    fn below_stays_in_range() {
+        let mut rng = Rng::seeded(7);
+        let mut seen = [false; 3];
+        for _ in 0..100 {
+            seen[rng.below(3) as usize] = true;
+        }
+        assert_eq!(seen, [true; 3]);
+    }
+}
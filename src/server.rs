@@ -0,0 +1,90 @@
+use std::io::{self, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use crate::{describe_error, lock, read_next_line, split_statements, Config, RbdbError, Session, Storage};
+
+// Accepts connections forever, giving each one its own thread. Every connection works on the same store,
+// so a key inserted by one client is visible to all the others
+pub fn serve<S: Storage + Send + 'static>(listener: TcpListener, store: Arc<Mutex<S>>, config: Config) -> io::Result<()> {
+    let config = Arc::new(config);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            // A connection that failed before we got it only affects that client
+            Err(e) => {
+                eprintln!("Failed to accept connection: {e}");
+                continue;
+            }
+        };
+
+        let store = Arc::clone(&store);
+        let config = Arc::clone(&config);
+        thread::spawn(move || {
+            if let Err(e) = handle_client(stream, &store, &config) {
+                eprintln!("Connection closed with an error: {e}");
+            }
+        });
+    }
+    Ok(())
+}
+
+// Reads one query per line and writes the reply straight back. Unlike the REPL, errors and misses go
+// to the client too, since the server's stderr is nowhere near them
+fn handle_client<S: Storage>(stream: TcpStream, store: &Mutex<S>, config: &Config) -> io::Result<()> {
+    // Replies and SUBSCRIBE events both write to the client, a line at a time so they never interleave
+    let out = Arc::new(Mutex::new(stream.try_clone()?));
+    let mut reader = BufReader::new(stream);
+
+    // Events are passed on from their own thread, so they arrive even while the client isn't sending anything.
+    // It finishes once the session, and with it every sender, is dropped at the end of the connection
+    let (events, received) = mpsc::channel::<String>();
+    let event_out = Arc::clone(&out);
+    thread::spawn(move || {
+        for event in received {
+            if writeln!(lock(&event_out), "{event}").is_err() {
+                break;
+            }
+        }
+    });
+
+    // A transaction belongs to the connection that opened it, and is dropped if the client goes away mid-way.
+    // The same goes for its subscriptions. Files on the server are out of reach unless --serve-files says otherwise
+    let mut session = Session::new(store).with_subscription(config.notifier.subscription(events)).with_files(config.serve_files);
+
+    loop {
+        let line = match read_next_line(&mut reader, config.max_line_bytes) {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(RbdbError::Io(e)) => return Err(e),
+            // A client sending lines that long is misbehaving, so it's told why and disconnected
+            Err(e) => {
+                writeln!(lock(&out), "{}", describe_error(&e))?;
+                break;
+            }
+        };
+        let trimmed = line.trim();
+
+        if trimmed.is_empty() {
+            continue;
+        }
+        if trimmed == "quit" || trimmed == "exit" {
+            break;
+        }
+
+        // Every statement on the line gets its own reply, in order
+        let statements = split_statements(trimmed);
+        let numbered = statements.len() > 1;
+        for (index, statement) in statements.into_iter().enumerate() {
+            let reply = match session.execute(statement, config) {
+                Ok(result) => result.to_string(),
+                Err(e) if numbered => format!("Statement {}: {}", index + 1, describe_error(&e)),
+                Err(e) => describe_error(&e),
+            };
+            writeln!(lock(&out), "{reply}")?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,702 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::Mutex;
+
+use crate::notify::Notifying;
+use crate::{execute_query, lock, log_outcome, parse_statement, process_query, Config, Entry, Subscription, Watch, DEFAULT_DATABASE, Query, QueryResult, QueryType, RbdbError, Storage};
+
+// One client's view of a shared store: the REPL, a script and each TCP connection all get their own.
+// It's what remembers things between lines, like whether a transaction is open
+pub struct Session<'a, S: Storage> {
+    store: &'a Mutex<S>,
+    // The writes made since BEGIN, which nobody else sees until COMMIT
+    transaction: Option<Transaction>,
+    // The keys WATCH is keeping an eye on until the next COMMIT or ROLLBACK
+    watch: Option<Watch>,
+    // Which database USE last switched to
+    database: String,
+    // What PREFIX last set, if it hasn't been cleared since
+    prefix: Option<String>,
+    // Where SUBSCRIBE sends this session's events. Only a --serve connection has somewhere to show them
+    subscription: Option<Subscription>,
+    // Whether SAVE, LOAD and the other commands that read or write files may run
+    files: bool,
+    // The changes UNDO can reverse, most recent last
+    undo: VecDeque<UndoStep>,
+}
+
+impl<'a, S: Storage> Session<'a, S> {
+    pub fn new(store: &'a Mutex<S>) -> Self {
+        Session { store, transaction: None, watch: None, database: DEFAULT_DATABASE.to_string(), prefix: None, subscription: None, files: true, undo: VecDeque::new() }
+    }
+
+    // Lets SUBSCRIBE work, with the events going wherever the subscription sends them
+    pub fn with_subscription(mut self, subscription: Subscription) -> Self {
+        self.subscription = Some(subscription);
+        self
+    }
+
+    // Lets the session's queries reach the filesystem or not. A --serve connection only can with --serve-files
+    pub fn with_files(mut self, allowed: bool) -> Self {
+        self.files = allowed;
+        self
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    // The database the session's queries go to, DEFAULT_DATABASE until USE picks another
+    pub fn database(&self) -> &str {
+        &self.database
+    }
+
+    // Parses and runs a single statement. Inside a transaction it runs against the buffered writes
+    // layered over the store, so it sees its own changes while the store itself is left alone
+    pub fn execute(&mut self, line: &str, config: &Config) -> Result<QueryResult, RbdbError> {
+        let outcome = self.execute_statement(line, config);
+        log_outcome(line, &outcome);
+        outcome
+    }
+
+    fn execute_statement(&mut self, line: &str, config: &Config) -> Result<QueryResult, RbdbError> {
+        let query = parse_statement(line, config)?;
+        let scoped;
+        let config = match &self.prefix {
+            Some(prefix) => {
+                scoped = Config { key_prefix: Some(prefix.clone()), ..config.clone() };
+                &scoped
+            }
+            None => config,
+        };
+
+        match query.q_type {
+            q_type if q_type.uses_files() && !self.files => return Err(RbdbError::FilesNotServed),
+            // Answered here rather than by process_query, which may already be holding the databases' lock
+            QueryType::DbList => return Ok(QueryResult::Keys(config.databases.names())),
+            // A transaction's writes belong to the database it was started in
+            QueryType::Use if self.in_transaction() => return Err(RbdbError::TransactionInProgress),
+            QueryType::Use => {
+                let name = query.key().to_string();
+                if name != DEFAULT_DATABASE {
+                    config.databases.with(&name, |_| ());
+                }
+                self.database = name.clone();
+                return Ok(QueryResult::Using(name));
+            }
+            // A bare PREFIX, or an empty one, goes back to using keys as they're given
+            QueryType::Prefix => {
+                self.prefix = query.arg(0).filter(|prefix| !prefix.is_empty()).cloned();
+                return Ok(QueryResult::PrefixSet(self.prefix.clone()));
+            }
+            QueryType::Subscribe => {
+                let subscription = self.subscription.as_ref().ok_or(RbdbError::NotServing)?;
+                subscription.add(query.key());
+                return Ok(QueryResult::Subscribed(query.key().to_string()));
+            }
+            // Watching only makes sense before the transaction starts, since its writes aren't seen until COMMIT
+            QueryType::Watch if self.in_transaction() => return Err(RbdbError::TransactionInProgress),
+            QueryType::Watch => {
+                let keys = query.scoped(config);
+                keys.validate_keys(config)?;
+                let watch = self.watch.get_or_insert_with(|| config.notifier.watch());
+                for key in &keys.args {
+                    watch.add(&self.database, key);
+                }
+                return Ok(QueryResult::Watching(query.args.clone()));
+            }
+            // A transaction's writes haven't reached the store yet, so ROLLBACK is the way to take them back
+            QueryType::Undo if self.in_transaction() => return Err(RbdbError::TransactionInProgress),
+            QueryType::Undo => return self.undo(config),
+            _ => {}
+        }
+
+        // Every change that reaches a store, including a COMMIT's, goes past the notifier on the way, and the
+        // entries it replaces are kept so UNDO can put them back
+        let transaction = &mut self.transaction;
+        let watch = &mut self.watch;
+        let notifier = &config.notifier;
+        let database = self.database.as_str();
+        let (result, previous) = if database == DEFAULT_DATABASE {
+            let mut store = lock(self.store);
+            let mut recording = Recording::new(Notifying { base: &mut *store, notifier, database });
+            let result = run(&query, &mut recording, transaction, watch, config);
+            (result, recording.previous)
+        } else {
+            config.databases.with(database, |store| {
+                let mut recording = Recording::new(Notifying { base: store, notifier, database });
+                let result = run(&query, &mut recording, transaction, watch, config);
+                (result, recording.previous)
+            })
+        };
+
+        // A read can drop an expired key, but that's tidying up rather than a change anyone would want undone
+        if (query.q_type.is_mutating() || query.q_type == QueryType::Commit) && !previous.is_empty() {
+            if self.undo.len() == UNDO_DEPTH {
+                self.undo.pop_front();
+            }
+            self.undo.push_back(UndoStep { statement: line.trim().to_string(), database: self.database.clone(), previous });
+        }
+        result
+    }
+
+    // Puts back the entries the most recent change replaced, in whichever database it was made
+    fn undo(&mut self, config: &Config) -> Result<QueryResult, RbdbError> {
+        let step = self.undo.pop_back().ok_or(RbdbError::NothingToUndo)?;
+        let notifier = &config.notifier;
+        if step.database == DEFAULT_DATABASE {
+            let mut store = lock(self.store);
+            restore(&mut Notifying { base: &mut *store, notifier, database: DEFAULT_DATABASE }, step.previous)?;
+        } else {
+            let database = step.database.as_str();
+            config.databases.with(database, |store| restore(&mut Notifying { base: store, notifier, database }, step.previous))?;
+        }
+        Ok(QueryResult::Undone(step.statement))
+    }
+}
+
+// How many changes UNDO can step back through. Past that the oldest is forgotten
+const UNDO_DEPTH: usize = 100;
+
+// What one statement changed, as the entries its keys had before it ran
+struct UndoStep {
+    // The statement as it was typed, so UNDO can say what it undid
+    statement: String,
+    database: String,
+    // None marks a key that didn't exist
+    previous: HashMap<String, Option<Entry>>,
+}
+
+fn restore<S: Storage>(store: &mut S, previous: HashMap<String, Option<Entry>>) -> Result<(), RbdbError> {
+    for (key, entry) in previous {
+        match entry {
+            Some(entry) => {
+                store.insert_entry(key, entry);
+            }
+            None => {
+                store.remove_entry(&key);
+            }
+        }
+    }
+    store.flush()?;
+    Ok(())
+}
+
+// Runs a query against whichever database the session is using, opening and closing transactions as it goes.
+// Ending a transaction either way also stops watching its keys
+fn run<S: Storage>(
+    query: &Query,
+    store: &mut S,
+    transaction: &mut Option<Transaction>,
+    watch: &mut Option<Watch>,
+    config: &Config,
+) -> Result<QueryResult, RbdbError> {
+    match (query.q_type, transaction.take()) {
+        (QueryType::Begin, Some(open)) => {
+            *transaction = Some(open);
+            Err(RbdbError::TransactionInProgress)
+        }
+        (QueryType::Begin, None) => {
+            *transaction = Some(Transaction::default());
+            Ok(QueryResult::Began)
+        }
+        (QueryType::Commit, Some(open)) => {
+            // Checked while the store is locked, so nothing can change a watched key between here and the writes
+            if let Some(key) = watch.take().as_ref().and_then(Watch::changed) {
+                return Err(RbdbError::WatchedKeyChanged(key.to_string()));
+            }
+            let count = open.apply(store);
+            store.flush()?;
+            Ok(QueryResult::Committed(count))
+        }
+        (QueryType::Rollback, Some(open)) => {
+            *watch = None;
+            Ok(QueryResult::RolledBack(open.writes.len()))
+        }
+        (QueryType::Commit | QueryType::Rollback, None) => Err(RbdbError::NoTransaction),
+        (_, Some(mut open)) => {
+            let result = process_query(query, &mut Overlay { base: &*store, transaction: &mut open }, config);
+            *transaction = Some(open);
+            result
+        }
+        (_, None) => execute_query(query, store, config),
+    }
+}
+
+// The writes buffered by an open transaction. None marks a key the transaction deleted
+#[derive(Default)]
+struct Transaction {
+    writes: HashMap<String, Option<Entry>>,
+    // Set by CLEAR, after which nothing in the store underneath shows through
+    cleared: bool,
+}
+
+impl Transaction {
+    // Makes every buffered write for real, returning how many keys it touched
+    fn apply<S: Storage>(self, store: &mut S) -> usize {
+        if self.cleared {
+            store.clear();
+        }
+        let count = self.writes.len();
+        for (key, write) in self.writes {
+            match write {
+                Some(entry) => {
+                    store.insert_entry(key, entry);
+                }
+                None => {
+                    store.remove_entry(&key);
+                }
+            }
+        }
+        count
+    }
+}
+
+// A store that remembers what each key held before the first change made to it through here, which is all UNDO
+// needs to reverse any command, however many keys it touched
+struct Recording<S: Storage> {
+    base: S,
+    previous: HashMap<String, Option<Entry>>,
+}
+
+impl<S: Storage> Recording<S> {
+    fn new(base: S) -> Self {
+        Recording { base, previous: HashMap::new() }
+    }
+
+    fn remember(&mut self, key: &str) {
+        if !self.previous.contains_key(key) {
+            self.previous.insert(key.to_string(), self.base.get_entry(key).cloned());
+        }
+    }
+}
+
+impl<S: Storage> Storage for Recording<S> {
+    fn get_entry(&self, key: &str) -> Option<&Entry> {
+        self.base.get_entry(key)
+    }
+
+    fn insert_entry(&mut self, key: String, entry: Entry) -> Option<Entry> {
+        self.remember(&key);
+        self.base.insert_entry(key, entry)
+    }
+
+    fn remove_entry(&mut self, key: &str) -> Option<Entry> {
+        self.remember(key);
+        self.base.remove_entry(key)
+    }
+
+    fn contains_key(&self, key: &str) -> bool {
+        self.base.contains_key(key)
+    }
+
+    fn len(&self) -> usize {
+        self.base.len()
+    }
+
+    fn keys(&self) -> Box<dyn Iterator<Item = &String> + '_> {
+        self.base.keys()
+    }
+
+    fn clear(&mut self) {
+        let keys: Vec<String> = self.base.keys().cloned().collect();
+        for key in keys {
+            self.remember(&key);
+        }
+        self.base.clear()
+    }
+
+    fn range(&self, start: &str, end: &str) -> Vec<(&String, Cow<'_, str>)> {
+        self.base.range(start, end)
+    }
+
+    fn keys_after(&self, after: &str, count: usize) -> Vec<&String> {
+        self.base.keys_after(after, count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.base.flush()
+    }
+
+    fn backend_name(&self) -> &'static str {
+        self.base.backend_name()
+    }
+}
+
+// The store as a transaction sees it: its own writes first, then whatever it hasn't touched underneath
+struct Overlay<'a, S: Storage> {
+    base: &'a S,
+    transaction: &'a mut Transaction,
+}
+
+impl<S: Storage> Storage for Overlay<'_, S> {
+    fn get_entry(&self, key: &str) -> Option<&Entry> {
+        match self.transaction.writes.get(key) {
+            Some(write) => write.as_ref(),
+            None if self.transaction.cleared => None,
+            None => self.base.get_entry(key),
+        }
+    }
+
+    fn insert_entry(&mut self, key: String, entry: Entry) -> Option<Entry> {
+        let previous = self.get_entry(&key).cloned();
+        self.transaction.writes.insert(key, Some(entry));
+        previous
+    }
+
+    fn remove_entry(&mut self, key: &str) -> Option<Entry> {
+        // Deleting a key that isn't there changes nothing, so there's nothing to buffer
+        let previous = self.get_entry(key).cloned();
+        if previous.is_some() {
+            self.transaction.writes.insert(key.to_string(), None);
+        }
+        previous
+    }
+
+    fn len(&self) -> usize {
+        self.keys().count()
+    }
+
+    fn keys(&self) -> Box<dyn Iterator<Item = &String> + '_> {
+        let writes = &self.transaction.writes;
+        let untouched = self
+            .base
+            .keys()
+            .filter(move |key| !self.transaction.cleared && !writes.contains_key(*key));
+        let written = writes.iter().filter(|(_, write)| write.is_some()).map(|(key, _)| key);
+        Box::new(untouched.chain(written))
+    }
+
+    fn clear(&mut self) {
+        self.transaction.writes.clear();
+        self.transaction.cleared = true;
+    }
+
+    fn backend_name(&self) -> &'static str {
+        self.base.backend_name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run<S: Storage>(session: &mut Session<S>, line: &str) -> QueryResult {
+        session.execute(line, &Config::default()).unwrap()
+    }
+
+    #[test]
+    fn commit_applies_the_writes() {
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        store.lock().unwrap().insert("gone".to_string(), Entry::new("soon"));
+        let mut session = Session::new(&store);
+
+        assert_eq!(run(&mut session, "BEGIN"), QueryResult::Began);
+        run(&mut session, "INSERT a 1");
+        run(&mut session, "INCR counter");
+        run(&mut session, "DELETE gone");
+        assert!(store.lock().unwrap().contains_key("gone"));
+        assert!(!store.lock().unwrap().contains_key("a"));
+
+        assert_eq!(run(&mut session, "COMMIT"), QueryResult::Committed(3));
+        assert!(!session.in_transaction());
+        let store = store.into_inner().unwrap();
+        assert_eq!(store.get("a").unwrap().value, "1");
+        assert_eq!(store.get("counter").unwrap().value, "1");
+        assert!(!store.contains_key("gone"));
+    }
+
+    #[test]
+    fn rollback_discards_the_writes() {
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        store.lock().unwrap().insert("kept".to_string(), Entry::new("yes"));
+        let mut session = Session::new(&store);
+
+        run(&mut session, "BEGIN");
+        run(&mut session, "INSERT a 1");
+        run(&mut session, "CLEAR");
+        assert_eq!(run(&mut session, "ROLLBACK"), QueryResult::RolledBack(0));
+
+        let store = store.into_inner().unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get("kept").unwrap().value, "yes");
+    }
+
+    #[test]
+    fn reads_see_the_transactions_writes() {
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        store.lock().unwrap().insert("b".to_string(), Entry::new("2"));
+        let mut session = Session::new(&store);
+
+        run(&mut session, "BEGIN");
+        run(&mut session, "INSERT a 1");
+        run(&mut session, "UPDATE b 20");
+        assert_eq!(run(&mut session, "SELECT a"), QueryResult::Found { key: "a".to_string(), value: "1".to_string() });
+        assert_eq!(run(&mut session, "SELECT b"), QueryResult::Found { key: "b".to_string(), value: "20".to_string() });
+        assert_eq!(run(&mut session, "COUNT"), QueryResult::Count(2));
+
+        run(&mut session, "DELETE a");
+        assert_eq!(run(&mut session, "SELECT a"), QueryResult::NotFound("a".to_string()));
+        assert_eq!(run(&mut session, "KEYS"), QueryResult::Keys(vec!["b".to_string()]));
+
+        // Another session on the same store still sees it as it was
+        let mut other = Session::new(&store);
+        assert_eq!(run(&mut other, "SELECT b"), QueryResult::Found { key: "b".to_string(), value: "2".to_string() });
+    }
+
+    #[test]
+    fn transaction_commands_out_of_order() {
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let mut session = Session::new(&store);
+
+        assert!(matches!(session.execute("COMMIT", &Config::default()), Err(RbdbError::NoTransaction)));
+        assert!(matches!(session.execute("ROLLBACK", &Config::default()), Err(RbdbError::NoTransaction)));
+        run(&mut session, "BEGIN");
+        assert!(matches!(session.execute("BEGIN", &Config::default()), Err(RbdbError::TransactionInProgress)));
+        assert!(session.in_transaction());
+    }
+
+    #[test]
+    fn databases_are_isolated() {
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let config = Config::default();
+        let mut session = Session::new(&store);
+
+        session.execute("INSERT shared default", &config).unwrap();
+        assert_eq!(session.execute("USE other", &config).unwrap(), QueryResult::Using("other".to_string()));
+        assert_eq!(session.database(), "other");
+        assert_eq!(session.execute("SELECT shared", &config).unwrap(), QueryResult::NotFound("shared".to_string()));
+        session.execute("INSERT shared other", &config).unwrap();
+        session.execute("INSERT only_other 1", &config).unwrap();
+        assert_eq!(session.execute("COUNT", &config).unwrap(), QueryResult::Count(2));
+
+        // The store rbdb was started with never sees the other database's keys
+        assert_eq!(store.lock().unwrap().len(), 1);
+        session.execute("USE default", &config).unwrap();
+        assert_eq!(session.execute("SELECT shared", &config).unwrap(), QueryResult::Found { key: "shared".to_string(), value: "default".to_string() });
+        assert_eq!(session.execute("EXISTS only_other", &config).unwrap(), QueryResult::Bool(false));
+
+        // A second session that switches to the same name sees the same keys
+        let mut other = Session::new(&store);
+        other.execute("USE other", &config).unwrap();
+        assert_eq!(other.execute("SELECT shared", &config).unwrap(), QueryResult::Found { key: "shared".to_string(), value: "other".to_string() });
+    }
+
+    #[test]
+    fn dblist_shows_every_database() {
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let config = Config::default();
+        let mut session = Session::new(&store);
+
+        assert_eq!(session.execute("DBLIST", &config).unwrap(), QueryResult::Keys(vec!["default".to_string()]));
+        session.execute("USE zeta", &config).unwrap();
+        session.execute("USE alpha", &config).unwrap();
+        let names = vec!["alpha".to_string(), "default".to_string(), "zeta".to_string()];
+        assert_eq!(session.execute("DBLIST", &config).unwrap(), QueryResult::Keys(names));
+    }
+
+    #[test]
+    fn use_inside_a_transaction() {
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let config = Config::default();
+        let mut session = Session::new(&store);
+
+        session.execute("USE other", &config).unwrap();
+        session.execute("BEGIN", &config).unwrap();
+        session.execute("INSERT a 1", &config).unwrap();
+        assert!(matches!(session.execute("USE default", &config), Err(RbdbError::TransactionInProgress)));
+        assert_eq!(session.execute("COMMIT", &config).unwrap(), QueryResult::Committed(1));
+
+        // The commit went to the database the transaction was started in
+        assert!(store.lock().unwrap().is_empty());
+        assert_eq!(session.execute("SELECT a", &config).unwrap(), QueryResult::Found { key: "a".to_string(), value: "1".to_string() });
+    }
+
+    #[test]
+    fn commit_aborts_when_a_watched_key_changes() {
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        store.lock().unwrap().insert("balance".to_string(), Entry::new("10"));
+        let config = Config::default();
+        let mut session = Session::new(&store);
+        let mut other = Session::new(&store);
+
+        assert_eq!(session.execute("WATCH balance", &config).unwrap(), QueryResult::Watching(vec!["balance".to_string()]));
+        session.execute("BEGIN", &config).unwrap();
+        session.execute("INCRBY balance -5", &config).unwrap();
+        other.execute("UPDATE balance 100", &config).unwrap();
+
+        let result = session.execute("COMMIT", &config);
+        assert!(matches!(result, Err(RbdbError::WatchedKeyChanged(key)) if key == "balance"));
+        // The transaction is gone, and the other session's write stands
+        assert!(!session.in_transaction());
+        assert_eq!(store.lock().unwrap().get("balance").unwrap().value, "100");
+
+        // The watch ended with the transaction, so the next one goes through
+        session.execute("BEGIN", &config).unwrap();
+        session.execute("INCRBY balance -5", &config).unwrap();
+        other.execute("UPDATE balance 200", &config).unwrap();
+        assert_eq!(session.execute("COMMIT", &config).unwrap(), QueryResult::Committed(1));
+    }
+
+    #[test]
+    fn commit_ignores_changes_to_unwatched_keys() {
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let config = Config::default();
+        let mut session = Session::new(&store);
+        let mut other = Session::new(&store);
+
+        session.execute("WATCH a", &config).unwrap();
+        session.execute("BEGIN", &config).unwrap();
+        session.execute("INSERT a 1", &config).unwrap();
+        other.execute("INSERT b 2", &config).unwrap();
+        assert_eq!(session.execute("COMMIT", &config).unwrap(), QueryResult::Committed(1));
+
+        // Too late once the transaction has started
+        session.execute("BEGIN", &config).unwrap();
+        assert!(matches!(session.execute("WATCH a", &config), Err(RbdbError::TransactionInProgress)));
+    }
+
+    #[test]
+    fn watches_belong_to_a_database() {
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let config = Config::default();
+        let mut session = Session::new(&store);
+        let mut other = Session::new(&store);
+
+        // The same key name in the default database is a different key
+        session.execute("USE other", &config).unwrap();
+        session.execute("WATCH balance", &config).unwrap();
+        session.execute("BEGIN", &config).unwrap();
+        session.execute("INSERT balance 5", &config).unwrap();
+        other.execute("INSERT balance 100", &config).unwrap();
+        assert_eq!(session.execute("COMMIT", &config).unwrap(), QueryResult::Committed(1));
+
+        session.execute("WATCH balance", &config).unwrap();
+        session.execute("BEGIN", &config).unwrap();
+        other.execute("USE other", &config).unwrap();
+        other.execute("UPDATE balance 6", &config).unwrap();
+        assert!(matches!(session.execute("COMMIT", &config), Err(RbdbError::WatchedKeyChanged(_))));
+    }
+
+    #[test]
+    fn prefix_scopes_keys() {
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let mut session = Session::new(&store);
+        run(&mut session, "INSERT user outside");
+
+        assert_eq!(run(&mut session, "PREFIX app:"), QueryResult::PrefixSet(Some("app:".to_string())));
+        run(&mut session, "INSERT user inside");
+        run(&mut session, "INCR hits");
+        assert_eq!(run(&mut session, "SELECT user"), QueryResult::Found { key: "user".to_string(), value: "inside".to_string() });
+        assert_eq!(store.lock().unwrap().get("app:user").unwrap().value, "inside");
+
+        assert_eq!(run(&mut session, "PREFIX"), QueryResult::PrefixSet(None));
+        assert_eq!(run(&mut session, "SELECT user"), QueryResult::Found { key: "user".to_string(), value: "outside".to_string() });
+        assert_eq!(run(&mut session, "SELECT app:hits"), QueryResult::Found { key: "app:hits".to_string(), value: "1".to_string() });
+    }
+
+    #[test]
+    fn prefix_views_of_keys_and_count() {
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let mut session = Session::new(&store);
+        run(&mut session, "MSET app:a 1 app:b 2 other 3");
+        run(&mut session, "PREFIX app:");
+
+        let short = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(run(&mut session, "KEYS"), QueryResult::Keys(short));
+        assert_eq!(run(&mut session, "KEYS b"), QueryResult::Keys(vec!["b".to_string()]));
+        assert_eq!(run(&mut session, "COUNT"), QueryResult::Count(2));
+
+        // ALL looks past the prefix at the store as it really is
+        let full = vec!["app:a".to_string(), "app:b".to_string(), "other".to_string()];
+        assert_eq!(run(&mut session, "KEYS * ALL"), QueryResult::Keys(full));
+        assert_eq!(run(&mut session, "COUNT ALL"), QueryResult::Count(3));
+        assert!(matches!(session.execute("COUNT some", &Config::default()), Err(RbdbError::UnknownOption(_))));
+    }
+
+    #[test]
+    fn prefix_keeps_to_its_namespace() {
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let mut session = Session::new(&store);
+        run(&mut session, "INSERT other 1");
+        run(&mut session, "PREFIX app:");
+
+        // Results name keys the way the session does
+        assert_eq!(run(&mut session, "INSERT a 1").to_string(), "SUCCESS: Inserted a:1 into database");
+        assert_eq!(run(&mut session, "UPDATE a 2").to_string(), "SUCCESS: Updated a with 2");
+        assert_eq!(run(&mut session, "RENAME a b").to_string(), "SUCCESS: Renamed a to b");
+        assert_eq!(run(&mut session, "DELETE b").to_string(), "SUCCESS: Deleted b");
+        run(&mut session, "INSERT a 1");
+
+        // DUMP and RANDOMKEY only see the namespace, and CLEAR only empties it
+        assert_eq!(run(&mut session, "DUMP"), QueryResult::Dump(vec![("a".to_string(), "1".to_string())]));
+        assert_eq!(run(&mut session, "RANDOMKEY"), QueryResult::Previous(Some("a".to_string())));
+        assert_eq!(run(&mut session, "CLEAR"), QueryResult::Cleared(1));
+        assert_eq!(run(&mut session, "RANDOMKEY"), QueryResult::Previous(None));
+        run(&mut session, "PREFIX");
+        assert_eq!(run(&mut session, "COUNT"), QueryResult::Count(1));
+        assert_eq!(run(&mut session, "SELECT other").to_string(), "1");
+    }
+
+    #[test]
+    fn undo_reverts_the_last_change() {
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let config = Config::default();
+        let mut session = Session::new(&store);
+        let value = |key: &str| store.lock().unwrap().get(key).map(|entry| entry.value.as_str().into_owned());
+
+        assert!(matches!(session.execute("UNDO", &config), Err(RbdbError::NothingToUndo)));
+
+        // An insert is undone by removing the key
+        run(&mut session, "INSERT a 1");
+        assert_eq!(run(&mut session, "UNDO"), QueryResult::Undone("INSERT a 1".to_string()));
+        assert_eq!(value("a"), None);
+
+        // An update by putting the old value back, and a delete by putting the key back
+        run(&mut session, "INSERT a 1");
+        run(&mut session, "UPDATE a 2");
+        run(&mut session, "SELECT a");
+        assert_eq!(run(&mut session, "UNDO").to_string(), "SUCCESS: Undid UPDATE a 2");
+        assert_eq!(value("a"), Some("1".to_string()));
+        run(&mut session, "DELETE a");
+        run(&mut session, "UNDO");
+        assert_eq!(value("a"), Some("1".to_string()));
+
+        // Each UNDO steps one change further back, whichever keys the changes touched
+        run(&mut session, "MSET b 2 c 3");
+        run(&mut session, "CLEAR");
+        run(&mut session, "UNDO");
+        assert_eq!(store.lock().unwrap().len(), 3);
+        run(&mut session, "UNDO");
+        assert_eq!((value("a"), value("b"), value("c")), (Some("1".to_string()), None, None));
+        run(&mut session, "UNDO");
+        assert!(matches!(session.execute("UNDO", &config), Err(RbdbError::NothingToUndo)));
+        assert_eq!(value("a"), None);
+    }
+
+    #[test]
+    fn undo_follows_the_change_to_its_database() {
+        let store: Mutex<HashMap<String, Entry>> = Mutex::new(HashMap::new());
+        let config = Config::default();
+        let mut session = Session::new(&store);
+
+        session.execute("USE other", &config).unwrap();
+        session.execute("INSERT a 1", &config).unwrap();
+        session.execute("USE default", &config).unwrap();
+        session.execute("INSERT a 1", &config).unwrap();
+        session.execute("BEGIN", &config).unwrap();
+        assert!(matches!(session.execute("UNDO", &config), Err(RbdbError::TransactionInProgress)));
+        session.execute("UPDATE a 2", &config).unwrap();
+        session.execute("COMMIT", &config).unwrap();
+
+        // A whole transaction is undone as one
+        assert_eq!(session.execute("UNDO", &config).unwrap(), QueryResult::Undone("COMMIT".to_string()));
+        assert_eq!(store.lock().unwrap().get("a").unwrap().value, "1");
+        session.execute("UNDO", &config).unwrap();
+        assert!(!store.lock().unwrap().contains_key("a"));
+        session.execute("UNDO", &config).unwrap();
+        session.execute("USE other", &config).unwrap();
+        assert_eq!(session.execute("COUNT", &config).unwrap(), QueryResult::Count(0));
+    }
+}
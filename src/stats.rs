@@ -0,0 +1,67 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Counters for STATS, kept in Config. Clones share the same counters, so every connection adds to one set
+// of numbers. They're only ever added to or zeroed, so plain atomics are enough
+#[derive(Debug, Clone, Default)]
+pub struct Stats(Arc<Counters>);
+
+#[derive(Debug, Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    reads: AtomicU64,
+    writes: AtomicU64,
+    evictions: AtomicU64,
+}
+
+// The counters as they stood at one moment
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct StatsSnapshot {
+    // SELECTs that found their key, and ones that didn't
+    pub hits: u64,
+    pub misses: u64,
+    // Every query that only looks at the store, and every one that changes it
+    pub reads: u64,
+    pub writes: u64,
+    // Keys pushed out to stay under --max-keys
+    pub evictions: u64,
+}
+
+impl Stats {
+    pub fn record_hit(&self) {
+        self.0.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_miss(&self) {
+        self.0.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_read(&self) {
+        self.0.reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_write(&self) {
+        self.0.writes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_evictions(&self, count: usize) {
+        self.0.evictions.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            hits: self.0.hits.load(Ordering::Relaxed),
+            misses: self.0.misses.load(Ordering::Relaxed),
+            reads: self.0.reads.load(Ordering::Relaxed),
+            writes: self.0.writes.load(Ordering::Relaxed),
+            evictions: self.0.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn reset(&self) {
+        for counter in [&self.0.hits, &self.0.misses, &self.0.reads, &self.0.writes, &self.0.evictions] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io;
+use std::ops::Bound;
+
+use crate::Value;
+
+// What the store actually holds for each key: the value itself plus any metadata that goes with it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub value: Value,
+    // Unix time in milliseconds after which the entry counts as gone. None means it never expires
+    pub expires_at: Option<u64>,
+    // Unix time in milliseconds when TOUCH last marked the entry as used. None if it never has
+    pub accessed_at: Option<u64>,
+    // The values the key held before, newest first, for HISTORY. Only kept with --history
+    pub history: VecDeque<String>,
+}
+
+impl Entry {
+    pub fn new(value: impl Into<String>) -> Self {
+        Entry { value: Value::Plain(value.into()), expires_at: None, accessed_at: None, history: VecDeque::new() }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+}
+
+// The backend the query engine reads from and writes to. Anything that can hold String keys and Entry values
+// can implement this and be dropped into process_query/rbdb_run in place of the default HashMap.
+// Backends only have to provide the entry-level methods, the plain String get/insert/remove come for free
+pub trait Storage {
+    fn get_entry(&self, key: &str) -> Option<&Entry>;
+
+    // Returns the previous entry if the key was already present
+    fn insert_entry(&mut self, key: String, entry: Entry) -> Option<Entry>;
+
+    fn remove_entry(&mut self, key: &str) -> Option<Entry>;
+
+    // Borrowed straight from the store unless the value is kept compressed
+    fn get(&self, key: &str) -> Option<Cow<'_, str>> {
+        self.get_entry(key).map(|entry| entry.value.as_str())
+    }
+
+    // Stores a plain value with no expiry, replacing whatever entry was there before
+    fn insert(&mut self, key: String, value: String) -> Option<String> {
+        self.insert_entry(key, Entry::new(value)).map(|entry| entry.value.into_string())
+    }
+
+    fn remove(&mut self, key: &str) -> Option<String> {
+        self.remove_entry(key).map(|entry| entry.value.into_string())
+    }
+
+    fn contains_key(&self, key: &str) -> bool {
+        self.get_entry(key).is_some()
+    }
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // No ordering is promised here, callers that need one should sort
+    fn keys(&self) -> Box<dyn Iterator<Item = &String> + '_>;
+
+    fn clear(&mut self);
+
+    // Every entry whose key falls between start and end inclusive, in key order. The default walks
+    // the whole keyspace and sorts, ordered backends should override it with something cheaper
+    fn range(&self, start: &str, end: &str) -> Vec<(&String, Cow<'_, str>)> {
+        let mut entries: Vec<(&String, Cow<'_, str>)> = self
+            .keys()
+            .filter(|key| key.as_str() >= start && key.as_str() <= end)
+            .filter_map(|key| self.get(key).map(|value| (key, value)))
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    // Up to count keys that sort after the given key, in key order. SCAN pages through the store with it.
+    // Like range, the default sorts the whole keyspace and ordered backends should override it
+    fn keys_after(&self, after: &str, count: usize) -> Vec<&String> {
+        let mut keys: Vec<&String> = self.keys().filter(|key| key.as_str() > after).collect();
+        keys.sort();
+        keys.truncate(count);
+        keys
+    }
+
+    // Called after every query. A backend that writes its changes somewhere as it goes reports any failure
+    // here, the in-memory ones have nothing to do
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    // What INFO calls this backend. Wrappers like the write-ahead log report the backend underneath them
+    fn backend_name(&self) -> &'static str {
+        "custom"
+    }
+}
+
+impl Storage for HashMap<String, Entry> {
+    fn get_entry(&self, key: &str) -> Option<&Entry> {
+        HashMap::get(self, key)
+    }
+
+    fn insert_entry(&mut self, key: String, entry: Entry) -> Option<Entry> {
+        HashMap::insert(self, key, entry)
+    }
+
+    fn remove_entry(&mut self, key: &str) -> Option<Entry> {
+        HashMap::remove(self, key)
+    }
+
+    fn contains_key(&self, key: &str) -> bool {
+        HashMap::contains_key(self, key)
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+
+    fn keys(&self) -> Box<dyn Iterator<Item = &String> + '_> {
+        Box::new(HashMap::keys(self))
+    }
+
+    fn clear(&mut self) {
+        HashMap::clear(self)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "hash"
+    }
+}
+
+// Keeps keys in sorted order, so iteration and range scans come back ordered without an extra sort
+impl Storage for BTreeMap<String, Entry> {
+    fn get_entry(&self, key: &str) -> Option<&Entry> {
+        BTreeMap::get(self, key)
+    }
+
+    fn insert_entry(&mut self, key: String, entry: Entry) -> Option<Entry> {
+        BTreeMap::insert(self, key, entry)
+    }
+
+    fn remove_entry(&mut self, key: &str) -> Option<Entry> {
+        BTreeMap::remove(self, key)
+    }
+
+    fn contains_key(&self, key: &str) -> bool {
+        BTreeMap::contains_key(self, key)
+    }
+
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+
+    fn keys(&self) -> Box<dyn Iterator<Item = &String> + '_> {
+        Box::new(BTreeMap::keys(self))
+    }
+
+    fn clear(&mut self) {
+        BTreeMap::clear(self)
+    }
+
+    fn backend_name(&self) -> &'static str {
+        "btree"
+    }
+
+    fn range(&self, start: &str, end: &str) -> Vec<(&String, Cow<'_, str>)> {
+        // BTreeMap::range panics on an inverted range, so treat it as empty instead
+        if start > end {
+            return Vec::new();
+        }
+        BTreeMap::range::<str, _>(self, (Bound::Included(start), Bound::Included(end)))
+            .map(|(key, entry)| (key, entry.value.as_str()))
+            .collect()
+    }
+
+    fn keys_after(&self, after: &str, count: usize) -> Vec<&String> {
+        BTreeMap::range::<str, _>(self, (Bound::Excluded(after), Bound::Unbounded)).map(|(key, _)| key).take(count).collect()
+    }
+}
+
+// Lets a boxed backend, like the ones USE keeps for each extra database, go anywhere a Storage is expected
+impl<S: Storage + ?Sized> Storage for Box<S> {
+    fn get_entry(&self, key: &str) -> Option<&Entry> {
+        (**self).get_entry(key)
+    }
+
+    fn insert_entry(&mut self, key: String, entry: Entry) -> Option<Entry> {
+        (**self).insert_entry(key, entry)
+    }
+
+    fn remove_entry(&mut self, key: &str) -> Option<Entry> {
+        (**self).remove_entry(key)
+    }
+
+    fn contains_key(&self, key: &str) -> bool {
+        (**self).contains_key(key)
+    }
+
+    fn len(&self) -> usize {
+        (**self).len()
+    }
+
+    fn keys(&self) -> Box<dyn Iterator<Item = &String> + '_> {
+        (**self).keys()
+    }
+
+    fn clear(&mut self) {
+        (**self).clear()
+    }
+
+    fn range(&self, start: &str, end: &str) -> Vec<(&String, Cow<'_, str>)> {
+        (**self).range(start, end)
+    }
+
+    fn keys_after(&self, after: &str, count: usize) -> Vec<&String> {
+        (**self).keys_after(after, count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn backend_name(&self) -> &'static str {
+        (**self).backend_name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill<S: Storage>(store: &mut S) {
+        for key in ["pear", "apple", "fig", "banana"] {
+            store.insert(key.to_string(), "fruit".to_string());
+        }
+    }
+
+    #[test]
+    fn btree_keys_are_sorted() {
+        let mut store: BTreeMap<String, Entry> = BTreeMap::new();
+        fill(&mut store);
+        let keys: Vec<&String> = Storage::keys(&store).collect();
+        assert_eq!(keys, vec!["apple", "banana", "fig", "pear"]);
+    }
+
+    #[test]
+    fn range_is_inclusive_and_sorted() {
+        let mut hash: HashMap<String, Entry> = HashMap::new();
+        let mut btree: BTreeMap<String, Entry> = BTreeMap::new();
+        fill(&mut hash);
+        fill(&mut btree);
+        let expected = vec!["apple", "banana", "fig"];
+        let hash_keys: Vec<&String> = Storage::range(&hash, "apple", "fig").into_iter().map(|(k, _)| k).collect();
+        let btree_keys: Vec<&String> = Storage::range(&btree, "apple", "fig").into_iter().map(|(k, _)| k).collect();
+        assert_eq!(hash_keys, expected);
+        assert_eq!(btree_keys, expected);
+        assert!(Storage::range(&btree, "z", "a").is_empty());
+    }
+
+    #[test]
+    fn backends_agree() {
+        let mut hash: HashMap<String, Entry> = HashMap::new();
+        let mut btree: BTreeMap<String, Entry> = BTreeMap::new();
+        fill(&mut hash);
+        fill(&mut btree);
+        assert_eq!(Storage::remove(&mut hash, "fig"), Storage::remove(&mut btree, "fig"));
+        assert_eq!(Storage::len(&hash), Storage::len(&btree));
+
+        let mut hash_keys: Vec<&String> = Storage::keys(&hash).collect();
+        hash_keys.sort();
+        let btree_keys: Vec<&String> = Storage::keys(&btree).collect();
+        assert_eq!(hash_keys, btree_keys);
+    }
+
+    #[test]
+    fn entry_expiry() {
+        let mut entry = Entry::new("value");
+        assert!(!entry.is_expired(u64::MAX));
+        entry.expires_at = Some(1_000);
+        assert!(!entry.is_expired(999));
+        assert!(entry.is_expired(1_000));
+    }
+}
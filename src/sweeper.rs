@@ -0,0 +1,75 @@
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::notify::Notifying;
+use crate::{expire_all, lock, now_millis, Notifier, Storage, DEFAULT_DATABASE};
+
+// Evicts expired entries from a shared store on a background thread, so keys that are never read
+// again don't sit around using memory. Each removal goes past the notifier like any other deletion, as one
+// from the default database. The thread is stopped and joined when the Sweeper is dropped
+pub struct Sweeper {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Sweeper {
+    pub fn start<S: Storage + Send + 'static>(store: Arc<Mutex<S>>, notifier: Notifier, interval: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+
+        let handle = thread::spawn(move || {
+            // Waiting on the channel doubles as the sleep between sweeps, and wakes up as soon as we're told to stop
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let mut store = lock(&store);
+                expire_all(&mut Notifying { base: &mut *store, notifier: &notifier, database: DEFAULT_DATABASE }, now_millis());
+            }
+        });
+
+        Sweeper { stop: Some(stop), handle: Some(handle) }
+    }
+}
+
+impl Drop for Sweeper {
+    fn drop(&mut self) {
+        // Dropping the sender disconnects the channel, which ends the loop
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Entry;
+    use std::collections::HashMap;
+
+    #[test]
+    fn sweeps_expired_keys() {
+        let mut map: HashMap<String, Entry> = HashMap::new();
+        map.insert("short".to_string(), Entry { expires_at: Some(now_millis() + 20), ..Entry::new("lived") });
+        map.insert("forever".to_string(), Entry::new("value"));
+        let store = Arc::new(Mutex::new(map));
+
+        let notifier = Notifier::default();
+        let (events, received) = mpsc::channel();
+        let subscription = notifier.subscription(events);
+        subscription.add("*");
+        let sweeper = Sweeper::start(Arc::clone(&store), notifier, Duration::from_millis(10));
+        thread::sleep(Duration::from_millis(200));
+        // Subscribers hear about it like any other deletion
+        assert_eq!(received.try_iter().collect::<Vec<_>>(), vec!["EVENT deleted default short"]);
+
+        // Looking straight at the map rather than going through a query, so nothing lazily expired it
+        let map = store.lock().unwrap();
+        assert!(!map.contains_key("short"));
+        assert!(map.contains_key("forever"));
+        drop(map);
+
+        // Once the sweeper is gone its thread has let go of the store
+        drop(sweeper);
+        assert_eq!(Arc::strong_count(&store), 1);
+    }
+}
@@ -0,0 +1,178 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::compress;
+
+// A stored value. Large ones may be kept compressed, but every way of reading one hands back the original
+// string, so nothing outside this file needs to know which it is
+#[derive(Debug, Clone)]
+pub enum Value {
+    Plain(String),
+    // len is the length of the original string in bytes, so STRLEN doesn't have to decompress it
+    Compressed { bytes: Vec<u8>, len: usize },
+    // Raw bytes from SETB, which needn't be UTF-8. Read as a string they're shown with any invalid sequences
+    // replaced, so only as_bytes gets them back exactly
+    Bytes(Vec<u8>),
+}
+
+impl Value {
+    // The value compressed if it's longer than threshold bytes and compressing it actually saves something
+    pub fn compressed(self, threshold: usize) -> Value {
+        match self {
+            Value::Plain(text) if text.len() > threshold => {
+                let bytes = compress::compress(text.as_bytes());
+                if bytes.len() < text.len() {
+                    Value::Compressed { bytes, len: text.len() }
+                } else {
+                    Value::Plain(text)
+                }
+            }
+            other => other,
+        }
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        matches!(self, Value::Compressed { .. })
+    }
+
+    pub fn is_bytes(&self) -> bool {
+        matches!(self, Value::Bytes(_))
+    }
+
+    pub fn as_str(&self) -> Cow<'_, str> {
+        match self {
+            Value::Plain(text) => Cow::Borrowed(text),
+            Value::Compressed { bytes, .. } => Cow::Owned(decompress(bytes)),
+            Value::Bytes(bytes) => String::from_utf8_lossy(bytes),
+        }
+    }
+
+    pub fn into_string(self) -> String {
+        match self {
+            Value::Plain(text) => text,
+            Value::Compressed { bytes, .. } => decompress(&bytes),
+            Value::Bytes(bytes) => String::from_utf8(bytes).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()),
+        }
+    }
+
+    // The value exactly as it was stored, whether or not it's text
+    pub fn as_bytes(&self) -> Cow<'_, [u8]> {
+        match self {
+            Value::Bytes(bytes) => Cow::Borrowed(bytes),
+            Value::Plain(text) => Cow::Borrowed(text.as_bytes()),
+            Value::Compressed { bytes, .. } => Cow::Owned(decompress(bytes).into_bytes()),
+        }
+    }
+
+    // The length of the original value in bytes
+    pub fn len(&self) -> usize {
+        match self {
+            Value::Plain(text) => text.len(),
+            Value::Compressed { len, .. } => *len,
+            Value::Bytes(bytes) => bytes.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // How many bytes the value takes up as it's kept, which for a compressed one is less than len
+    pub fn stored_len(&self) -> usize {
+        match self {
+            Value::Compressed { bytes, .. } => bytes.len(),
+            other => other.len(),
+        }
+    }
+}
+
+// Only Value::compressed makes compressed bytes, always from a whole String, so they can't fail to come back
+fn decompress(bytes: &[u8]) -> String {
+    let bytes = compress::decompress(bytes).expect("compressed values are only made by Value::compressed");
+    String::from_utf8(bytes).expect("compressed values are only made from strings")
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Self {
+        Value::Plain(text)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Self {
+        Value::Plain(text.to_string())
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(bytes: Vec<u8>) -> Self {
+        Value::Bytes(bytes)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+// Two values are equal when they read back the same bytes, however each one happens to be stored
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.len() == other.len() && self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl PartialEq<str> for Value {
+    fn eq(&self, other: &str) -> bool {
+        self.len() == other.len() && *self.as_bytes() == *other.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for Value {
+    fn eq(&self, other: &&str) -> bool {
+        self == *other
+    }
+}
+
+impl PartialEq<String> for Value {
+    fn eq(&self, other: &String) -> bool {
+        self == other.as_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_values_round_trip_compressed() {
+        let text = "all work and no play makes jack a dull boy. ".repeat(200);
+        let value = Value::from(text.clone()).compressed(64);
+        assert!(value.is_compressed());
+        assert!(matches!(&value, Value::Compressed { bytes, .. } if bytes.len() < text.len() / 10));
+        assert_eq!(value.len(), text.len());
+        assert_eq!(value.as_str(), text);
+        assert_eq!(value.into_string(), text);
+    }
+
+    #[test]
+    fn bytes_read_back_exactly() {
+        let value = Value::from(vec![b'o', b'k', 0xFF, 0x00]);
+        assert_eq!(value.as_bytes(), &[b'o', b'k', 0xFF, 0x00][..]);
+        assert_eq!(value.len(), 4);
+        // The text view can't hold 0xFF, so it gets a replacement character
+        assert_eq!(value.as_str(), "ok\u{FFFD}\0");
+        assert_ne!(value, Value::from("ok\u{FFFD}\0"));
+        assert_eq!(Value::from(b"text".to_vec()), Value::from("text"));
+    }
+
+    #[test]
+    fn small_or_incompressible_values_stay_plain() {
+        assert!(!Value::from("short").compressed(64).is_compressed());
+        // No repeats to find, so compressing would only make it longer
+        let rng = crate::SharedRng::seeded(7);
+        let noise: String = (0..200).map(|_| char::from(b'!' + rng.below(90) as u8)).collect();
+        assert!(!Value::from(noise).compressed(64).is_compressed());
+    }
+}
@@ -0,0 +1,204 @@
+use std::borrow::Cow;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+
+use crate::{base64, escape_dump_field, unescape_dump_field, Entry, RbdbError, Storage, Value};
+
+// A Storage that writes every change to an append-only log before handing it to the store underneath, and
+// replays that log when it's opened again. Between the two, a crash loses nothing that was acknowledged.
+//
+// Each record is one line, with fields escaped the same way as DUMP:
+//   SET <key> <value> <expires_at or ->
+//   SETB <key> <value as base64> <expires_at or ->   (for values from SETB, which may not be text)
+//   DEL <key>
+//   CLEAR
+pub struct WalStore<S: Storage> {
+    inner: S,
+    log: File,
+    // Storage methods can't fail, so a failed write is kept here until flush reports it
+    error: Option<io::Error>,
+}
+
+impl<S: Storage> WalStore<S> {
+    // Replays the log at path into inner, then keeps appending to it. A missing log is created empty
+    pub fn open(path: &str, mut inner: S) -> Result<Self, RbdbError> {
+        let contents = match std::fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        // Only whole lines count. Anything after the last newline is a record that was cut off mid-write
+        let complete = contents.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        let text = String::from_utf8_lossy(&contents[..complete]);
+        for (line_number, line) in text.lines().enumerate() {
+            apply_record(line, &mut inner)
+                .ok_or_else(|| RbdbError::MalformedFile { path: path.to_string(), line: line_number + 1 })?;
+        }
+
+        let log = OpenOptions::new().create(true).append(true).open(path)?;
+        // Drop the torn tail so the next record starts on a fresh line
+        log.set_len(complete as u64)?;
+
+        Ok(WalStore { inner, log, error: None })
+    }
+
+    fn append(&mut self, record: String) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.log.write_all(record.as_bytes()) {
+            self.error = Some(e);
+        }
+    }
+}
+
+fn apply_record<S: Storage>(line: &str, store: &mut S) -> Option<()> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    match fields.as_slice() {
+        [kind @ ("SET" | "SETB"), key, value, expires_at] => {
+            let expires_at = match *expires_at {
+                "-" => None,
+                millis => Some(millis.parse().ok()?),
+            };
+            let value = match *kind {
+                "SETB" => Value::from(base64::decode(value)?),
+                _ => Value::from(unescape_dump_field(value)?),
+            };
+            // Access times aren't logged, so a replayed entry starts out never having been touched
+            let entry = Entry { value, expires_at, ..Entry::new(String::new()) };
+            store.insert_entry(unescape_dump_field(key)?, entry);
+        }
+        ["DEL", key] => {
+            store.remove_entry(&unescape_dump_field(key)?);
+        }
+        ["CLEAR"] => store.clear(),
+        _ => return None,
+    }
+    Some(())
+}
+
+impl<S: Storage> Storage for WalStore<S> {
+    fn get_entry(&self, key: &str) -> Option<&Entry> {
+        self.inner.get_entry(key)
+    }
+
+    fn insert_entry(&mut self, key: String, entry: Entry) -> Option<Entry> {
+        let expires_at = entry.expires_at.map_or("-".to_string(), |millis| millis.to_string());
+        let record = if entry.value.is_bytes() {
+            format!("SETB\t{}\t{}\t{}\n", escape_dump_field(&key), base64::encode(&entry.value.as_bytes()), expires_at)
+        } else {
+            format!("SET\t{}\t{}\t{}\n", escape_dump_field(&key), escape_dump_field(&entry.value.as_str()), expires_at)
+        };
+        self.append(record);
+        self.inner.insert_entry(key, entry)
+    }
+
+    fn remove_entry(&mut self, key: &str) -> Option<Entry> {
+        // Removing a key that isn't there changes nothing, so there's nothing to log
+        if self.inner.contains_key(key) {
+            self.append(format!("DEL\t{}\n", escape_dump_field(key)));
+        }
+        self.inner.remove_entry(key)
+    }
+
+    fn contains_key(&self, key: &str) -> bool {
+        self.inner.contains_key(key)
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn keys(&self) -> Box<dyn Iterator<Item = &String> + '_> {
+        self.inner.keys()
+    }
+
+    fn clear(&mut self) {
+        self.append("CLEAR\n".to_string());
+        self.inner.clear()
+    }
+
+    fn range(&self, start: &str, end: &str) -> Vec<(&String, Cow<'_, str>)> {
+        self.inner.range(start, end)
+    }
+
+    fn keys_after(&self, after: &str, count: usize) -> Vec<&String> {
+        self.inner.keys_after(after, count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn backend_name(&self) -> &'static str {
+        self.inner.backend_name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn temp_log(name: &str) -> String {
+        let path = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_file(&path);
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn replays_after_restart() {
+        let path = temp_log("rbdb_wal_replay.log");
+
+        let mut wal = WalStore::open(&path, HashMap::<String, Entry>::new()).unwrap();
+        wal.insert("kept".to_string(), "tab\there".to_string());
+        wal.insert("gone".to_string(), "soon".to_string());
+        wal.insert_entry("expiring".to_string(), Entry { expires_at: Some(1_234), ..Entry::new("v") });
+        wal.insert_entry("binary".to_string(), Entry { value: Value::from(vec![0xFF, b'\n', 0x00]), ..Entry::new("") });
+        wal.remove("gone");
+        assert!(wal.flush().is_ok());
+        let before = wal.inner.clone();
+        drop(wal);
+
+        // A fresh process would start from an empty store and the log alone
+        let recovered = WalStore::open(&path, HashMap::<String, Entry>::new()).unwrap();
+        assert_eq!(recovered.inner, before);
+        assert_eq!(recovered.get("kept").unwrap(), "tab\there");
+        assert!(!recovered.contains_key("gone"));
+        assert_eq!(recovered.get_entry("binary").unwrap().value.as_bytes(), &[0xFF, b'\n', 0x00][..]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn drops_a_torn_last_record() {
+        let path = temp_log("rbdb_wal_torn.log");
+        std::fs::write(&path, "SET\ta\t1\t-\nCLEAR\nSET\tb\t2\t-\nSET\tc\thalf wri").unwrap();
+
+        let mut wal = WalStore::open(&path, HashMap::<String, Entry>::new()).unwrap();
+        assert_eq!(Storage::len(&wal), 1);
+        assert_eq!(wal.get("b").unwrap(), "2");
+
+        // Later records still land on their own line
+        wal.insert("d".to_string(), "4".to_string());
+        drop(wal);
+        let wal = WalStore::open(&path, HashMap::<String, Entry>::new()).unwrap();
+        assert_eq!(wal.get("d").unwrap(), "4");
+        assert!(!wal.contains_key("c"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_corrupt_record() {
+        let path = temp_log("rbdb_wal_corrupt.log");
+        std::fs::write(&path, "SET\ta\t1\t-\nBOGUS\nSET\tb\t2\t-\n").unwrap();
+        let result = WalStore::open(&path, HashMap::<String, Entry>::new());
+        assert!(matches!(result, Err(RbdbError::MalformedFile { line: 2, .. })));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,37 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// Runs the real binary against a data file, piping input to the prompt as if someone had typed it
+fn session(args: &[&str], input: &str) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rbdb")).args(args).stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::null()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn changes_are_saved_on_exit() {
+    let path = std::env::temp_dir().join("rbdb_autosave.db");
+    let _ = std::fs::remove_file(&path);
+    let path = path.to_str().unwrap();
+
+    session(&[path], "INSERT a 1\nINSERT b 2\nquit\n");
+    session(&[path], "UPDATE a 10\nDELETE b\n");
+
+    let contents = std::fs::read_to_string(path).unwrap();
+    assert!(contents.starts_with("#rbdb:escaped\na\t10\n"));
+    assert!(!contents.contains("b\t"));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn no_autosave_leaves_the_file_alone() {
+    let path = std::env::temp_dir().join("rbdb_no_autosave.db");
+    std::fs::write(&path, "a\t1\n").unwrap();
+    let path = path.to_str().unwrap();
+
+    session(&["--no-autosave", path], "UPDATE a 10\nquit\n");
+    assert_eq!(std::fs::read_to_string(path).unwrap(), "a\t1\n");
+
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use common::{rbdb, rbdb_in};
+
+#[test]
+fn version_prints_the_crate_version() {
+    for flag in ["--version", "-V"] {
+        let (stdout, _, code) = rbdb(&[flag]);
+        assert_eq!(stdout, format!("rbdb {}\n", env!("CARGO_PKG_VERSION")));
+        assert_eq!(code, 0);
+    }
+}
+
+#[test]
+fn help_lists_flags_and_commands() {
+    for flag in ["--help", "-h"] {
+        let (stdout, _, code) = rbdb(&[flag]);
+        assert_eq!(code, 0);
+        assert!(stdout.starts_with("Usage: rbdb [flags] [data-file]\n"));
+        assert!(stdout.contains("  --max-keys <number>"));
+        assert!(stdout.contains("\nINSERT key value "));
+        assert!(stdout.contains("\nHELP [command] "));
+    }
+
+    // It wins over everything else on the line, so nothing is run
+    let (stdout, _, code) = rbdb(&["-c", "INSERT a b", "--help"]);
+    assert!(stdout.starts_with("Usage:"));
+    assert_eq!(code, 0);
+}
+
+#[test]
+fn verbosity_raises_the_log_level() {
+    // Nothing is logged by default
+    let (stdout, stderr, _) = rbdb(&["-c", "INSERT a b"]);
+    assert_eq!(stdout, "SUCCESS: Inserted a:b into database\n");
+    assert_eq!(stderr, "");
+
+    // -v logs each statement's outcome, on stderr so the result on stdout is the same as ever
+    let (stdout, stderr, _) = rbdb(&["-v", "-c", "INSERT a b; SELECT zz"]);
+    assert_eq!(stdout, "SUCCESS: Inserted a:b into database\n");
+    assert!(stderr.contains(r#"[INFO] "INSERT a b" -> Inserted { key: "a", value: "b" }"#), "{stderr}");
+    assert!(stderr.contains(r#"[INFO] "SELECT zz" -> NotFound("zz")"#), "{stderr}");
+    assert!(!stderr.contains("[DEBUG]"), "{stderr}");
+
+    // -vv adds how each one parsed
+    let (_, stderr, _) = rbdb(&["-vv", "-c", "INSERT a b"]);
+    assert!(stderr.contains(r#"[DEBUG] "INSERT a b" parsed as Insert with args ["a", "b"]"#), "{stderr}");
+    let (_, stderr, _) = rbdb(&["-v", "-v", "-c", "BOGUS"]);
+    assert!(stderr.contains(r#"[INFO] "BOGUS" failed: Invalid query type: BOGUS"#), "{stderr}");
+}
+
+#[test]
+fn flags_win_over_the_config_file_which_wins_over_defaults() {
+    let dir = std::env::temp_dir().join("rbdb_config_precedence");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let query = "INSERT a 12345; TTL a; INFO";
+
+    // With no rbdb.toml, the defaults: no TTL, no value limit, the hash backend
+    let (stdout, _, code) = rbdb_in(&dir, &["-c", query]);
+    assert_eq!(code, 0);
+    assert!(stdout.starts_with("SUCCESS: Inserted a:12345 into database\n-1\n"), "{stdout}");
+    assert!(stdout.contains("backend:hash"), "{stdout}");
+
+    // rbdb.toml in the current directory is read without being asked for
+    std::fs::write(dir.join("rbdb.toml"), "backend = \"btree\"\ndefault_ttl = 60\nmax_value_bytes = 3\n").unwrap();
+    let (stdout, stderr, _) = rbdb_in(&dir, &["-c", query]);
+    assert!(stderr.contains("Value is 5 bytes, over the limit of 3"), "{stderr}");
+    let (stdout_ttl, _, _) = rbdb_in(&dir, &["-c", "INSERT a 1; TTL a"]);
+    assert_eq!(stdout_ttl, "SUCCESS: Inserted a:1 into database\n60\n");
+    assert!(stdout.contains("backend:btree"), "{stdout}");
+
+    // Each flag overrides the file's setting, leaving the rest of the file in force
+    let (stdout, _, code) = rbdb_in(&dir, &["--max-value-bytes", "10", "--default-ttl", "5", "-c", query]);
+    assert_eq!(code, 0);
+    assert!(stdout.starts_with("SUCCESS: Inserted a:12345 into database\n5\n"), "{stdout}");
+    assert!(stdout.contains("backend:btree"), "{stdout}");
+    let (stdout, _, _) = rbdb_in(&dir, &["--backend", "hash", "-c", "INFO"]);
+    assert!(stdout.contains("backend:hash"), "{stdout}");
+
+    // --config reads another file instead
+    std::fs::write(dir.join("other.toml"), "default_ttl = 7\n").unwrap();
+    let (stdout, _, _) = rbdb_in(&dir, &["--config", "other.toml", "-c", query]);
+    assert!(stdout.starts_with("SUCCESS: Inserted a:12345 into database\n7\n"), "{stdout}");
+    assert!(stdout.contains("backend:hash"), "{stdout}");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_bad_config_file_stops_startup() {
+    let dir = std::env::temp_dir().join("rbdb_config_malformed");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    std::fs::write(dir.join("rbdb.toml"), "# settings\nbackend = btree\n").unwrap();
+    let (stdout, stderr, code) = rbdb_in(&dir, &["-c", "INSERT a b"]);
+    assert_eq!(code, 1);
+    assert_eq!(stdout, "");
+    assert_eq!(stderr, "Could not read config file rbdb.toml: line 2: backend must be a quoted string\n");
+
+    // A flag missing its value is reported as such, not as an empty one
+    let (_, stderr, code) = rbdb_in(&dir, &["--config", "missing.toml", "--backend"]);
+    assert_eq!(code, 1);
+    assert_eq!(stderr, "--backend requires hash or btree\n");
+
+    // A file that isn't there is only an error when it was asked for by name
+    let (_, stderr, code) = rbdb_in(&dir, &["--config", "missing.toml", "-c", "INSERT a b"]);
+    assert_eq!(code, 1);
+    assert_eq!(stderr, "Could not read config file missing.toml: it does not exist\n");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
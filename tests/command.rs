@@ -0,0 +1,45 @@
+mod common;
+
+use common::rbdb;
+
+#[test]
+fn one_shot_query_prints_its_result() {
+    let (stdout, _, code) = rbdb(&["-c", "INSERT a b"]);
+    assert_eq!(stdout, "SUCCESS: Inserted a:b into database\n");
+    assert_eq!(code, 0);
+}
+
+#[test]
+fn one_shot_query_against_a_data_file() {
+    let path = std::env::temp_dir().join("rbdb_one_shot.db");
+    std::fs::write(&path, "greeting\thello\n").unwrap();
+    let path = path.to_str().unwrap();
+
+    let (stdout, _, code) = rbdb(&[path, "-c", "SELECT greeting"]);
+    assert_eq!(stdout, "hello\n");
+    assert_eq!(code, 0);
+
+    // Nothing is written back, so the query sees the file as it was
+    let (stdout, _, code) = rbdb(&["-c", "SELECT missing", path]);
+    assert_eq!(stdout, "");
+    assert_eq!(code, 2);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn misses_and_errors_are_reported_once_by_the_caller() {
+    // process_query only returns them. The one line each gets comes from the front end, which keeps results on
+    // stdout and problems on stderr, so a printed message from the engine as well would show up as a second line
+    let cases = [
+        ("SELECT missing", "No entry found for key = missing\n", 2),
+        ("INSERT a", "Query processing failed: Query requires a value, but none was provided\n", 1),
+        ("UPDATE missing 1", "Query processing failed: No entry found for key = missing\n", 1),
+    ];
+    for (query, message, expected_code) in cases {
+        let (stdout, stderr, code) = rbdb(&["-c", query]);
+        assert_eq!(stdout, "", "{query}");
+        assert_eq!(stderr, message, "{query}");
+        assert_eq!(code, expected_code, "{query}");
+    }
+}
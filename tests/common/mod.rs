@@ -0,0 +1,16 @@
+// Shared by the test files that run the real binary. Each one uses only some of what's here
+#![allow(dead_code)]
+
+use std::path::Path;
+use std::process::Command;
+
+// Runs rbdb with the given arguments and hands back its stdout, stderr and exit code
+pub fn rbdb(args: &[&str]) -> (String, String, i32) {
+    rbdb_in(Path::new("."), args)
+}
+
+// The same, run from dir, for the tests that depend on what's in the current directory
+pub fn rbdb_in(dir: &Path, args: &[&str]) -> (String, String, i32) {
+    let output = Command::new(env!("CARGO_BIN_EXE_rbdb")).args(args).current_dir(dir).output().unwrap();
+    (String::from_utf8(output.stdout).unwrap(), String::from_utf8(output.stderr).unwrap(), output.status.code().unwrap())
+}
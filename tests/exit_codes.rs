@@ -0,0 +1,62 @@
+mod common;
+
+use common::rbdb;
+
+// Runs the real binary with the given arguments and hands back its exit code
+fn exit_code(args: &[&str]) -> i32 {
+    rbdb(args).2
+}
+
+#[test]
+fn success_is_zero() {
+    assert_eq!(exit_code(&["-c", "INSERT a 1; SELECT a"]), 0);
+}
+
+#[test]
+fn failure_is_one() {
+    assert_eq!(exit_code(&["-c", "DELETE missing"]), 1);
+    assert_eq!(exit_code(&["-c", "FROBNICATE"]), 1);
+}
+
+#[test]
+fn not_found_is_two() {
+    assert_eq!(exit_code(&["-c", "SELECT missing"]), 2);
+}
+
+#[test]
+fn only_the_last_query_counts() {
+    assert_eq!(exit_code(&["-c", "SELECT missing; INSERT a 1"]), 0);
+
+    let script = std::env::temp_dir().join("rbdb_exit_code_script.rbdb");
+    std::fs::write(&script, "INSERT a 1\nSELECT b\n").unwrap();
+    assert_eq!(exit_code(&["--script", script.to_str().unwrap()]), 2);
+    std::fs::remove_file(&script).unwrap();
+}
+
+#[test]
+fn a_missing_data_file_starts_empty() {
+    let path = std::env::temp_dir().join("rbdb_exit_code_missing.db");
+    let _ = std::fs::remove_file(&path);
+    let path = path.to_str().unwrap();
+
+    let (stdout, stderr, code) = rbdb(&[path, "-c", "COUNT"]);
+    assert_eq!(stdout, "0\n");
+    assert_eq!(stderr, format!("{path} does not exist, starting with an empty store\n"));
+    assert_eq!(code, 0);
+}
+
+#[test]
+fn an_unreadable_data_file_is_an_error() {
+    let path = std::env::temp_dir().join("rbdb_exit_code_unreadable.db");
+    std::fs::write(&path, [0xFF, 0xFE, b'\t', 0x80]).unwrap();
+    let path = path.to_str().unwrap();
+
+    // Nothing runs, rather than carrying on with an empty store that would be saved over the file
+    let (stdout, stderr, code) = rbdb(&[path, "-c", "INSERT a 1"]);
+    assert_eq!(stdout, "");
+    assert!(stderr.starts_with(&format!("Application Error: Could not load {path}: ")), "{stderr}");
+    assert_eq!(code, 1);
+    assert_eq!(std::fs::read(path).unwrap(), [0xFF, 0xFE, b'\t', 0x80]);
+
+    std::fs::remove_file(path).unwrap();
+}
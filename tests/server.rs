@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use rbdb::{serve, Config, Entry};
+
+// Binds to port 0 so the OS picks a free port, then runs the server in the background
+fn start_server() -> std::net::SocketAddr {
+    start_server_with(Config::default())
+}
+
+fn start_server_with(config: Config) -> std::net::SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let store: Arc<Mutex<HashMap<String, Entry>>> = Arc::new(Mutex::new(HashMap::new()));
+    thread::spawn(move || serve(listener, store, config));
+    addr
+}
+
+fn send(reader: &mut BufReader<TcpStream>, query: &str) -> String {
+    writeln!(reader.get_mut(), "{query}").unwrap();
+    let mut reply = String::new();
+    reader.read_line(&mut reply).unwrap();
+    reply.trim_end().to_string()
+}
+
+#[test]
+fn insert_then_select_over_tcp() {
+    let addr = start_server();
+    let mut client = BufReader::new(TcpStream::connect(addr).unwrap());
+
+    assert_eq!(send(&mut client, "INSERT name rbdb"), "SUCCESS: Inserted name:rbdb into database");
+    assert_eq!(send(&mut client, "SELECT name"), "rbdb");
+    assert_eq!(send(&mut client, "SELECT missing"), "No entry found for key = missing");
+    assert_eq!(send(&mut client, "BOGUS"), "Query is malformed: Invalid query type: BOGUS");
+}
+
+#[test]
+fn connections_share_the_store() {
+    let addr = start_server();
+
+    // The first client hangs up without saying goodbye, which must not take the server down
+    {
+        let mut first = BufReader::new(TcpStream::connect(addr).unwrap());
+        assert_eq!(send(&mut first, "INSERT shared yes"), "SUCCESS: Inserted shared:yes into database");
+    }
+
+    let mut second = BufReader::new(TcpStream::connect(addr).unwrap());
+    assert_eq!(send(&mut second, "SELECT shared"), "yes");
+}
+
+#[test]
+fn ping_over_tcp() {
+    let addr = start_server();
+    let mut client = BufReader::new(TcpStream::connect(addr).unwrap());
+
+    assert_eq!(send(&mut client, "PING"), "PONG");
+    assert_eq!(send(&mut client, "PING hello"), "hello");
+}
+
+#[test]
+fn subscribers_hear_about_changes() {
+    let addr = start_server();
+    let mut listener = BufReader::new(TcpStream::connect(addr).unwrap());
+    let mut writer = BufReader::new(TcpStream::connect(addr).unwrap());
+
+    assert_eq!(send(&mut listener, "SUBSCRIBE user:*"), "SUCCESS: Subscribed to user:*");
+    send(&mut writer, "INSERT other 1");
+    send(&mut writer, "INSERT user:1 alice");
+    send(&mut writer, "DELETE user:1");
+
+    let mut event = String::new();
+    listener.read_line(&mut event).unwrap();
+    assert_eq!(event.trim_end(), "EVENT inserted default user:1");
+    event.clear();
+    listener.read_line(&mut event).unwrap();
+    assert_eq!(event.trim_end(), "EVENT deleted default user:1");
+}
+
+#[test]
+fn over_long_lines_drop_the_connection() {
+    let addr = start_server_with(Config { max_line_bytes: Some(64), ..Config::default() });
+    let mut client = BufReader::new(TcpStream::connect(addr).unwrap());
+    assert_eq!(send(&mut client, "PING"), "PONG");
+    let long = format!("INSERT big {}", "x".repeat(100_000));
+    assert_eq!(send(&mut client, &long), "Query is malformed: Line is longer than the limit of 64 bytes");
+
+    // Nothing more comes back, since the server has hung up
+    let mut rest = String::new();
+    assert_eq!(client.read_line(&mut rest).unwrap(), 0);
+}
+
+#[test]
+fn files_are_out_of_reach_without_serve_files() {
+    let path = std::env::temp_dir().join("rbdb_files_are_out_of_reach.db");
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+    let refused = "Query processing failed: Commands that read or write files need --serve-files over a --serve connection";
+
+    let mut client = BufReader::new(TcpStream::connect(start_server()).unwrap());
+    for command in ["SAVE", "LOAD", "EXPORT", "IMPORT", "INGEST"] {
+        assert_eq!(send(&mut client, &format!("{command} {path}")), refused);
+    }
+    assert!(!std::path::Path::new(path).exists());
+
+    let config = Config { serve_files: true, ..Config::default() };
+    let mut client = BufReader::new(TcpStream::connect(start_server_with(config)).unwrap());
+    assert_eq!(send(&mut client, &format!("SAVE {path}")), format!("SUCCESS: Saved 0 entries to {path}"));
+    std::fs::remove_file(path).unwrap();
+}